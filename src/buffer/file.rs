@@ -0,0 +1,76 @@
+// How a file was stored when it was read, as fileformat and fileencoding values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnDisk {
+    pub format: &'static str,
+    pub encoding: &'static str,
+    pub final_newline: bool,
+}
+
+// The text of a file and how it was stored. UTF-8 with or without a BOM and
+// UTF-16 with a BOM are recognised; anything else is read as latin1.
+pub fn decode(bytes: &[u8]) -> (String, OnDisk) {
+    let (text, encoding) = if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        (String::from_utf8_lossy(rest).into_owned(), "utf-8-bom")
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        (decode_utf16(rest, u16::from_be_bytes), "utf-16be")
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        (decode_utf16(rest, u16::from_le_bytes), "utf-16le")
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), "utf-8"),
+            Err(_) => (bytes.iter().map(|&b| b as char).collect(), "latin1"),
+        }
+    };
+
+    let format = if text.contains("\r\n") {
+        "dos"
+    } else if text.contains('\r') && !text.contains('\n') {
+        "mac"
+    } else {
+        "unix"
+    };
+    let final_newline = text.is_empty() || text.ends_with(['\n', '\r']);
+    (text, OnDisk { format, encoding, final_newline })
+}
+
+// Lines of decoded text. In a dos file a line ending in a bare \n is still a line.
+pub fn split_lines(text: &str, format: &str) -> Vec<String> {
+    let separator = if format == "mac" { '\r' } else { '\n' };
+    let text = text.strip_suffix(separator).unwrap_or(text);
+    if text.is_empty() {
+        return vec![String::new()];
+    }
+    text.split(separator)
+        .map(|line| match format {
+            "dos" => line.strip_suffix('\r').unwrap_or(line).to_string(),
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
+pub fn line_ending(format: &str) -> &'static str {
+    match format {
+        "dos" => "\r\n",
+        "mac" => "\r",
+        _ => "\n",
+    }
+}
+
+// Bytes to write for `text` in a fileencoding
+pub fn encode(text: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    match encoding {
+        "utf-8-bom" => Ok([b"\xEF\xBB\xBF".as_slice(), text.as_bytes()].concat()),
+        "utf-16be" => Ok([0xFE, 0xFF].into_iter().chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()),
+        "utf-16le" => Ok([0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()),
+        "latin1" => text
+            .chars()
+            .map(|c| u8::try_from(c).map_err(|_| format!("Cannot write {:?} as latin1", c)))
+            .collect(),
+        _ => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]])).collect();
+    String::from_utf16_lossy(&units)
+}
@@ -82,11 +82,14 @@ impl GapBuffer {
         }
     }
 
-    pub fn to_string(&self) -> String {
+}
+
+impl std::fmt::Display for GapBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut result = String::with_capacity(self.len());
         result.extend(&self.buffer[..self.gap_start]);
         result.extend(&self.buffer[self.gap_end..]);
-        result
+        f.write_str(&result)
     }
 }
 
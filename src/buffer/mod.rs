@@ -1,24 +1,47 @@
+mod file;
 mod gap_buffer;
 mod line;
 
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::path::PathBuf;
+use crate::indent::IndentSettings;
+use crate::input::Abbreviations;
+use crate::options::{OptionValue, Options};
 use crate::syntax::Highlighter;
 use syntect::highlighting::Style;
 
+pub use file::OnDisk;
 pub use gap_buffer::GapBuffer;
 pub use line::Line;
 
+// What a buffer holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferKind {
+    File,        // Text read from or to be written to a file
+    Help,        // The :help text, not a file
+    CommandLine, // The history listed by q: or q/, whose lines are run with Enter
+}
+
 #[derive(Clone)]
 pub struct Buffer {
     lines: Vec<String>,
     file_path: Option<PathBuf>,
+    kind: BufferKind,
     modified: bool,
     undo_stack: Vec<BufferState>,
     redo_stack: Vec<BufferState>,
     highlighter: Highlighter,
     syntax_name: Option<String>,
+    undo_group_depth: usize,
+    undo_group_saved: bool,
+    changedtick: u64,
+    abbreviations: Abbreviations, // Buffer-local, from :iabbrev <buffer>
+    options: Options, // Buffer-local options
+    on_disk: Option<OnDisk>, // None until the buffer is read from a file
+    marks: HashMap<char, (usize, usize)>, // a-z from m, < and > from visual mode
+    tracked_lines: Vec<usize>, // Lines :g has still to visit, last one first
 }
 
 #[derive(Clone)]
@@ -30,15 +53,7 @@ pub struct BufferState {
 
 impl Buffer {
     pub fn new() -> Self {
-        Self {
-            lines: vec![String::new()],
-            file_path: None,
-            modified: false,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            highlighter: Highlighter::new(),
-            syntax_name: None,
-        }
+        Self::with_lines(vec![String::new()], None, Highlighter::new(), None)
     }
 
     pub fn from_file(path: &str) -> io::Result<Self> {
@@ -47,44 +62,72 @@ impl Buffer {
         let syntax_name = highlighter.detect_syntax(&file_path);
         
         // Try to read the file, but if it doesn't exist, create a new buffer with the path
-        let content = match fs::read_to_string(path) {
-            Ok(content) => content,
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
             Err(e) if e.kind() == io::ErrorKind::NotFound => {
                 // File doesn't exist, create new buffer with the path
-                return Ok(Self {
-                    lines: vec![String::new()],
-                    file_path: Some(file_path),
-                    modified: false,
-                    undo_stack: Vec::new(),
-                    redo_stack: Vec::new(),
-                    highlighter,
-                    syntax_name,
-                });
+                return Ok(Self::with_lines(vec![String::new()], Some(file_path), highlighter, syntax_name));
             }
             Err(e) => return Err(e), // Other errors (permission, etc.)
         };
 
-        let lines: Vec<String> = if content.is_empty() {
-            vec![String::new()]
-        } else {
-            content.lines().map(|s| s.to_string()).collect()
-        };
+        let (content, on_disk) = file::decode(&bytes);
+        let lines = file::split_lines(&content, on_disk.format);
+
+        let mut buffer = Self::with_lines(lines, Some(file_path), highlighter, syntax_name);
+        buffer.on_disk = Some(on_disk);
+        Ok(buffer)
+    }
+
+    // A buffer that is not a file, such as the help text
+    pub fn scratch(kind: BufferKind, lines: Vec<String>) -> Self {
+        let mut buffer = Self::with_lines(lines, None, Highlighter::new(), None);
+        buffer.kind = kind;
+        buffer
+    }
 
-        Ok(Self {
+    fn with_lines(
+        lines: Vec<String>,
+        file_path: Option<PathBuf>,
+        highlighter: Highlighter,
+        syntax_name: Option<String>,
+    ) -> Self {
+        Self {
             lines,
-            file_path: Some(file_path),
+            file_path,
+            kind: BufferKind::File,
             modified: false,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
             highlighter,
             syntax_name,
-        })
+            undo_group_depth: 0,
+            undo_group_saved: false,
+            changedtick: 0,
+            abbreviations: Abbreviations::new(),
+            options: Options::new(),
+            on_disk: None,
+            marks: HashMap::new(),
+            tracked_lines: Vec::new(),
+        }
     }
 
+    // Written with the buffer's fileformat and fileencoding. The last line gets
+    // a line ending if the file had one when read (or is new), or with insertfinalnewline.
     pub fn save(&mut self) -> io::Result<()> {
-        if let Some(path) = &self.file_path {
-            let content = self.lines.join("\n");
-            fs::write(path, content)?;
+        if let Some(path) = self.file_path.clone() {
+            if self.options.bool("trimtrailingwhitespace") {
+                self.trim_trailing_whitespace();
+            }
+            let ending = file::line_ending(&self.options.string("fileformat"));
+            let mut content = self.lines.join(ending);
+            let final_newline = self.options.bool("insertfinalnewline") || self.on_disk.is_none_or(|d| d.final_newline);
+            if final_newline && !content.is_empty() {
+                content.push_str(ending);
+            }
+            let bytes = file::encode(&content, &self.options.string("fileencoding"))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            fs::write(path, bytes)?;
             self.modified = false;
             Ok(())
         } else {
@@ -95,6 +138,24 @@ impl Buffer {
         }
     }
 
+    // Strip spaces and tabs from line ends, as one undo step
+    fn trim_trailing_whitespace(&mut self) {
+        self.begin_undo_group();
+        for line in 0..self.lines.len() {
+            let trimmed = self.lines[line].trim_end_matches([' ', '\t']);
+            if trimmed.len() != self.lines[line].len() {
+                let trimmed = trimmed.to_string();
+                self.set_line(line, trimmed);
+            }
+        }
+        self.end_undo_group();
+    }
+
+    // How the file was stored when it was read
+    pub fn on_disk(&self) -> Option<OnDisk> {
+        self.on_disk
+    }
+
     pub fn save_as(&mut self, path: &str) -> io::Result<()> {
         self.file_path = Some(PathBuf::from(path));
         self.save()
@@ -104,6 +165,16 @@ impl Buffer {
         self.lines.get(idx)
     }
 
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    // Trade the text for `lines` without it counting as a change, to draw a
+    // preview of a command in its place and then swap the text back
+    pub fn swap_lines(&mut self, lines: &mut Vec<String>) {
+        std::mem::swap(&mut self.lines, lines);
+    }
+
     pub fn line_count(&self) -> usize {
         self.lines.len()
     }
@@ -130,13 +201,50 @@ impl Buffer {
             let rest = self.lines[line].split_off(col);
             self.lines.insert(line + 1, rest);
             self.modified = true;
+            for mark in self.marks.values_mut() {
+                if mark.0 > line {
+                    mark.0 += 1;
+                } else if mark.0 == line && mark.1 >= col {
+                    *mark = (line + 1, mark.1 - col);
+                }
+            }
+            self.shift_tracked_lines(line + 1, 1);
         }
     }
 
+    pub fn set_line(&mut self, line: usize, content: String) {
+        if line < self.lines.len() {
+            self.save_state(line, 0);
+            self.lines[line] = content;
+            self.modified = true;
+        }
+    }
+
+    pub fn insert_line(&mut self, line: usize, content: String) {
+        let line = line.min(self.lines.len());
+        self.save_state(line, 0);
+        self.lines.insert(line, content);
+        self.modified = true;
+        for mark in self.marks.values_mut() {
+            if mark.0 >= line {
+                mark.0 += 1;
+            }
+        }
+        self.shift_tracked_lines(line, 1);
+    }
+
     pub fn delete_line(&mut self, line: usize) -> Option<String> {
         if line < self.lines.len() && self.lines.len() > 1 {
             self.save_state(line, 0);
             self.modified = true;
+            self.marks.retain(|_, mark| mark.0 != line);
+            for mark in self.marks.values_mut() {
+                if mark.0 > line {
+                    mark.0 -= 1;
+                }
+            }
+            self.tracked_lines.retain(|&tracked| tracked != line);
+            self.shift_tracked_lines(line + 1, -1);
             Some(self.lines.remove(line))
         } else if self.lines.len() == 1 {
             self.save_state(line, 0);
@@ -156,9 +264,105 @@ impl Buffer {
             if !self.lines[line].is_empty() && !next_line.is_empty() {
                 self.lines[line].push(' ');
             }
-            self.lines[line].push_str(&next_line.trim_start());
+            let joined_at = self.lines[line].len();
+            let trimmed = next_line.len() - next_line.trim_start().len();
+            self.lines[line].push_str(next_line.trim_start());
             self.modified = true;
+            for mark in self.marks.values_mut() {
+                if mark.0 == line + 1 {
+                    *mark = (line, joined_at + mark.1.saturating_sub(trimmed));
+                } else if mark.0 > line + 1 {
+                    mark.0 -= 1;
+                }
+            }
+            self.tracked_lines.retain(|&tracked| tracked != line + 1);
+            self.shift_tracked_lines(line + 2, -1);
+        }
+    }
+
+    // Move lines `first` to `last` to start at line `at` of the text without
+    // them, taking their marks and tracked lines along
+    pub fn move_lines(&mut self, first: usize, last: usize, at: usize) {
+        if last >= self.lines.len() {
+            return;
+        }
+        self.save_state(first, 0);
+        let moved: Vec<String> = self.lines.drain(first..=last).collect();
+        let count = moved.len();
+        let at = at.min(self.lines.len());
+        self.lines.splice(at..at, moved);
+        self.modified = true;
+        let new_line = |line: usize| {
+            if (first..=last).contains(&line) {
+                return at + line - first;
+            }
+            let without = if line > last { line - count } else { line };
+            if without >= at { without + count } else { without }
+        };
+        for mark in self.marks.values_mut() {
+            mark.0 = new_line(mark.0);
+        }
+        for line in self.tracked_lines.iter_mut() {
+            *line = new_line(*line);
         }
+        self.tracked_lines.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    // Marks follow their line when lines above are added or removed, and go
+    // away with it when it is deleted
+    pub fn set_mark(&mut self, name: char, line: usize, col: usize) {
+        self.marks.insert(name, (line, col));
+    }
+
+    pub fn mark(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(&name).copied()
+    }
+
+    // Lines to visit one at a time while commands change the buffer, as :g
+    // does with the lines that match. Like marks they follow their line and
+    // are dropped when it is deleted.
+    pub fn track_lines(&mut self, mut lines: Vec<usize>) {
+        lines.sort_unstable_by(|a, b| b.cmp(a));
+        self.tracked_lines = lines;
+    }
+
+    // The first tracked line not visited yet
+    pub fn next_tracked_line(&mut self) -> Option<usize> {
+        self.tracked_lines.pop()
+    }
+
+    pub fn clear_tracked_lines(&mut self) {
+        self.tracked_lines.clear();
+    }
+
+    // Move tracked lines from `from` on by `by` lines
+    fn shift_tracked_lines(&mut self, from: usize, by: isize) {
+        for line in self.tracked_lines.iter_mut().filter(|line| **line >= from) {
+            *line = line.saturating_add_signed(by);
+        }
+    }
+
+    // Byte range of the keyword under or after the cursor on a line
+    pub fn word_at(&self, line: usize, col: usize) -> Option<(usize, usize)> {
+        let text = self.lines.get(line)?;
+        let is_keyword = |c: char| c.is_alphanumeric() || c == '_';
+
+        let start = text
+            .char_indices()
+            .find(|&(i, c)| is_keyword(c) && i + c.len_utf8() > col)
+            .map(|(i, _)| i)?;
+        // Walk back to the start of the word when the cursor is inside it
+        let start = text[..start]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_keyword(c))
+            .last()
+            .map_or(start, |(i, _)| i);
+        let end = text[start..]
+            .char_indices()
+            .find(|&(_, c)| !is_keyword(c))
+            .map_or(text.len(), |(i, _)| start + i);
+        Some((start, end))
     }
 
     pub fn is_modified(&self) -> bool {
@@ -169,12 +373,81 @@ impl Buffer {
         self.file_path.as_ref()
     }
 
+    pub fn kind(&self) -> BufferKind {
+        self.kind
+    }
+
+    // As the status line and :buffer show it
+    pub fn name(&self) -> String {
+        match (self.kind, &self.file_path) {
+            (BufferKind::File, Some(path)) => path.display().to_string(),
+            (BufferKind::File, None) => "[No Name]".to_string(),
+            (BufferKind::Help, _) => "[Help]".to_string(),
+            (BufferKind::CommandLine, _) => "[Command Line]".to_string(),
+        }
+    }
+
     pub fn get_line_mut(&mut self, idx: usize) -> Option<&mut String> {
         self.lines.get_mut(idx)
     }
 
+    // Everything changed until the matching end_undo_group is undone as one step
+    pub fn begin_undo_group(&mut self) {
+        self.undo_group_depth += 1;
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.undo_group_depth = self.undo_group_depth.saturating_sub(1);
+        if self.undo_group_depth == 0 {
+            self.undo_group_saved = false;
+        }
+    }
+
+    // Bumped on every change, so callers can tell whether a command edited the buffer
+    pub fn changedtick(&self) -> u64 {
+        self.changedtick
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    // shiftwidth, tabstop and expandtab for this buffer
+    pub fn indent_settings(&self) -> IndentSettings {
+        IndentSettings::from_options(&self.options)
+    }
+
+    pub fn set_indent_settings(&mut self, settings: IndentSettings) {
+        self.options.set("expandtab", OptionValue::Bool(settings.expand_tabs));
+        self.options.set("shiftwidth", OptionValue::Number(settings.shift_width));
+        self.options.set("softtabstop", OptionValue::Number(settings.soft_tab_stop));
+        self.options.set("tabstop", OptionValue::Number(settings.tab_stop));
+    }
+
+    pub fn abbreviations(&self) -> &Abbreviations {
+        &self.abbreviations
+    }
+
+    pub fn abbreviations_mut(&mut self) -> &mut Abbreviations {
+        &mut self.abbreviations
+    }
+
     fn save_state(&mut self, cursor_line: usize, cursor_col: usize) {
         const MAX_UNDO_STACK: usize = 100;
+
+        self.changedtick += 1;
+
+        // Only the first change inside a group records a snapshot
+        if self.undo_group_depth > 0 {
+            if self.undo_group_saved {
+                return;
+            }
+            self.undo_group_saved = true;
+        }
         
         let state = BufferState {
             lines: self.lines.clone(),
@@ -203,6 +476,7 @@ impl Buffer {
             
             // Restore previous state
             self.lines = state.lines;
+            self.tracked_lines.clear();
             self.modified = true;
             Some((state.cursor_line, state.cursor_col))
         } else {
@@ -222,6 +496,7 @@ impl Buffer {
             
             // Restore redo state
             self.lines = state.lines;
+            self.tracked_lines.clear();
             self.modified = true;
             Some((state.cursor_line, state.cursor_col))
         } else {
@@ -249,6 +524,19 @@ impl Buffer {
         }
     }
     
+    pub fn set_color_scheme(&mut self, name: &str) -> Result<(), String> {
+        self.highlighter.set_theme(name)
+    }
+
+    // Name of the syntax a filetype name or extension refers to
+    pub fn find_syntax(&self, token: &str) -> Option<String> {
+        self.highlighter.find_syntax(token)
+    }
+
+    pub fn set_syntax(&mut self, syntax_name: Option<String>) {
+        self.syntax_name = syntax_name;
+    }
+
     pub fn syntax_name(&self) -> Option<&str> {
         self.syntax_name.as_deref()
     }
@@ -0,0 +1,270 @@
+use crate::buffer::Buffer;
+use crate::search::{self, split_pattern, SearchState};
+
+// Where an address starts before its +N/-N offsets
+#[derive(Debug, Clone, PartialEq)]
+enum Base {
+    Current,      // `.`, or nothing before an offset
+    Last,         // `$`
+    Line(usize),  // A line number, 0 for before the first line
+    Mark(char),   // `'x`
+    Search { pattern: String, forward: bool }, // `/pat/` or `?pat?`
+}
+
+// One line address of an Ex command, looked up in the buffer by `resolve`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    base: Base,
+    offset: isize,
+}
+
+impl Address {
+    fn current() -> Self {
+        Self { base: Base::Current, offset: 0 }
+    }
+
+    // The line number it stands for, 1 for the first line and 0 for before it.
+    // `current` is the line it is relative to. Past the end is an error, or
+    // with `clamp` the last line.
+    pub fn resolve(&self, buffer: &Buffer, current: usize, search: &SearchState, clamp: bool) -> Result<usize, String> {
+        let line = match &self.base {
+            Base::Current => current,
+            Base::Last => buffer.line_count(),
+            Base::Line(line) => *line,
+            Base::Mark(name) => buffer.mark(*name).ok_or_else(|| format!("Mark not set: {}", name))?.0 + 1,
+            Base::Search { pattern, forward } => {
+                // An empty pattern reuses the last search
+                let pattern = if pattern.is_empty() { &search.query } else { pattern };
+                if pattern.is_empty() {
+                    return Err("No previous regular expression".to_string());
+                }
+                search::find_line(buffer, &search.regex(pattern)?, current.saturating_sub(1), *forward)
+                    .ok_or_else(|| format!("Pattern not found: {}", pattern))?
+                    + 1
+            }
+        };
+        line.checked_add_signed(self.offset)
+            .map(|line| if clamp { line.min(buffer.line_count()) } else { line })
+            .filter(|&line| line <= buffer.line_count())
+            .ok_or_else(|| "Invalid range".to_string())
+    }
+}
+
+// The addresses before a command name, with whether each one followed a `;`
+#[derive(Debug, Clone, Default)]
+pub struct Range {
+    addresses: Vec<(Address, bool)>,
+}
+
+impl Range {
+    // How many addresses were given, at most the two that are used
+    pub fn len(&self) -> usize {
+        self.addresses.len().min(2)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    // First and last line numbers, swapped when given backwards. An address
+    // after `;` is relative to the one before it instead of the cursor line.
+    // With `clamp`, as for a range with no command after it, lines past the
+    // end are the last line.
+    pub fn resolve(&self, buffer: &Buffer, cursor_line: usize, search: &SearchState, clamp: bool) -> Result<Option<(usize, usize)>, String> {
+        let mut lines = Vec::with_capacity(self.addresses.len());
+        for (address, after_semicolon) in &self.addresses {
+            let current = match lines.last() {
+                Some(&previous) if *after_semicolon => previous,
+                _ => cursor_line,
+            };
+            lines.push(address.resolve(buffer, current, search, clamp)?);
+        }
+        Ok(match lines.as_slice() {
+            [] => None,
+            [line] => Some((*line, *line)),
+            [.., first, last] => Some((*first.min(last), *first.max(last))),
+        })
+    }
+}
+
+// The range at the start of a command line and the text after it
+pub fn parse_range(text: &str) -> Result<(Range, &str), String> {
+    let mut rest = text.trim_start_matches([' ', ':']);
+    let mut range = Range::default();
+    if let Some(after) = rest.strip_prefix('%') {
+        range.addresses.push((Address { base: Base::Line(1), offset: 0 }, false));
+        range.addresses.push((Address { base: Base::Last, offset: 0 }, false));
+        return Ok((range, after));
+    }
+
+    let mut after_semicolon = false;
+    loop {
+        let (address, after) = parse_address(rest)?;
+        rest = after.trim_start();
+        let separator = rest.chars().next().filter(|c| matches!(c, ',' | ';'));
+        // A missing address next to a separator is the current line
+        match address {
+            Some(address) => range.addresses.push((address, after_semicolon)),
+            None if separator.is_some() || after_semicolon || !range.is_empty() => {
+                range.addresses.push((Address::current(), after_semicolon));
+            }
+            None => {}
+        }
+        match separator {
+            Some(separator) => {
+                after_semicolon = separator == ';';
+                rest = rest[1..].trim_start();
+            }
+            None => return Ok((range, rest)),
+        }
+    }
+}
+
+// One address: a line number, `.`, `$`, a mark or a search, then any number
+// of offsets. An offset alone is relative to the current line.
+pub fn parse_address(text: &str) -> Result<(Option<Address>, &str), String> {
+    let mut chars = text.char_indices();
+    let (base, mut rest) = match chars.next() {
+        Some((_, c)) if c.is_ascii_digit() => {
+            let (number, rest) = split_number(text);
+            (Some(Base::Line(number)), rest)
+        }
+        Some((_, '.')) => (Some(Base::Current), &text[1..]),
+        Some((_, '$')) => (Some(Base::Last), &text[1..]),
+        Some((_, '\'')) => match chars.next() {
+            Some((i, name)) if name.is_ascii_lowercase() || matches!(name, '<' | '>') => {
+                (Some(Base::Mark(name)), &text[i + name.len_utf8()..])
+            }
+            Some((_, name)) => return Err(format!("Invalid mark: {}", name)),
+            None => return Err("Missing mark name".to_string()),
+        },
+        Some((_, delimiter @ ('/' | '?'))) => {
+            let (pattern, rest) = split_pattern(&text[1..], delimiter);
+            (Some(Base::Search { pattern, forward: delimiter == '/' }), rest)
+        }
+        _ => (None, text),
+    };
+
+    let mut offset = 0isize;
+    let mut has_offset = false;
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        let (number, after) = match rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            true => split_number(&rest[1..]),
+            false => (1, &rest[1..]),
+        };
+        let number = isize::try_from(number).unwrap_or(isize::MAX);
+        offset = offset.saturating_add(if sign == '+' { number } else { -number });
+        has_offset = true;
+        rest = after;
+    }
+
+    let base = match (base, has_offset) {
+        (Some(base), _) => base,
+        (None, true) => Base::Current,
+        (None, false) => return Ok((None, text)),
+    };
+    Ok((Some(Address { base, offset }), rest))
+}
+
+fn split_number(text: &str) -> (usize, &str) {
+    let len = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    (text[..len].parse().unwrap_or(usize::MAX), &text[len..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferKind;
+
+    fn buffer() -> Buffer {
+        let lines = ["one", "two", "foo", "four", "foo bar"];
+        Buffer::scratch(BufferKind::File, lines.iter().map(|line| line.to_string()).collect())
+    }
+
+    // The lines a command line's range stands for with the cursor on `cursor`
+    fn lines(buffer: &Buffer, text: &str, cursor: usize) -> Result<Option<(usize, usize)>, String> {
+        let (range, rest) = parse_range(text)?;
+        range.resolve(buffer, cursor, &SearchState::new(), rest.is_empty())
+    }
+
+    #[test]
+    fn numbers() {
+        let buffer = buffer();
+        assert_eq!(lines(&buffer, "", 2), Ok(None));
+        assert_eq!(lines(&buffer, "3", 1), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, "2,4", 1), Ok(Some((2, 4))));
+        assert_eq!(lines(&buffer, "4,2", 1), Ok(Some((2, 4))));
+        assert_eq!(lines(&buffer, "%", 1), Ok(Some((1, 5))));
+        assert_eq!(lines(&buffer, ".,$", 3), Ok(Some((3, 5))));
+        assert_eq!(lines(&buffer, ",", 3), Ok(Some((3, 3))));
+    }
+
+    #[test]
+    fn past_the_end() {
+        let buffer = buffer();
+        assert_eq!(lines(&buffer, "99", 1), Ok(Some((5, 5))));
+        assert_eq!(lines(&buffer, "99d", 1), Err("Invalid range".to_string()));
+        assert_eq!(lines(&buffer, "1,6d", 1), Err("Invalid range".to_string()));
+        assert_eq!(lines(&buffer, "-2", 1), Err("Invalid range".to_string()));
+    }
+
+    #[test]
+    fn offsets() {
+        let buffer = buffer();
+        assert_eq!(lines(&buffer, ".+2", 1), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, "+", 2), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, "-", 2), Ok(Some((1, 1))));
+        assert_eq!(lines(&buffer, "3-", 1), Ok(Some((2, 2))));
+        assert_eq!(lines(&buffer, "$-1", 1), Ok(Some((4, 4))));
+        assert_eq!(lines(&buffer, "+1+1-3+2", 2), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, ".,+2", 2), Ok(Some((2, 4))));
+    }
+
+    #[test]
+    fn semicolon() {
+        let buffer = buffer();
+        // After `;` an address counts from the one before it, after `,` from the cursor
+        assert_eq!(lines(&buffer, "2;+1", 1), Ok(Some((2, 3))));
+        assert_eq!(lines(&buffer, "2,+1", 1), Ok(Some((2, 2))));
+        assert_eq!(lines(&buffer, "/foo/;/foo/", 1), Ok(Some((3, 5))));
+        assert_eq!(lines(&buffer, "/foo/,/foo/", 1), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, "3;", 1), Ok(Some((3, 3))));
+    }
+
+    #[test]
+    fn searches() {
+        let buffer = buffer();
+        assert_eq!(lines(&buffer, "/foo", 3), Ok(Some((5, 5))));
+        assert_eq!(lines(&buffer, "?foo?", 5), Ok(Some((3, 3))));
+        assert_eq!(lines(&buffer, "/foo/+1", 1), Ok(Some((4, 4))));
+        assert_eq!(lines(&buffer, "/nothing/", 1), Err("Pattern not found: nothing".to_string()));
+        assert_eq!(lines(&buffer, "//", 1), Err("No previous regular expression".to_string()));
+
+        let mut search = SearchState::new();
+        search.set_pattern("two", true, Default::default());
+        let (range, _) = parse_range("//").unwrap();
+        assert_eq!(range.resolve(&buffer, 4, &search, true), Ok(Some((2, 2))));
+    }
+
+    #[test]
+    fn marks() {
+        let mut buffer = buffer();
+        buffer.set_mark('a', 3, 0);
+        buffer.set_mark('b', 1, 2);
+        assert_eq!(lines(&buffer, "'a", 1), Ok(Some((4, 4))));
+        assert_eq!(lines(&buffer, "'b,'a", 1), Ok(Some((2, 4))));
+        assert_eq!(lines(&buffer, "'a+1", 1), Ok(Some((5, 5))));
+        assert_eq!(lines(&buffer, "'c", 1), Err("Mark not set: c".to_string()));
+        assert_eq!(lines(&buffer, "'A", 1), Err("Invalid mark: A".to_string()));
+    }
+
+    #[test]
+    fn rest_of_the_line() {
+        let (range, rest) = parse_range(":3,4d x").unwrap();
+        assert_eq!(range.len(), 2);
+        assert_eq!(rest, "d x");
+        let (range, rest) = parse_range("s/a/b/").unwrap();
+        assert!(range.is_empty());
+        assert_eq!(rest, "s/a/b/");
+    }
+}
@@ -0,0 +1,362 @@
+use super::address::parse_address;
+use super::executor::{CommandContext, CommandDef, CommandExecutor, CommandFn, ExCommand};
+use super::substitute;
+use crate::buffer::Buffer;
+use crate::indent;
+use crate::input::{AbbrevCommand, MapCommand, ABBREV_COMMANDS, MAP_COMMANDS};
+use crate::mode::command::CommandAction;
+use crate::register::Register;
+use crate::search::{self, split_pattern};
+
+// How many changed lines it takes for a command to report them, vim's 'report'
+pub const REPORT: usize = 2;
+
+// Name, shortest abbreviation, takes a range, takes a !, | ends it, function
+const COMMANDS: &[(&str, usize, bool, bool, bool, CommandFn)] = &[
+    ("delete", 1, true, false, true, delete),
+    ("yank", 1, true, false, true, yank),
+    ("move", 1, true, false, true, move_lines),
+    ("t", 1, true, false, true, copy),
+    ("copy", 2, true, false, true, copy),
+    (">", 1, true, false, true, shift),
+    ("<", 1, true, false, true, shift),
+    ("join", 1, true, true, true, join),
+    ("print", 1, true, false, true, print),
+    ("substitute", 1, true, false, false, substitute::substitute),
+    ("&", 1, true, false, true, substitute::repeat),
+    ("normal", 4, true, true, false, normal),
+    ("global", 1, true, true, false, global),
+    ("vglobal", 1, true, false, false, global),
+    ("mark", 2, true, false, true, mark),
+    ("nohlsearch", 3, false, false, true, nohlsearch),
+    ("quit", 1, false, true, true, quit),
+    ("write", 1, false, true, true, write),
+    ("wq", 2, false, true, true, write_quit),
+    ("xit", 1, false, true, true, write_quit),
+    ("edit", 1, false, false, true, edit),
+    ("split", 2, false, false, true, split),
+    ("vsplit", 2, false, false, true, split),
+    ("close", 3, false, false, true, close),
+    ("only", 2, false, false, true, only),
+    ("buffer", 1, false, false, true, buffer),
+    ("help", 1, false, false, true, help),
+    ("set", 2, false, false, true, set),
+    ("setlocal", 4, false, false, true, set),
+    ("retab", 3, false, true, true, retab),
+];
+
+pub(super) fn register(executor: &mut CommandExecutor) {
+    for &(name, abbrev, range, bang, bar, run) in COMMANDS {
+        executor.register_command(CommandDef { name, abbrev, range, bang, bar, run });
+    }
+    for &(name, abbrev, _, _) in MAP_COMMANDS {
+        executor.register_command(CommandDef { name, abbrev, range: false, bang: true, bar: true, run: map });
+    }
+    for &(name, abbrev, _, _) in ABBREV_COMMANDS {
+        executor.register_command(CommandDef { name, abbrev, range: false, bang: false, bar: true, run: abbreviate });
+    }
+}
+
+// Put the cursor on the first non-blank of a line
+pub(super) fn go_to_line(context: &mut CommandContext, line: usize) {
+    let line = line.min(context.buffer.line_count().saturating_sub(1));
+    context.cursor.line = line;
+    context.cursor.col = context.buffer.get_line(line).map_or(0, |text| indent::indent_len(text));
+    context.cursor.remember_col(context.buffer);
+}
+
+// `[x] [count]`: a register name when the command takes one, then a count
+// of lines counted from the last line of the range
+fn register_and_range(context: &CommandContext, cmd: &ExCommand, takes_register: bool) -> Result<(char, (usize, usize)), String> {
+    let mut args = cmd.args.trim_end();
+    let mut register = '"';
+    if takes_register {
+        if let Some(name) = args.chars().next().filter(|c| !c.is_ascii_digit()) {
+            if !name.is_ascii_alphabetic() && name != '"' {
+                return Err(format!("Invalid register name: {}", name));
+            }
+            register = name;
+            args = args[1..].trim_start();
+        }
+    }
+    let range = match args {
+        "" => cmd.range,
+        _ => match args.parse::<usize>() {
+            Ok(count) if count > 0 => count_range(context.buffer, cmd.range, count),
+            Ok(_) => return Err("Positive count required".to_string()),
+            Err(_) => return Err(format!("Trailing characters: {}", args)),
+        },
+    };
+    Ok((register, range))
+}
+
+// `count` lines from the last line of a range
+pub(super) fn count_range(buffer: &Buffer, (_, last): (usize, usize), count: usize) -> (usize, usize) {
+    (last, last.saturating_add(count - 1).min(buffer.line_count() - 1))
+}
+
+fn lines(context: &CommandContext, (first, last): (usize, usize)) -> Vec<String> {
+    (first..=last).filter_map(|line| context.buffer.get_line(line).cloned()).collect()
+}
+
+// The line after which :m and :t put lines, 0 for above the first
+fn destination(context: &CommandContext, args: &str) -> Result<usize, String> {
+    match parse_address(args.trim())? {
+        (Some(address), "") => address.resolve(context.buffer, context.cursor.line + 1, context.search, false),
+        (Some(_), rest) => Err(format!("Trailing characters: {}", rest)),
+        (None, _) => Err("Invalid address".to_string()),
+    }
+}
+
+fn report(count: usize, what: &str) -> CommandAction {
+    if count > REPORT {
+        CommandAction::Message(format!("{} {}", count, what))
+    } else {
+        CommandAction::None
+    }
+}
+
+fn delete(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let (register, (first, last)) = register_and_range(context, cmd, true)?;
+    let deleted = lines(context, (first, last));
+    for _ in first..=last {
+        context.buffer.delete_line(first);
+    }
+    context.registers.set(register, Register::linewise(deleted));
+    go_to_line(context, first);
+    Ok(report(last - first + 1, "fewer lines"))
+}
+
+fn yank(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let (register, range) = register_and_range(context, cmd, true)?;
+    context.registers.set(register, Register::linewise(lines(context, range)));
+    Ok(report(range.1 - range.0 + 1, "lines yanked"))
+}
+
+fn move_lines(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let (first, last) = cmd.range;
+    let target = destination(context, cmd.args)?;
+    if target > first && target <= last {
+        return Err("Move lines into themselves".to_string());
+    }
+    let count = last - first + 1;
+    if target != first && target != last + 1 {
+        let at = if target > last { target - count } else { target };
+        context.buffer.move_lines(first, last, at);
+        go_to_line(context, at + count - 1);
+    } else {
+        go_to_line(context, last);
+    }
+    Ok(report(count, "lines moved"))
+}
+
+fn copy(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let target = destination(context, cmd.args)?;
+    let copied = lines(context, cmd.range);
+    let count = copied.len();
+    for (i, line) in copied.into_iter().enumerate() {
+        context.buffer.insert_line(target + i, line);
+    }
+    go_to_line(context, target + count - 1);
+    Ok(report(count, "more lines"))
+}
+
+// :> and :<, one shiftwidth per > or <
+fn shift(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let symbol = if cmd.name == ">" { '>' } else { '<' };
+    let rest = cmd.args.trim_start_matches(symbol);
+    let times = 1 + cmd.args.len() - rest.len();
+    let args = ExCommand { args: rest.trim_start(), ..*cmd };
+    let (_, (first, last)) = register_and_range(context, &args, false)?;
+
+    let settings = context.buffer.indent_settings();
+    for line in first..=last {
+        let text = match context.buffer.get_line(line) {
+            Some(text) if !text.trim().is_empty() => text,
+            _ => continue,
+        };
+        let mut width = indent::indent_width(text, settings.tab_stop);
+        for _ in 0..times {
+            width = settings.shift(width, symbol == '>');
+        }
+        indent::set_indent(context.buffer, line, width, &settings);
+    }
+    go_to_line(context, last);
+    let count = last - first + 1;
+    let plural = if times == 1 { "" } else { "s" };
+    Ok(report(count, &format!("lines {}ed {} time{}", symbol, times, plural)))
+}
+
+// :j joins the range, or the line and the next when the range is one line.
+// :j! keeps the lines' whitespace.
+fn join(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let (_, (first, mut last)) = register_and_range(context, cmd, false)?;
+    if first == last {
+        last = (first + 1).min(context.buffer.line_count() - 1);
+    }
+    for _ in first..last {
+        if cmd.bang {
+            let next = context.buffer.get_line(first + 1).cloned().unwrap_or_default();
+            let mut line = context.buffer.get_line(first).cloned().unwrap_or_default();
+            line.push_str(&next);
+            context.buffer.set_line(first, line);
+            context.buffer.delete_line(first + 1);
+        } else {
+            context.buffer.join_lines(first);
+        }
+    }
+    go_to_line(context, first);
+    Ok(CommandAction::None)
+}
+
+fn print(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let (_, range) = register_and_range(context, cmd, false)?;
+    let printed = lines(context, range);
+    go_to_line(context, range.1);
+    Ok(CommandAction::Message(printed.join(" | ")))
+}
+
+fn normal(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    if cmd.args.is_empty() {
+        return Err("Argument required".to_string());
+    }
+    Ok(CommandAction::Normal {
+        lines: (cmd.addresses > 0).then_some(cmd.range),
+        keys: cmd.args.to_string(),
+        remap: !cmd.bang,
+    })
+}
+
+// :g/pattern/cmd runs cmd on each line of the range (the whole file without
+// one) that matches, :g! and :v on each line that doesn't. The editor runs it
+// once the lines are known; cmd is :p when left out.
+fn global(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    if context.in_global {
+        return Err("Cannot do :global recursive".to_string());
+    }
+    let delimiter = substitute::delimiter(cmd.args).ok_or("Regular expression missing from :global")?;
+    let (pattern, command) = split_pattern(&cmd.args[delimiter.len_utf8()..], delimiter);
+    let pattern = match pattern.is_empty() {
+        true => context.search.last_pattern()?,
+        false => pattern,
+    };
+    let regex = context.search.regex(&pattern)?;
+    // So that `:g/foo/s//bar/` replaces foo
+    context.search.use_pattern(&pattern);
+    let matching = search::matching_lines(context.buffer, &regex);
+    let invert = cmd.bang || cmd.name == "vglobal";
+    let (first, last) = match cmd.addresses {
+        0 => (0, context.buffer.line_count() - 1),
+        _ => cmd.range,
+    };
+    let lines: Vec<usize> = (first..=last)
+        .filter(|&line| matching.binary_search(&line).is_ok() != invert)
+        .collect();
+    if lines.is_empty() {
+        return Err(match invert {
+            true => format!("Pattern found in every line: {}", pattern),
+            false => format!("Pattern not found: {}", pattern),
+        });
+    }
+    let command = match command.trim() {
+        "" => "p".to_string(),
+        _ => command.to_string(),
+    };
+    Ok(CommandAction::Global { lines, command })
+}
+
+fn mark(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let mut chars = cmd.args.trim_end().chars();
+    match (chars.next(), chars.next()) {
+        (Some(name), None) if name.is_ascii_lowercase() => {
+            context.buffer.set_mark(name, cmd.range.1, 0);
+            Ok(CommandAction::None)
+        }
+        (None, _) => Err("Argument required".to_string()),
+        _ => Err(format!("Invalid argument: {}", cmd.args)),
+    }
+}
+
+fn nohlsearch(_context: &mut CommandContext, _cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::NoHighlight)
+}
+
+fn quit(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(if cmd.bang { CommandAction::ForceQuit } else { CommandAction::Quit })
+}
+
+fn write(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let path = cmd.args.trim();
+    let saved = if path.is_empty() { context.buffer.save() } else { context.buffer.save_as(path) };
+    saved.map_err(|e| format!("Error: {}", e))?;
+    Ok(CommandAction::None)
+}
+
+fn write_quit(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    write(context, cmd)?;
+    quit(context, cmd)
+}
+
+fn edit(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    match cmd.args.trim() {
+        "" => Err("Argument required".to_string()),
+        path => Ok(CommandAction::Edit(path.to_string())),
+    }
+}
+
+fn split(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let path = cmd.args.split_whitespace().next().map(str::to_string);
+    Ok(if cmd.name == "split" {
+        CommandAction::SplitHorizontal(path)
+    } else {
+        CommandAction::SplitVertical(path)
+    })
+}
+
+fn buffer(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::Buffer(cmd.args.trim().to_string()))
+}
+
+fn help(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::Help(cmd.args.trim().to_string()))
+}
+
+fn close(_context: &mut CommandContext, _cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::CloseWindow)
+}
+
+fn only(_context: &mut CommandContext, _cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::CloseOtherWindows)
+}
+
+fn set(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    Ok(CommandAction::Set { args: cmd.args.trim().to_string(), local: cmd.name == "setlocal" })
+}
+
+// `:ret[ab][!] [N]`: retab every whitespace run with !, and set the tabstop to N
+fn retab(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let tab_stop = match cmd.args.trim() {
+        "" => None,
+        arg => match arg.parse::<usize>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => return Err(format!("Invalid tabstop: {}", arg)),
+        },
+    };
+    let changed = indent::retab(context.buffer, tab_stop, cmd.bang);
+    Ok(CommandAction::Message(format!("{} lines changed", changed)))
+}
+
+fn map(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let bang = if cmd.bang { "!" } else { "" };
+    match MapCommand::parse(&format!("{}{} {}", cmd.name, bang, cmd.args)) {
+        Some(parsed) => parsed.map(CommandAction::Map),
+        None => Err(format!("Not an editor command: {}", cmd.name)),
+    }
+}
+
+fn abbreviate(_context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    match AbbrevCommand::parse(&format!("{} {}", cmd.name, cmd.args)) {
+        Some(parsed) => parsed.map(CommandAction::Abbreviate),
+        None => Err(format!("Not an editor command: {}", cmd.name)),
+    }
+}
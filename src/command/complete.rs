@@ -0,0 +1,123 @@
+// Tab completion on the command line: command names, then an argument that
+// depends on the command
+use super::address::parse_range;
+use super::executor::{split_name, CommandExecutor};
+use crate::completion::dir_entries;
+use crate::help;
+use crate::options::{self, OPTIONS};
+
+// What the editor has to offer besides the commands themselves
+pub struct CompletionSources {
+    pub buffers: Vec<String>,               // Buffer names, for :buffer
+    pub values: Vec<(&'static str, String)>, // The value in effect of each option, for :set name=
+}
+
+impl CommandExecutor {
+    // The matches for the word before the end of `line`, and where that word
+    // starts. None when there is nothing to complete there.
+    pub fn complete(&self, line: &str, sources: &CompletionSources) -> Option<(usize, Vec<String>)> {
+        // Only the last command of a line like `:w | e foo`
+        let commands = self.split(line);
+        let last = commands.last().filter(|last| line.ends_with(last.as_str()))?;
+        let name_start = match parse_range(last) {
+            Ok((_, rest)) => line.len() - rest.trim_start().len(),
+            Err(_) => return None,
+        };
+
+        let (name, rest) = split_name(&line[name_start..]);
+        if rest.is_empty() && name.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Some((name_start, self.names_starting_with(name)));
+        }
+        let command = self.find(name)?;
+        let args = rest.strip_prefix('!').unwrap_or(rest);
+        if !args.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let args_start = line.len() - args.trim_start().len();
+        let word_start = line[args_start..].rfind(char::is_whitespace).map_or(args_start, |i| args_start + i + 1);
+
+        match command.name {
+            "edit" | "split" | "vsplit" | "write" | "wq" | "xit" => Some(complete_path(line, word_start)),
+            "set" | "setlocal" => Some(complete_option(line, word_start, sources)),
+            "buffer" => {
+                let typed = &line[args_start..];
+                Some((args_start, sources.buffers.iter().filter(|name| name.contains(typed)).cloned().collect()))
+            }
+            "help" => {
+                let typed = line[args_start..].to_lowercase();
+                let mut tags: Vec<String> = help::tags()
+                    .iter()
+                    .filter(|(tag, _)| tag.to_lowercase().starts_with(&typed))
+                    .map(|(tag, _)| tag.clone())
+                    .collect();
+                tags.sort_by_key(|tag| tag.len());
+                Some((args_start, tags))
+            }
+            _ => None,
+        }
+    }
+
+    // Full command names with a prefix, sorted
+    fn names_starting_with(&self, prefix: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .commands
+            .iter()
+            .map(|command| command.name)
+            .filter(|name| name.starts_with(prefix) && name.chars().all(|c| c.is_ascii_alphabetic()))
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// Files in the directory typed so far, each as the whole path
+fn complete_path(line: &str, word_start: usize) -> (usize, Vec<String>) {
+    let word = &line[word_start..];
+    let (dir, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+    let paths = dir_entries(dir, prefix)
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| format!("{}{}", dir, name))
+        .collect();
+    (word_start, paths)
+}
+
+// Option names, `no` and `inv` forms of boolean ones, or after `name=` the
+// values it takes
+fn complete_option(line: &str, word_start: usize, sources: &CompletionSources) -> (usize, Vec<String>) {
+    let word = &line[word_start..];
+    if let Some((name, typed)) = word.split_once('=') {
+        let Some(def) = options::find(name).filter(|def| !def.is_bool()) else { return (word_start, Vec::new()) };
+        let current = sources.values.iter().find(|(name, _)| *name == def.name).map(|(_, value)| value.as_str());
+        let mut values: Vec<String> = Vec::new();
+        for value in current.into_iter().chain(def.choices().iter().copied()) {
+            if !value.is_empty() && value.starts_with(typed) && !values.iter().any(|old| old == value) {
+                values.push(value.to_string());
+            }
+        }
+        let value_start = word_start + name.len() + 1;
+        return (value_start, values);
+    }
+
+    let mut names = Vec::new();
+    for def in OPTIONS {
+        if def.name.starts_with(word) {
+            names.push(def.name.to_string());
+        }
+        for negation in ["no", "inv"] {
+            if def.is_bool() && word.strip_prefix(negation).is_some_and(|rest| def.name.starts_with(rest)) {
+                names.push(format!("{}{}", negation, def.name));
+            }
+        }
+    }
+    if "all".starts_with(word) && !word.is_empty() {
+        names.push("all".to_string());
+    }
+    names.sort();
+    (word_start, names)
+}
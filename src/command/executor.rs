@@ -1,35 +1,180 @@
-use std::collections::HashMap;
+use super::address::parse_range;
+use super::builtin;
+use super::substitute::{self, LastSubstitute, Preview};
+use crate::buffer::Buffer;
+use crate::cursor::Cursor;
+use crate::mode::command::CommandAction;
+use crate::register::Registers;
+use crate::search::SearchState;
 
-type CommandFn = fn(&[&str]) -> Result<String, String>;
+// What a command may change itself. Anything else it asks the editor for
+// with the CommandAction it returns.
+pub struct CommandContext<'a> {
+    pub buffer: &'a mut Buffer,
+    pub cursor: &'a mut Cursor,
+    pub registers: &'a mut Registers,
+    pub search: &'a mut SearchState,
+    pub substitute: &'a mut Option<LastSubstitute>, // Repeated by :& and g&
+    pub in_global: bool,                            // Run by :g for one of its lines
+}
+
+// One command of a command line, with its range looked up
+pub struct ExCommand<'a> {
+    pub name: &'static str,
+    pub range: (usize, usize), // First and last line, the cursor line when no address is given
+    pub addresses: usize,      // How many addresses were typed: 0, 1 or 2
+    pub bang: bool,
+    pub args: &'a str,
+}
+
+pub type CommandFn = fn(&mut CommandContext, &ExCommand) -> Result<CommandAction, String>;
+
+pub struct CommandDef {
+    pub name: &'static str,
+    pub abbrev: usize, // Length of the shortest accepted prefix of the name
+    pub range: bool,   // Takes a line range
+    pub bang: bool,    // Takes a !
+    pub bar: bool,     // A | ends it; otherwise the | is part of its argument
+    pub run: CommandFn,
+}
 
 pub struct CommandExecutor {
-    commands: HashMap<String, CommandFn>,
+    pub(super) commands: Vec<CommandDef>, // Tried in order for abbreviated names
 }
 
 impl CommandExecutor {
     pub fn new() -> Self {
         let mut executor = Self {
-            commands: HashMap::new(),
+            commands: Vec::new(),
         };
         executor.register_default_commands();
         executor
     }
 
     fn register_default_commands(&mut self) {
-        // Commands will be registered here
+        builtin::register(self);
+    }
+
+    pub fn register_command(&mut self, command: CommandDef) {
+        self.commands.push(command);
+    }
+
+    // The command a name refers to: an exact name, or else the first one it
+    // is an accepted abbreviation of
+    pub fn find(&self, name: &str) -> Option<&CommandDef> {
+        self.commands.iter().find(|command| command.name == name).or_else(|| {
+            self.commands
+                .iter()
+                .find(|command| name.len() >= command.abbrev && command.name.starts_with(name))
+        })
+    }
+
+    // The commands of a command line, split at each | that isn't escaped as \|
+    // or part of the argument of a command such as :normal
+    pub fn split(&self, line: &str) -> Vec<String> {
+        let mut commands = Vec::new();
+        let mut rest = line;
+        loop {
+            let name_start = match parse_range(rest) {
+                Ok((_, after)) => rest.len() - after.len(),
+                Err(_) => rest.len(),
+            };
+            let (name, _) = split_name(rest[name_start..].trim_start());
+            if self.find(name).is_some_and(|command| !command.bar) {
+                commands.push(rest.to_string());
+                return commands;
+            }
+
+            let mut command = rest[..name_start].to_string();
+            let mut chars = rest[name_start..].char_indices();
+            let mut next = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' if rest[name_start + i + 1..].starts_with('|') => {
+                        command.push('|');
+                        chars.next();
+                    }
+                    '|' => {
+                        next = Some(name_start + i + 1);
+                        break;
+                    }
+                    c => command.push(c),
+                }
+            }
+            commands.push(command);
+            match next {
+                Some(next) => rest = &rest[next..],
+                None => return commands,
+            }
+        }
     }
 
-    pub fn register_command(&mut self, name: &str, func: CommandFn) {
-        self.commands.insert(name.to_string(), func);
+    // Run one command. Errors come back as CommandAction::Error.
+    pub fn execute(&self, line: &str, context: &mut CommandContext) -> CommandAction {
+        match self.run(line, context) {
+            Ok(action) => action,
+            Err(msg) => CommandAction::Error(msg),
+        }
     }
 
-    pub fn execute(&self, name: &str, args: &[&str]) -> Result<String, String> {
-        if let Some(cmd) = self.commands.get(name) {
-            cmd(args)
-        } else {
-            Err(format!("Unknown command: {}", name))
+    fn run(&self, line: &str, context: &mut CommandContext) -> Result<CommandAction, String> {
+        match self.parse(line, context)? {
+            (Some(command), ex_command) => (command.run)(context, &ex_command),
+            // A range alone goes to its last line
+            (None, ex_command) => {
+                if ex_command.addresses > 0 {
+                    builtin::go_to_line(context, ex_command.range.1);
+                }
+                Ok(CommandAction::None)
+            }
         }
     }
+
+    // What a command line being typed would do, drawn before it is run with
+    // inccommand. Only :s has a preview.
+    pub fn preview(&self, line: &str, context: &CommandContext) -> Option<Preview> {
+        let commands = self.split(line);
+        let [command] = commands.as_slice() else { return None };
+        match self.parse(command, context) {
+            Ok((Some(command), ex_command)) if command.name == "substitute" => substitute::preview(context, &ex_command),
+            _ => None,
+        }
+    }
+
+    // The command a line names, None for a range alone, and its range and arguments
+    fn parse<'a>(&self, line: &'a str, context: &CommandContext) -> Result<(Option<&CommandDef>, ExCommand<'a>), String> {
+        let (range, rest) = parse_range(line)?;
+        let cursor_line = context.cursor.line + 1;
+        // Like vim, `:99999` alone goes to the last line
+        let lines = range.resolve(context.buffer, cursor_line, context.search, rest.trim().is_empty())?;
+        let (first, last) = lines.unwrap_or((cursor_line, cursor_line));
+        let mut ex_command = ExCommand {
+            name: "",
+            range: (first.max(1) - 1, last.max(1) - 1),
+            addresses: range.len(),
+            bang: false,
+            args: "",
+        };
+        let rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok((None, ex_command));
+        }
+
+        let (name, rest) = split_name(rest);
+        let command = self.find(name).ok_or_else(|| format!("Not an editor command: {}", line.trim()))?;
+        if lines.is_some() && !command.range {
+            return Err("No range allowed".to_string());
+        }
+        let (bang, args) = match rest.strip_prefix('!') {
+            Some(_) if !command.bang => return Err("No ! allowed".to_string()),
+            Some(args) => (true, args),
+            None => (false, rest),
+        };
+        ex_command.name = command.name;
+        ex_command.bang = bang;
+        ex_command.args = args.trim_start();
+        Ok((Some(command), ex_command))
+    }
 }
 
 impl Default for CommandExecutor {
@@ -38,3 +183,13 @@ impl Default for CommandExecutor {
     }
 }
 
+// A command name and what follows it. Names are letters, or a single symbol
+// such as > or &.
+pub(super) fn split_name(text: &str) -> (&str, &str) {
+    let len = match text.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => text.chars().next().map_or(0, char::len_utf8),
+        Some(len) => len,
+        None => text.len(),
+    };
+    text.split_at(len)
+}
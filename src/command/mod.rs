@@ -1,5 +1,11 @@
+mod address;
+mod builtin;
+mod complete;
 mod executor;
+mod substitute;
 
-pub use crate::mode::command::{CommandMode, CommandResult, execute_command, CommandAction};
-pub use executor::CommandExecutor;
-
+pub use crate::mode::command::{CommandAction, CommandLineContext, CommandMode, CommandResult, Wildmenu};
+pub use builtin::REPORT;
+pub use complete::CompletionSources;
+pub use executor::{CommandContext, CommandDef, CommandExecutor, CommandFn, ExCommand};
+pub use substitute::{LastSubstitute, Preview, SubstituteCount, Substitution};
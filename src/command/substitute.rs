@@ -0,0 +1,519 @@
+use super::builtin::{count_range, go_to_line};
+use super::executor::{CommandContext, ExCommand};
+use crate::buffer::Buffer;
+use crate::mode::command::CommandAction;
+use crate::regex::{Captures, Regex};
+use crate::search::split_pattern;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub global: bool,              // g: every match in the line, not just the first
+    pub confirm: bool,             // c: ask before each one
+    pub count_only: bool,          // n: report the matches without changing them
+    pub no_error: bool,            // e: no error when nothing matches
+    pub ignore_case: Option<bool>, // i or I
+}
+
+impl Flags {
+    // Flags after the pattern and replacement. A leading & keeps the flags of
+    // the last substitution.
+    fn parse(text: &str, previous: Option<Flags>) -> (Flags, &str) {
+        let (mut flags, text) = match text.strip_prefix('&') {
+            Some(rest) => (previous.unwrap_or_default(), rest),
+            None => (Flags::default(), text),
+        };
+        let len = text.find(|c: char| !"gcneiI".contains(c)).unwrap_or(text.len());
+        for flag in text[..len].chars() {
+            match flag {
+                'g' => flags.global = !flags.global,
+                'c' => flags.confirm = true,
+                'n' => flags.count_only = true,
+                'e' => flags.no_error = true,
+                'i' => flags.ignore_case = Some(true),
+                _ => flags.ignore_case = Some(false),
+            }
+        }
+        (flags, &text[len..])
+    }
+}
+
+// The last :s, repeated by :&, :&& and g&
+#[derive(Debug, Clone)]
+pub struct LastSubstitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: Flags,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Case {
+    UpperNext, // \u
+    LowerNext, // \l
+    Upper,     // \U until \E
+    Lower,     // \L until \E
+    Keep,      // \E or \e
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Group(usize), // & or \0 for the whole match, \1 to \9 for a group
+    Case(Case),
+}
+
+// A replacement string: & and \0-\9 insert the match and its groups, \u \l \U
+// \L \E change case, \r breaks the line and \n inserts a NUL, as in vim
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pieces: Vec<Piece>,
+}
+
+impl Replacement {
+    pub fn parse(text: &str) -> Self {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            let piece = match c {
+                '&' => Piece::Group(0),
+                '\\' => match chars.next() {
+                    Some(digit @ '0'..='9') => Piece::Group(digit as usize - '0' as usize),
+                    Some('u') => Piece::Case(Case::UpperNext),
+                    Some('l') => Piece::Case(Case::LowerNext),
+                    Some('U') => Piece::Case(Case::Upper),
+                    Some('L') => Piece::Case(Case::Lower),
+                    Some('E') | Some('e') => Piece::Case(Case::Keep),
+                    Some('r') => {
+                        literal.push('\n');
+                        continue;
+                    }
+                    Some('n') => {
+                        literal.push('\0');
+                        continue;
+                    }
+                    Some('t') => {
+                        literal.push('\t');
+                        continue;
+                    }
+                    Some(c) => {
+                        literal.push(c);
+                        continue;
+                    }
+                    None => {
+                        literal.push('\\');
+                        continue;
+                    }
+                },
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut literal)));
+            }
+            pieces.push(piece);
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Self { pieces }
+    }
+
+    // The text replacing a match in `line`
+    pub fn expand(&self, line: &str, captures: &Captures) -> String {
+        let mut result = String::new();
+        let mut next = None;
+        let mut run = Case::Keep;
+        let mut push = |text: &str, next: &mut Option<Case>, run: Case| {
+            for c in text.chars() {
+                let case = next.take().unwrap_or(run);
+                match case {
+                    Case::UpperNext | Case::Upper => result.extend(c.to_uppercase()),
+                    Case::LowerNext | Case::Lower => result.extend(c.to_lowercase()),
+                    Case::Keep => result.push(c),
+                }
+            }
+        };
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => push(text, &mut next, run),
+                Piece::Group(n) => {
+                    if let Some((start, end)) = captures.get(*n) {
+                        push(&line[start..end], &mut next, run);
+                    }
+                }
+                Piece::Case(case @ (Case::UpperNext | Case::LowerNext)) => next = Some(*case),
+                Piece::Case(case) => run = *case,
+            }
+        }
+        result
+    }
+}
+
+// The lines a substitution works on: a buffer, or a copy of its lines for a preview
+pub trait Text {
+    fn line(&self, n: usize) -> Option<&str>;
+    fn set_line(&mut self, n: usize, text: String);
+    fn insert_line(&mut self, n: usize, text: String);
+}
+
+impl Text for Buffer {
+    fn line(&self, n: usize) -> Option<&str> {
+        self.get_line(n).map(String::as_str)
+    }
+
+    fn set_line(&mut self, n: usize, text: String) {
+        Buffer::set_line(self, n, text);
+    }
+
+    fn insert_line(&mut self, n: usize, text: String) {
+        Buffer::insert_line(self, n, text);
+    }
+}
+
+impl Text for Vec<String> {
+    fn line(&self, n: usize) -> Option<&str> {
+        self.get(n).map(String::as_str)
+    }
+
+    fn set_line(&mut self, n: usize, text: String) {
+        self[n] = text;
+    }
+
+    fn insert_line(&mut self, n: usize, text: String) {
+        self.insert(n, text);
+    }
+}
+
+// One :s over a range of lines, either all at once or a match at a time
+// while confirming
+pub struct Substitution {
+    regex: Regex,
+    replacement: Replacement,
+    pub replacement_text: String,
+    flags: Flags,
+    line: usize,
+    col: usize, // Where the next match is looked for
+    last_line: usize,
+    no_empty_match_at: Option<usize>, // Right after a match, where an empty one isn't taken
+    current: Option<(usize, Captures)>,
+    count: usize,
+    lines: usize,
+    changes: Vec<(usize, usize, usize)>, // Line, start and end of each replacement, or match with n
+}
+
+impl Substitution {
+    fn new(regex: Regex, replacement: Replacement, replacement_text: &str, flags: Flags, (first, last): (usize, usize)) -> Self {
+        Self {
+            regex,
+            replacement,
+            replacement_text: replacement_text.to_string(),
+            flags,
+            line: first,
+            col: 0,
+            last_line: last,
+            no_empty_match_at: None,
+            current: None,
+            count: 0,
+            lines: 0,
+            changes: Vec::new(),
+        }
+    }
+
+    // The next match as its line and byte range, which replace or skip then acts on
+    pub fn next_match<T: Text + ?Sized>(&mut self, text: &T) -> Option<(usize, usize, usize)> {
+        while self.line <= self.last_line {
+            let line = text.line(self.line)?;
+            let mut from = self.col;
+            while from <= line.len() {
+                let Some(captures) = self.regex.captures_at(line, from) else { break };
+                let (start, end) = captures.range();
+                if start == end && Some(start) == self.no_empty_match_at {
+                    from = start + line[start..].chars().next().map_or(1, char::len_utf8);
+                    continue;
+                }
+                self.current = Some((self.line, captures));
+                return Some((self.line, start, end));
+            }
+            self.line += 1;
+            self.col = 0;
+            self.no_empty_match_at = None;
+        }
+        None
+    }
+
+    pub fn replace<T: Text + ?Sized>(&mut self, text: &mut T) {
+        let Some((line, captures)) = self.current.take() else { return };
+        let (start, end) = captures.range();
+        self.count += 1;
+        if self.changes.last().is_none_or(|&(changed, _, _)| changed != line) {
+            self.lines += 1;
+        }
+        if self.flags.count_only {
+            self.changes.push((line, start, end));
+            self.advance(line, end, start == end, text);
+            return;
+        }
+
+        let old = text.line(line).unwrap_or_default().to_string();
+        let new = self.replacement.expand(&old, &captures);
+        let replaced = format!("{}{}{}", &old[..start], new, &old[end..]);
+        // \r in the replacement splits the line
+        let parts: Vec<&str> = replaced.split('\n').collect();
+        text.set_line(line, parts[0].to_string());
+        for (i, part) in parts.iter().enumerate().skip(1) {
+            text.insert_line(line + i, part.to_string());
+        }
+        let added = parts.len() - 1;
+        self.last_line += added;
+        let end_col = match new.rfind('\n') {
+            Some(i) => new.len() - i - 1,
+            None => start + new.len(),
+        };
+        self.changes.push((line + added, if added > 0 { 0 } else { start }, end_col));
+        self.advance(line + added, end_col, start == end, text);
+    }
+
+    pub fn skip<T: Text + ?Sized>(&mut self, text: &T) {
+        let Some((line, captures)) = self.current.take() else { return };
+        let (start, end) = captures.range();
+        self.advance(line, end, start == end, text);
+    }
+
+    // Every match left, without asking
+    pub fn run<T: Text + ?Sized>(&mut self, text: &mut T) {
+        while self.next_match(text).is_some() {
+            self.replace(text);
+        }
+    }
+
+    // Carry on after a match ending at `col`: in the same line with g,
+    // otherwise in the next. An empty match keeps the character after it, and
+    // no empty match is taken right where a match ended.
+    fn advance<T: Text + ?Sized>(&mut self, line: usize, col: usize, empty: bool, text: &T) {
+        if !self.flags.global {
+            self.line = line + 1;
+            self.col = 0;
+            self.no_empty_match_at = None;
+            return;
+        }
+        self.line = line;
+        self.col = col;
+        self.no_empty_match_at = None;
+        if empty {
+            let line = text.line(line).unwrap_or_default();
+            self.col += line[col.min(line.len())..].chars().next().map_or(1, char::len_utf8);
+        } else {
+            self.no_empty_match_at = Some(col);
+        }
+    }
+
+    // The match next_match found, until it is replaced or skipped
+    pub fn current(&self) -> Option<(usize, usize, usize)> {
+        let (line, captures) = self.current.as_ref()?;
+        let (start, end) = captures.range();
+        Some((*line, start, end))
+    }
+
+    pub fn last_changed(&self) -> Option<usize> {
+        self.changes.last().map(|&(line, _, _)| line)
+    }
+
+    // How many matches it replaced (or counted) on how many lines, so far
+    pub fn total(&self) -> SubstituteCount {
+        SubstituteCount { count: self.count, lines: self.lines, count_only: self.flags.count_only }
+    }
+}
+
+// What :s did, added up over the lines of :g
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubstituteCount {
+    pub count: usize,
+    pub lines: usize,
+    pub count_only: bool,
+}
+
+impl SubstituteCount {
+    pub fn add(&mut self, other: SubstituteCount) {
+        self.count += other.count;
+        self.lines += other.lines;
+        self.count_only = other.count_only;
+    }
+
+    // "N substitutions on M lines", or matches with the n flag
+    pub fn report(&self) -> String {
+        let (one, many) = if self.count_only { ("match", "matches") } else { ("substitution", "substitutions") };
+        format!(
+            "{} {} on {} {}",
+            self.count,
+            if self.count == 1 { one } else { many },
+            self.lines,
+            if self.lines == 1 { "line" } else { "lines" }
+        )
+    }
+}
+
+// What a :s being typed would do, drawn in place of the buffer (inccommand)
+pub struct Preview {
+    pub lines: Vec<String>,                 // The text after the substitution
+    pub matches: Vec<(usize, usize, usize)>, // The replaced text, or just the matches until there is a replacement
+}
+
+// :s/pattern/replacement/flags count. Without a pattern, :s repeats the last
+// substitution like :&.
+pub fn substitute(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let Some(delimiter) = delimiter(cmd.args) else {
+        return repeat(context, cmd);
+    };
+    let (last, rest, _) = parse(context, cmd.args, delimiter)?;
+    run(context, cmd, last, rest)
+}
+
+// :& repeats the last substitution with the same pattern and replacement,
+// and :&& with its flags as well
+pub fn repeat(context: &mut CommandContext, cmd: &ExCommand) -> Result<CommandAction, String> {
+    let Some(previous) = context.substitute.clone() else {
+        return Err("No previous substitute regular expression".to_string());
+    };
+    let (flags, rest) = Flags::parse(cmd.args, Some(previous.flags));
+    let last = LastSubstitute { flags, ..previous };
+    run(context, cmd, last, rest)
+}
+
+// The substitution a command line being typed would make, on a copy of the lines
+pub fn preview(context: &CommandContext, cmd: &ExCommand) -> Option<Preview> {
+    let (mut last, rest, has_replacement) = parse(context, cmd.args, delimiter(cmd.args)?).ok()?;
+    if !has_replacement {
+        last.flags.count_only = true;
+        last.flags.global = true;
+    }
+    let range = range(context, cmd, rest).ok()?;
+    let regex = Regex::new(&last.pattern, last.flags.ignore_case.unwrap_or_else(|| context.search.ignores_case(&last.pattern))).ok()?;
+    let mut substitution = Substitution::new(regex, Replacement::parse(&last.replacement), &last.replacement, last.flags, range);
+    let mut lines = context.buffer.lines().to_vec();
+    substitution.run(&mut lines);
+    Some(Preview { lines, matches: substitution.changes })
+}
+
+// Any punctuation but \, ", | and & may separate the parts of :s or :g
+pub(super) fn delimiter(args: &str) -> Option<char> {
+    args.chars()
+        .next()
+        .filter(|&c| !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|' | '&'))
+}
+
+// `/pattern/replacement/flags` after :s: the substitution, what follows its
+// flags and whether the replacement was there at all
+fn parse<'a>(context: &CommandContext, args: &'a str, delimiter: char) -> Result<(LastSubstitute, &'a str, bool), String> {
+    let (pattern, rest) = split_pattern(&args[delimiter.len_utf8()..], delimiter);
+    let has_replacement = ends_pattern(&args[delimiter.len_utf8()..], delimiter);
+    let (replacement, rest) = split_replacement(rest, delimiter);
+    let previous = context.substitute.as_ref();
+    let (flags, rest) = Flags::parse(rest, previous.map(|last| last.flags));
+    let pattern = match pattern.is_empty() {
+        false => pattern,
+        true => context.search.last_pattern()?,
+    };
+    let replacement = expand_tilde(&replacement, previous.map_or("", |last| last.replacement.as_str()));
+    Ok((LastSubstitute { pattern, replacement, flags }, rest, has_replacement))
+}
+
+// Whether a delimiter that isn't escaped ends the pattern
+fn ends_pattern(text: &str, delimiter: char) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if c == delimiter => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// The lines of the command, or `count` lines from its last one
+fn range(context: &CommandContext, cmd: &ExCommand, rest: &str) -> Result<(usize, usize), String> {
+    match rest.trim() {
+        "" => Ok(cmd.range),
+        count => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count_range(context.buffer, cmd.range, count)),
+            _ => Err(format!("Trailing characters: {}", count)),
+        },
+    }
+}
+
+fn run(context: &mut CommandContext, cmd: &ExCommand, last: LastSubstitute, rest: &str) -> Result<CommandAction, String> {
+    let range = range(context, cmd, rest)?;
+    let regex = Regex::new(&last.pattern, last.flags.ignore_case.unwrap_or_else(|| context.search.ignores_case(&last.pattern)))?;
+    let replacement = Replacement::parse(&last.replacement);
+    let mut substitution = Substitution::new(regex, replacement, &last.replacement, last.flags, range);
+    let flags = last.flags;
+    let pattern = last.pattern.clone();
+    context.search.use_pattern(&pattern);
+    *context.substitute = Some(last);
+
+    // :g runs it on one line at a time, where it not matching is no error.
+    // Asking about each match isn't done there, so it is refused rather than
+    // replacing them all.
+    let no_error = flags.no_error || context.in_global;
+    if flags.confirm && !flags.count_only && context.in_global {
+        return Err("Cannot confirm a substitution run by :global".to_string());
+    }
+    if flags.confirm && !flags.count_only {
+        return match substitution.next_match(context.buffer) {
+            Some(_) => Ok(CommandAction::ConfirmSubstitute(Box::new(substitution))),
+            None if no_error => Ok(CommandAction::None),
+            None => Err(format!("Pattern not found: {}", pattern)),
+        };
+    }
+    substitution.run(context.buffer);
+    match substitution.last_changed() {
+        Some(line) => {
+            go_to_line(context, line);
+            Ok(CommandAction::Substituted(substitution.total()))
+        }
+        None if no_error => Ok(CommandAction::None),
+        None => Err(format!("Pattern not found: {}", pattern)),
+    }
+}
+
+// The replacement up to an unescaped `delimiter`, keeping its other escapes
+fn split_replacement(text: &str, delimiter: char) -> (String, &str) {
+    let mut replacement = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (replacement, &text[i + c.len_utf8()..]);
+        }
+        replacement.push(c);
+        if c == '\\' {
+            if let Some((_, next)) = chars.next() {
+                if next == delimiter {
+                    replacement.pop();
+                }
+                replacement.push(next);
+            }
+        }
+    }
+    (replacement, "")
+}
+
+// ~ in a replacement stands for the previous replacement, as it was written
+fn expand_tilde(text: &str, previous: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => result.push_str(previous),
+            '\\' => {
+                result.push(c);
+                result.extend(chars.next());
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
@@ -0,0 +1,282 @@
+use crate::buffer::Buffer;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Keyword,
+    Line,
+    FilePath,
+}
+
+// Insert-mode completion: candidates for the text between `start_col` and the
+// cursor, best first, narrowed as the user keeps typing
+pub struct Completion {
+    kind: CompletionKind,
+    line: usize,
+    start_col: usize,
+    prefix: String,
+    candidates: Vec<String>,
+    matches: Vec<String>,
+    selected: Option<usize>,
+}
+
+// Where a candidate was found; lower sorts first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Proximity {
+    source: usize, // 0 current buffer, 1 other buffers, 2 dictionary
+    distance: usize,
+}
+
+impl Completion {
+    pub fn keywords(
+        buffers: &[Buffer],
+        current: usize,
+        line: usize,
+        col: usize,
+        dictionary: Option<&Path>,
+    ) -> Self {
+        let text = buffers[current].get_line(line).map_or("", |v| v);
+        let start_col = text[..col.min(text.len())]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_keyword(c))
+            .last()
+            .map_or(col, |(i, _)| i);
+
+        let mut ranked = Ranked::default();
+        for (buffer_idx, buffer) in buffers.iter().enumerate() {
+            for line_idx in 0..buffer.line_count() {
+                let text = buffer.get_line(line_idx).map_or("", |v| v);
+                for (word_start, word) in words(text) {
+                    if buffer_idx == current && line_idx == line && word_start == start_col {
+                        // The word being completed
+                        continue;
+                    }
+                    let proximity = if buffer_idx == current {
+                        Proximity { source: 0, distance: line_idx.abs_diff(line) }
+                    } else {
+                        Proximity { source: 1, distance: 0 }
+                    };
+                    ranked.add(word, proximity);
+                }
+            }
+        }
+
+        if let Some(contents) = dictionary.and_then(|path| fs::read_to_string(path).ok()) {
+            for word in contents.split_whitespace() {
+                ranked.add(word, Proximity { source: 2, distance: 0 });
+            }
+        }
+
+        Self::new(CompletionKind::Keyword, line, start_col, text, col, ranked.into_sorted())
+    }
+
+    pub fn lines(buffers: &[Buffer], current: usize, line: usize, col: usize) -> Self {
+        let text = buffers[current].get_line(line).map_or("", |v| v);
+        let indent = text.len() - text.trim_start().len();
+        let start_col = indent.min(col);
+
+        let mut ranked = Ranked::default();
+        for (buffer_idx, buffer) in buffers.iter().enumerate() {
+            for line_idx in 0..buffer.line_count() {
+                if buffer_idx == current && line_idx == line {
+                    continue;
+                }
+                let candidate = buffer.get_line(line_idx).map_or("", |v| v).trim();
+                if candidate.is_empty() {
+                    continue;
+                }
+                let proximity = if buffer_idx == current {
+                    Proximity { source: 0, distance: line_idx.abs_diff(line) }
+                } else {
+                    Proximity { source: 1, distance: 0 }
+                };
+                ranked.add(candidate, proximity);
+            }
+        }
+
+        Self::new(CompletionKind::Line, line, start_col, text, col, ranked.into_sorted())
+    }
+
+    pub fn file_paths(buffer: &Buffer, line: usize, col: usize) -> Self {
+        let text = buffer.get_line(line).map_or("", |v| v);
+        let col = col.min(text.len());
+        let token_start = text[..col]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| !c.is_whitespace() && !"\"'()<>[]{},;=".contains(c))
+            .last()
+            .map_or(col, |(i, _)| i);
+        let token = &text[token_start..col];
+
+        // Complete the last path component inside the directory typed so far
+        let (dir, start_col) = match token.rfind('/') {
+            Some(slash) => (&token[..=slash], token_start + slash + 1),
+            None => ("", token_start),
+        };
+        let candidates = dir_entries(dir, &text[start_col..col]);
+        Self::new(CompletionKind::FilePath, line, start_col, text, col, candidates)
+    }
+
+    fn new(kind: CompletionKind, line: usize, start_col: usize, text: &str, col: usize, candidates: Vec<String>) -> Self {
+        let mut completion = Self {
+            kind,
+            line,
+            start_col,
+            prefix: String::new(),
+            candidates,
+            matches: Vec::new(),
+            selected: None,
+        };
+        completion.narrow(&text[start_col.min(text.len())..col.min(text.len())]);
+        completion
+    }
+
+    // Keep only candidates that extend the typed text
+    pub fn narrow(&mut self, prefix: &str) {
+        self.prefix = prefix.to_string();
+        self.matches = self
+            .candidates
+            .iter()
+            .filter(|c| c.starts_with(prefix) && c.as_str() != prefix)
+            .cloned()
+            .collect();
+        self.selected = None;
+    }
+
+    // Cycle through the matches and back to the typed text, returning what
+    // should now be in the buffer
+    pub fn select_next(&mut self) -> &str {
+        self.selected = match self.selected {
+            None if !self.matches.is_empty() => Some(0),
+            Some(i) if i + 1 < self.matches.len() => Some(i + 1),
+            _ => None,
+        };
+        self.current_text()
+    }
+
+    pub fn select_prev(&mut self) -> &str {
+        self.selected = match self.selected {
+            None => self.matches.len().checked_sub(1),
+            Some(0) => None,
+            Some(i) => Some(i - 1),
+        };
+        self.current_text()
+    }
+
+    pub fn current_text(&self) -> &str {
+        self.selected
+            .and_then(|i| self.matches.get(i))
+            .map_or(self.prefix.as_str(), |s| s.as_str())
+    }
+
+    // Whether typing `c` keeps narrowing the popup rather than closing it
+    pub fn continues_with(&self, c: char) -> bool {
+        match self.kind {
+            CompletionKind::Keyword => is_keyword(c),
+            CompletionKind::Line => true,
+            CompletionKind::FilePath => !c.is_whitespace() && c != '/',
+        }
+    }
+
+    pub fn kind(&self) -> CompletionKind {
+        self.kind
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn start_col(&self) -> usize {
+        self.start_col
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn matches(&self) -> &[String] {
+        &self.matches
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct Ranked {
+    words: HashMap<String, (Proximity, usize)>,
+}
+
+impl Ranked {
+    fn add(&mut self, word: &str, proximity: Proximity) {
+        let entry = self.words.entry(word.to_string()).or_insert((proximity, 0));
+        entry.0 = entry.0.min(proximity);
+        entry.1 += 1;
+    }
+
+    // Closest first, then most frequent
+    fn into_sorted(self) -> Vec<String> {
+        let mut words: Vec<(String, (Proximity, usize))> = self.words.into_iter().collect();
+        words.sort_by(|(a, (pa, ca)), (b, (pb, cb))| pa.cmp(pb).then(cb.cmp(ca)).then(a.cmp(b)));
+        words.into_iter().map(|(word, _)| word).collect()
+    }
+}
+
+fn is_keyword(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Keywords in a line with their byte offsets
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = None;
+    let mut result = Vec::new();
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, is_keyword(c)) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                result.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    result.into_iter()
+}
+
+// Names in the directory `dir` (relative, or from ~/), sorted, with a / after
+// directories. Hidden ones only when `prefix` starts with a dot.
+pub fn dir_entries(dir: &str, prefix: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(entries) = fs::read_dir(expand_home(dir)) {
+        for entry in entries.flatten() {
+            let mut name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                continue;
+            }
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                name.push('/');
+            }
+            names.push(name);
+        }
+    }
+    names.sort();
+    names
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    if dir.is_empty() {
+        return PathBuf::from(".");
+    }
+    match (dir.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(dir),
+    }
+}
@@ -0,0 +1,327 @@
+use crate::options::{self, OptionValue, Settings};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Buffer options from the .editorconfig files that apply to `file`: every one
+// from its directory up to the first with `root = true`, nearer files winning.
+pub fn settings_for(file: &Path) -> Settings {
+    let file = match file.canonicalize() {
+        Ok(file) => file,
+        // A new file: its directory exists even if it doesn't
+        Err(_) => match (file.parent().and_then(|dir| dir.canonicalize().ok()), file.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => file.to_path_buf(),
+        },
+    };
+
+    let mut files = Vec::new();
+    for dir in file.ancestors().skip(1) {
+        if let Ok(contents) = fs::read_to_string(dir.join(".editorconfig")) {
+            let parsed = parse(&contents);
+            let root = parsed.root;
+            files.push((dir.to_path_buf(), parsed));
+            if root {
+                break;
+            }
+        }
+    }
+
+    // Properties from the farthest file first, so nearer ones override them
+    let mut properties = HashMap::new();
+    for (dir, parsed) in files.iter().rev() {
+        let relative = match file.strip_prefix(dir) {
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => continue,
+        };
+        for (glob, section) in &parsed.sections {
+            if matches(glob, &relative) {
+                for (key, value) in section {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    to_settings(&properties)
+}
+
+struct EditorConfigFile {
+    root: bool,
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+// The INI-like format: `[glob]` headers, `key = value` pairs, `#` and `;` comments
+fn parse(contents: &str) -> EditorConfigFile {
+    let mut file = EditorConfigFile { root: false, sections: Vec::new() };
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            file.sections.push((glob.to_string(), Vec::new()));
+        } else if let Some((key, value)) = line.split_once(['=', ':']) {
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_string();
+            match file.sections.last_mut() {
+                Some((_, section)) => section.push((key, value)),
+                None if key == "root" => file.root = value.eq_ignore_ascii_case("true"),
+                None => {}
+            }
+        }
+    }
+    file
+}
+
+// Map the properties avim understands onto options. Values that don't make
+// sense, and `unset`, are skipped.
+fn to_settings(properties: &HashMap<String, String>) -> Settings {
+    let get = |key: &str| {
+        properties
+            .get(key)
+            .map(|value| value.to_lowercase())
+            .filter(|value| value != "unset")
+    };
+    let number = |key: &str| get(key).and_then(|value| value.parse::<usize>().ok());
+
+    let mut values: Vec<(&str, OptionValue)> = Vec::new();
+    match get("indent_style").as_deref() {
+        Some("tab") => values.push(("expandtab", OptionValue::Bool(false))),
+        Some("space") => values.push(("expandtab", OptionValue::Bool(true))),
+        _ => {}
+    }
+    let indent_size = number("indent_size");
+    if let Some(size) = indent_size {
+        values.push(("shiftwidth", OptionValue::Number(size)));
+    } else if get("indent_size").as_deref() == Some("tab") {
+        values.push(("shiftwidth", OptionValue::Number(0)));
+    }
+    // tab_width defaults to indent_size
+    if let Some(width) = number("tab_width").or(indent_size).filter(|&width| width > 0) {
+        values.push(("tabstop", OptionValue::Number(width)));
+    }
+    match get("end_of_line").as_deref() {
+        Some("lf") => values.push(("fileformat", OptionValue::String("unix".to_string()))),
+        Some("crlf") => values.push(("fileformat", OptionValue::String("dos".to_string()))),
+        Some("cr") => values.push(("fileformat", OptionValue::String("mac".to_string()))),
+        _ => {}
+    }
+    if let Some(charset) = get("charset") {
+        values.push(("fileencoding", OptionValue::String(charset)));
+    }
+    for (key, option) in [
+        ("trim_trailing_whitespace", "trimtrailingwhitespace"),
+        ("insert_final_newline", "insertfinalnewline"),
+    ] {
+        match get(key).as_deref() {
+            Some("true") => values.push((option, OptionValue::Bool(true))),
+            Some("false") => values.push((option, OptionValue::Bool(false))),
+            _ => {}
+        }
+    }
+    if get("max_line_length").as_deref() == Some("off") {
+        values.push(("textwidth", OptionValue::Number(0)));
+    } else if let Some(length) = number("max_line_length") {
+        values.push(("textwidth", OptionValue::Number(length)));
+    }
+
+    values
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let def = options::find(name)?;
+            let valid = match &value {
+                OptionValue::String(text) => def.choices().is_empty() || def.choices().contains(&text.as_str()),
+                _ => true,
+            };
+            valid.then_some((def, value))
+        })
+        .collect()
+}
+
+// Whether a section glob matches a path relative to the .editorconfig's
+// directory. A glob without a slash matches the file name in any directory.
+fn matches(glob: &str, relative: &str) -> bool {
+    let (glob, path) = match glob.strip_prefix('/') {
+        Some(glob) => (glob, relative),
+        None if glob.contains('/') => (glob, relative),
+        None => (glob, relative.rsplit('/').next().unwrap_or(relative)),
+    };
+    let glob: Vec<char> = glob.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob_match(&glob, &path)
+}
+
+// `*` and `?` stop at slashes, `**` doesn't; `[abc]`, `[!abc]`, `{a,b}` and
+// `{1..10}` as in the EditorConfig spec; `\` escapes
+fn glob_match(glob: &[char], path: &[char]) -> bool {
+    let (&first, rest) = match glob.split_first() {
+        Some(split) => split,
+        None => return path.is_empty(),
+    };
+    match first {
+        '*' if rest.first() == Some(&'*') => {
+            let rest = &rest[1..];
+            (0..=path.len()).any(|i| glob_match(rest, &path[i..]))
+        }
+        '*' => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| glob_match(rest, &path[i..])),
+        '?' => path.first().is_some_and(|&c| c != '/') && glob_match(rest, &path[1..]),
+        '[' => match rest.iter().position(|&c| c == ']') {
+            Some(end) => {
+                let (negated, class) = match rest[..end].split_first() {
+                    Some(('!', class)) => (true, class),
+                    _ => (false, &rest[..end]),
+                };
+                path.first().is_some_and(|&c| c != '/' && class_contains(class, c) != negated)
+                    && glob_match(&rest[end + 1..], &path[1..])
+            }
+            None => path.first() == Some(&'[') && glob_match(rest, &path[1..]),
+        },
+        '{' => match closing_brace(rest) {
+            Some(end) => {
+                let inner: String = rest[..end].iter().collect();
+                let after = &rest[end + 1..];
+                if let Some((low, high)) = number_range(&inner) {
+                    let digits = path.iter().take_while(|c| c.is_ascii_digit() || **c == '-').count();
+                    (1..=digits).any(|len| {
+                        let number: String = path[..len].iter().collect();
+                        number.parse::<i64>().is_ok_and(|n| (low..=high).contains(&n)) && glob_match(after, &path[len..])
+                    })
+                } else if inner.contains(',') {
+                    split_alternatives(&rest[..end]).into_iter().any(|alternative| {
+                        let glob: Vec<char> = alternative.iter().chain(after).copied().collect();
+                        glob_match(&glob, path)
+                    })
+                } else {
+                    path.first() == Some(&'{') && glob_match(rest, &path[1..])
+                }
+            }
+            None => path.first() == Some(&'{') && glob_match(rest, &path[1..]),
+        },
+        '\\' if !rest.is_empty() => path.first() == Some(&rest[0]) && glob_match(&rest[1..], &path[1..]),
+        c => path.first() == Some(&c) && glob_match(rest, &path[1..]),
+    }
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+// Index of the `}` closing a brace group, allowing nested groups
+fn closing_brace(glob: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &c) in glob.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// The comma separated parts of a brace group, leaving nested groups whole
+fn split_alternatives(inner: &[char]) -> Vec<&[char]> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, &c) in inner.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&inner[start..]);
+    parts
+}
+
+fn number_range(inner: &str) -> Option<(i64, i64)> {
+    let (low, high) = inner.split_once("..")?;
+    Some((low.parse().ok()?, high.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars() {
+        assert!(matches("*", "main.rs"));
+        assert!(matches("*.rs", "src/editor/main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/editor/mod.rs"));
+        assert!(matches("src/**.rs", "src/editor/mod.rs"));
+        assert!(matches("**/mod.rs", "src/editor/mod.rs"));
+        assert!(matches("/Makefile", "Makefile"));
+        assert!(!matches("/Makefile", "sub/Makefile"));
+        assert!(matches("Makefile", "sub/Makefile"));
+    }
+
+    #[test]
+    fn question_marks_and_classes() {
+        assert!(matches("?.c", "a.c"));
+        assert!(!matches("?.c", "ab.c"));
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[!abc].txt", "d.txt"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(matches("\\[x\\]", "[x]"));
+    }
+
+    #[test]
+    fn braces() {
+        assert!(matches("*.{js,ts}", "app.ts"));
+        assert!(!matches("*.{js,ts}", "app.rs"));
+        assert!(matches("{src,lib}/*.rs", "lib/a.rs"));
+        assert!(matches("*.{c,{h,hpp}}", "a.hpp"));
+        assert!(matches("file{1..10}.txt", "file7.txt"));
+        assert!(!matches("file{1..10}.txt", "file11.txt"));
+        assert!(matches("n{-3..3}", "n-2"));
+    }
+
+    #[test]
+    fn sections_and_properties() {
+        let file = parse("root = true\n# comment\n[*]\nindent_style = space\nindent_size = 2\n\n[*.go]\nindent_style = tab\n; more\n");
+        assert!(file.root);
+        assert_eq!(file.sections.len(), 2);
+        assert_eq!(file.sections[0].0, "*");
+        assert_eq!(file.sections[1].1, vec![("indent_style".to_string(), "tab".to_string())]);
+
+        let properties: HashMap<String, String> = [("indent_style", "Tab"), ("indent_size", "4"), ("end_of_line", "crlf"), ("max_line_length", "unset")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let settings: Vec<(&str, OptionValue)> = to_settings(&properties).into_iter().map(|(def, value)| (def.name, value)).collect();
+        assert_eq!(
+            settings,
+            vec![
+                ("expandtab", OptionValue::Bool(false)),
+                ("shiftwidth", OptionValue::Number(4)),
+                ("tabstop", OptionValue::Number(4)),
+                ("fileformat", OptionValue::String("dos".to_string())),
+            ]
+        );
+    }
+}
@@ -1,32 +1,78 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
 use std::fs;
 use std::io;
 use std::path::PathBuf;
 
-#[derive(Debug, Serialize, Deserialize)]
+pub mod editorconfig;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
     pub editor: EditorConfig,
     #[serde(default)]
     pub ui: UiConfig,
+    // Non-recursive mappings for one mode, e.g. [keybindings.normal] with "<leader>w" = ":w<CR>"
+    #[serde(default)]
+    pub keybindings: HashMap<String, HashMap<String, KeyBinding>>,
+    // Insert-mode abbreviations for buffers of one filetype, e.g. [abbreviations.rust]
+    #[serde(default)]
+    pub abbreviations: HashMap<String, HashMap<String, String>>,
+    // Auto-pairs for one filetype as two-character strings, e.g. rust = ["()", "{}"]
     #[serde(default)]
-    pub keybindings: HashMap<String, String>,
+    pub pairs: HashMap<String, Vec<String>>,
+    // Buffer options for one filetype, e.g. [filetype.python] with shiftwidth = 4
+    #[serde(default)]
+    pub filetype: HashMap<String, toml::Table>,
+}
+
+// The keys a mapping produces, optionally with a description for the key hint
+// popup: ":w<CR>" or { keys = ":w<CR>", desc = "Save" }
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyBinding {
+    Keys(String),
+    Described { keys: String, desc: String },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EditorConfig {
     #[serde(default = "default_tab_size")]
     pub tab_size: usize,
+    // Width of one indent level; 0 uses tab_size
     #[serde(default)]
+    pub shift_width: usize,
+    // With expand_tabs, Tab and Backspace move by this many columns; 0 uses shift_width for Tab
+    #[serde(default)]
+    pub soft_tab_stop: usize,
+    #[serde(default = "default_true")]
     pub expand_tabs: bool,
+    // Follow the tabs or spaces a file is already indented with
+    #[serde(default = "default_true")]
+    pub detect_indent: bool,
     #[serde(default = "default_true")]
     pub auto_indent: bool,
+    // Insert the closer when typing an opening bracket or quote
+    #[serde(default = "default_true")]
+    pub auto_pairs: bool,
     #[serde(default = "default_true")]
     pub line_numbers: bool,
+    // Word list used by keyword completion, one or more words per line
+    #[serde(default)]
+    pub dictionary: Option<String>,
+    // What <leader> stands for in mappings, in key notation like "<Space>"
+    #[serde(default = "default_leader")]
+    pub leader: String,
+    // Milliseconds to wait for the rest of a mapping whose first keys were typed
+    #[serde(default = "default_timeout_len")]
+    pub timeout_len: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct UiConfig {
     #[serde(default = "default_color_scheme")]
     pub color_scheme: String,
@@ -42,6 +88,14 @@ fn default_true() -> bool {
     true
 }
 
+fn default_leader() -> String {
+    "\\".to_string()
+}
+
+fn default_timeout_len() -> usize {
+    1000
+}
+
 fn default_color_scheme() -> String {
     "default".to_string()
 }
@@ -50,9 +104,16 @@ impl Default for EditorConfig {
     fn default() -> Self {
         Self {
             tab_size: default_tab_size(),
-            expand_tabs: false,
+            shift_width: 0,
+            soft_tab_stop: 0,
+            expand_tabs: true,
+            detect_indent: true,
             auto_indent: true,
+            auto_pairs: true,
             line_numbers: true,
+            dictionary: None,
+            leader: default_leader(),
+            timeout_len: default_timeout_len(),
         }
     }
 }
@@ -66,21 +127,50 @@ impl Default for UiConfig {
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            editor: EditorConfig::default(),
-            ui: UiConfig::default(),
-            keybindings: HashMap::new(),
+impl Config {
+    // $XDG_CONFIG_HOME/avim, falling back to ~/.config/avim
+    pub fn dir() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("avim"))
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        Self::dir().map(|dir| dir.join("config.toml"))
+    }
+
+    // ftplugin/<filetype>.toml files in the config directory, sorted by name
+    pub fn load_ftplugins() -> Result<Vec<(String, toml::Table)>, String> {
+        let dir = match Self::dir() {
+            Some(dir) => dir.join("ftplugin"),
+            None => return Ok(Vec::new()),
+        };
+        let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect(),
+            Err(_) => return Ok(Vec::new()),
+        };
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+        paths.sort();
+
+        let mut ftplugins = Vec::new();
+        for path in paths {
+            let filetype = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let table = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| parse_error_location(&contents, toml::from_str(&contents)))
+                .map_err(|e| format!("Error in {}: {}", path.display(), e))?;
+            ftplugins.push((filetype, table));
         }
+        Ok(ftplugins)
     }
-}
 
-impl Config {
+    // Parse errors say where in the file they are, on one line
     pub fn load(path: &PathBuf) -> io::Result<Self> {
         let contents = fs::read_to_string(path)?;
-        toml::from_str(&contents)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        parse_error_location(&contents, toml::from_str(&contents))
+            .map_err(|message| io::Error::new(io::ErrorKind::InvalidData, message))
     }
 
     pub fn save(&self, path: &PathBuf) -> io::Result<()> {
@@ -90,3 +180,15 @@ impl Config {
     }
 }
 
+// A TOML parse error as one line saying where in the file it is
+fn parse_error_location<T>(contents: &str, result: Result<T, toml::de::Error>) -> Result<T, String> {
+    result.map_err(|e| match e.span() {
+        Some(span) => {
+            let before = &contents[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            format!("line {}, column {}: {}", line, column, e.message())
+        }
+        None => e.message().to_string(),
+    })
+}
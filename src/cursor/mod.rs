@@ -1,10 +1,15 @@
+mod set;
+
 use crate::buffer::Buffer;
+use crate::indent::{byte_at_display_col, display_col};
+
+pub use set::CursorSet;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Cursor {
     pub line: usize,
     pub col: usize,
-    pub desired_col: usize, // For vertical movement
+    pub desired_col: usize, // The screen column vertical movement keeps to
 }
 
 impl Cursor {
@@ -16,38 +21,52 @@ impl Cursor {
         }
     }
 
-    pub fn move_left(&mut self, _buffer: &Buffer) {
-        if self.col > 0 {
-            self.col -= 1;
-            self.desired_col = self.col;
+    // A cursor on `col`, a byte offset, that keeps to the screen column it is in
+    pub fn at(line: usize, col: usize, buffer: &Buffer) -> Self {
+        let mut cursor = Self { line, col, desired_col: 0 };
+        cursor.remember_col(buffer);
+        cursor
+    }
+
+    // Keep to the screen column the cursor is in now, after moving it sideways
+    pub fn remember_col(&mut self, buffer: &Buffer) {
+        let line = buffer.get_line(self.line).map_or("", |v| v);
+        self.desired_col = display_col(line, self.col, buffer.indent_settings().tab_stop);
+    }
+
+    pub fn move_left(&mut self, buffer: &Buffer) {
+        if let Some(c) = buffer.get_line(self.line).and_then(|line| line[..self.col.min(line.len())].chars().next_back()) {
+            self.col -= c.len_utf8();
+            self.remember_col(buffer);
         }
     }
 
     pub fn move_right(&mut self, buffer: &Buffer) {
-        if let Some(line) = buffer.get_line(self.line) {
-            if self.col < line.len() {
-                self.col += 1;
-                self.desired_col = self.col;
-            }
+        if let Some(c) = buffer.get_line(self.line).and_then(|line| line.get(self.col..)).and_then(|rest| rest.chars().next()) {
+            self.col += c.len_utf8();
+            self.remember_col(buffer);
         }
     }
 
     pub fn move_up(&mut self, buffer: &Buffer) {
         if self.line > 0 {
-            self.line -= 1;
-            self.clamp_col(buffer);
-            self.col = self.desired_col.min(buffer.get_line(self.line).map(|l| l.len()).unwrap_or(0));
+            self.move_to_line_keeping_column(self.line - 1, buffer);
         }
     }
 
     pub fn move_down(&mut self, buffer: &Buffer) {
         if self.line < buffer.line_count() - 1 {
-            self.line += 1;
-            self.clamp_col(buffer);
-            self.col = self.desired_col.min(buffer.get_line(self.line).map(|l| l.len()).unwrap_or(0));
+            self.move_to_line_keeping_column(self.line + 1, buffer);
         }
     }
 
+    // Stay in the same screen column when lines differ in tabs
+    fn move_to_line_keeping_column(&mut self, line: usize, buffer: &Buffer) {
+        let tab_stop = buffer.indent_settings().tab_stop;
+        self.line = line;
+        self.col = byte_at_display_col(buffer.get_line(line).map_or("", |v| v), self.desired_col, tab_stop);
+    }
+
     pub fn move_line_start(&mut self) {
         self.col = 0;
         self.desired_col = 0;
@@ -56,26 +75,26 @@ impl Cursor {
     pub fn move_line_end(&mut self, buffer: &Buffer) {
         if let Some(line) = buffer.get_line(self.line) {
             self.col = line.len();
-            self.desired_col = self.col;
+            self.remember_col(buffer);
         }
     }
 
     pub fn move_word_forward(&mut self, buffer: &Buffer) {
         if let Some(line) = buffer.get_line(self.line) {
-            let chars: Vec<char> = line.chars().collect();
-            let mut pos = self.col;
+            let chars: Vec<(usize, char)> = line.char_indices().collect();
+            let mut pos = chars.partition_point(|&(i, _)| i < self.col);
 
             // Skip current word
-            while pos < chars.len() && !chars[pos].is_whitespace() {
+            while pos < chars.len() && !chars[pos].1.is_whitespace() {
                 pos += 1;
             }
             // Skip whitespace
-            while pos < chars.len() && chars[pos].is_whitespace() {
+            while pos < chars.len() && chars[pos].1.is_whitespace() {
                 pos += 1;
             }
 
-            self.col = pos;
-            self.desired_col = pos;
+            self.col = chars.get(pos).map_or(line.len(), |&(i, _)| i);
+            self.remember_col(buffer);
         }
     }
 
@@ -85,20 +104,20 @@ impl Cursor {
                 return;
             }
 
-            let chars: Vec<char> = line.chars().collect();
-            let mut pos = self.col - 1;
+            let chars: Vec<(usize, char)> = line.char_indices().collect();
+            let mut pos = chars.partition_point(|&(i, _)| i < self.col).saturating_sub(1);
 
             // Skip whitespace
-            while pos > 0 && chars[pos].is_whitespace() {
+            while pos > 0 && chars[pos].1.is_whitespace() {
                 pos -= 1;
             }
             // Skip word
-            while pos > 0 && !chars[pos - 1].is_whitespace() {
+            while pos > 0 && !chars[pos - 1].1.is_whitespace() {
                 pos -= 1;
             }
 
-            self.col = pos;
-            self.desired_col = pos;
+            self.col = chars.get(pos).map_or(0, |&(i, _)| i);
+            self.remember_col(buffer);
         }
     }
 
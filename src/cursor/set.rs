@@ -0,0 +1,168 @@
+use crate::buffer::Buffer;
+use super::Cursor;
+
+// The primary cursor plus any extra cursors in a window. Edits are run at
+// every cursor and the others are shifted so they keep pointing at the same text.
+pub struct CursorSet {
+    cursors: Vec<Cursor>, // Index 0 is the primary cursor
+}
+
+impl CursorSet {
+    pub fn new(primary: Cursor, extras: &[Cursor]) -> Self {
+        let mut cursors = Vec::with_capacity(extras.len() + 1);
+        cursors.push(primary);
+        cursors.extend_from_slice(extras);
+        Self { cursors }
+    }
+
+    pub fn primary(&self) -> Cursor {
+        self.cursors[0]
+    }
+
+    pub fn extras(&self) -> Vec<Cursor> {
+        self.cursors[1..].to_vec()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cursors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cursor> {
+        self.cursors.iter()
+    }
+
+    pub fn contains(&self, line: usize, col: usize) -> bool {
+        self.cursors.iter().any(|c| c.line == line && c.col == col)
+    }
+
+    pub fn add(&mut self, cursor: Cursor) -> bool {
+        if self.contains(cursor.line, cursor.col) {
+            return false;
+        }
+        self.cursors.push(cursor);
+        true
+    }
+
+    // Runs `edit` at each cursor, last one in the buffer first, and returns what it
+    // produced at the primary cursor
+    pub fn apply<T, F>(&mut self, buffer: &mut Buffer, mut edit: F) -> T
+    where
+        F: FnMut(&mut Cursor, &mut Buffer) -> T,
+    {
+        let mut order: Vec<usize> = (0..self.cursors.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse((self.cursors[i].line, self.cursors[i].col)));
+
+        let mut visited: Vec<usize> = Vec::with_capacity(order.len());
+        let mut primary_result = None;
+
+        for idx in order {
+            let before = self.cursors[idx];
+            let count_before = buffer.line_count();
+            let line_before = buffer.get_line(before.line).cloned().unwrap_or_default();
+            let len_before = line_before.len();
+            let len_next = buffer.get_line(before.line + 1).map(|l| l.len()).unwrap_or(0);
+
+            let result = edit(&mut self.cursors[idx], buffer);
+            if idx == 0 {
+                primary_result = Some(result);
+            }
+
+            let after = self.cursors[idx];
+            let line_delta = buffer.line_count() as isize - count_before as isize;
+            let len_after = buffer.get_line(before.line).map(|l| l.len()).unwrap_or(0);
+            // The next line was pulled up onto the edited one, as J and Delete at the end of a line do
+            let joined = line_delta == -1
+                && after.line == before.line
+                && buffer.get_line(before.line).is_some_and(|l| l.starts_with(&line_before));
+
+            // Cursors visited earlier sit after this one, so only they can move
+            for &other in &visited {
+                let cursor = &mut self.cursors[other];
+                if joined && cursor.line == before.line + 1 {
+                    // Its text ends the joined line, after any space put between the two
+                    cursor.col = len_after.saturating_sub(len_next.saturating_sub(cursor.col)).max(len_before);
+                    cursor.line = after.line;
+                } else if cursor.line > before.line {
+                    cursor.line = (cursor.line as isize + line_delta).max(0) as usize;
+                } else if cursor.line == before.line {
+                    if line_delta == 0 {
+                        cursor.col = (cursor.col as isize + len_after as isize - len_before as isize).max(0) as usize;
+                    } else {
+                        // Text after the edit follows the edited cursor onto its new line
+                        cursor.col = after.col + cursor.col.saturating_sub(before.col);
+                        cursor.line = after.line;
+                    }
+                }
+                cursor.remember_col(buffer);
+            }
+            visited.push(idx);
+        }
+
+        self.normalize(buffer);
+        primary_result.expect("primary cursor is always visited")
+    }
+
+    // Keep cursors inside the buffer and merge any that ended up together
+    fn normalize(&mut self, buffer: &Buffer) {
+        let last_line = buffer.line_count().saturating_sub(1);
+        for cursor in self.cursors.iter_mut() {
+            cursor.line = cursor.line.min(last_line);
+            cursor.col = cursor.col.min(buffer.get_line(cursor.line).map(|l| l.len()).unwrap_or(0));
+        }
+
+        let mut seen = Vec::with_capacity(self.cursors.len());
+        self.cursors.retain(|c| {
+            let pos = (c.line, c.col);
+            if seen.contains(&pos) {
+                false
+            } else {
+                seen.push(pos);
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::BufferKind;
+
+    fn buffer(lines: &[&str]) -> Buffer {
+        Buffer::scratch(BufferKind::File, lines.iter().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn join_moves_the_lower_cursor_onto_the_joined_line() {
+        let mut buffer = buffer(&["abc", "def", "ghi"]);
+        let mut cursors = CursorSet::new(Cursor::at(0, 3, &buffer), &[Cursor::at(1, 1, &buffer)]);
+        cursors.apply(&mut buffer, |cursor, buffer| {
+            // Delete at the end of the first line only
+            if cursor.line == 0 {
+                buffer.join_lines(cursor.line);
+            }
+        });
+        assert_eq!(buffer.lines(), ["abc def", "ghi"]);
+        let extra = cursors.extras()[0];
+        assert_eq!((extra.line, extra.col), (0, 5));
+        assert_eq!(&buffer.get_line(0).unwrap()[extra.col..], "ef");
+    }
+
+    #[test]
+    fn deleted_line_keeps_the_lower_cursor_column() {
+        let mut buffer = buffer(&["abc", "def", "ghi"]);
+        let mut cursors = CursorSet::new(Cursor::at(0, 1, &buffer), &[Cursor::at(1, 2, &buffer)]);
+        cursors.apply(&mut buffer, |cursor, buffer| {
+            if cursor.line == 0 {
+                buffer.delete_line(cursor.line);
+            }
+        });
+        assert_eq!(buffer.lines(), ["def", "ghi"]);
+        let extra = cursors.extras()[0];
+        assert_eq!((extra.line, extra.col), (0, 2));
+    }
+}
@@ -0,0 +1,167 @@
+use super::Editor;
+use crate::buffer::{Buffer, BufferKind};
+use crate::command::CommandAction;
+use crate::cursor::Cursor;
+use crate::mode::Mode;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+
+// What vim says to anything that would leave the window another way
+const INVALID: &str = "Invalid in command-line window; <CR> executes, CTRL-C quits";
+
+// The command-line window: the : or / history in a buffer of its own, edited
+// like any other, where Enter runs the line under the cursor
+pub(super) struct CommandWindow {
+    prompt: char,    // ':', '/' or '?'
+    previous: usize, // The window it was opened from, to go back to
+}
+
+impl Editor {
+    // q:, q/ and q?: split off a window listing the history, oldest first,
+    // with an empty line at the bottom for a new command
+    pub(super) fn open_command_window(&mut self, prompt: char) {
+        if self.command_window.is_some() {
+            self.message = Some(INVALID.to_string());
+            return;
+        }
+        let history = match prompt {
+            ':' => &self.histories.command,
+            _ => &self.histories.search,
+        };
+        let mut lines: Vec<String> = history.iter().map(str::to_string).collect();
+        lines.push(String::new());
+        let last = lines.len() - 1;
+
+        // The buffer is made again each time, in the same place
+        let buffer = Buffer::scratch(BufferKind::CommandLine, lines);
+        let buffers = self.window_manager.get_buffers_mut();
+        let buffer_id = match buffers.iter().position(|buffer| buffer.kind() == BufferKind::CommandLine) {
+            Some(buffer_id) => {
+                buffers[buffer_id] = buffer;
+                buffer_id
+            }
+            None => {
+                buffers.push(buffer);
+                buffers.len() - 1
+            }
+        };
+        self.setup_buffer(buffer_id);
+
+        let previous = self.window_manager.get_active_window_id();
+        if let Err(e) = self.window_manager.split_horizontal(None) {
+            self.message = Some(e);
+            return;
+        }
+        self.window_manager.set_active_window(self.window_manager.get_window_count() - 1);
+        self.show_buffer(buffer_id);
+        self.window_manager.set_active_cursor(Cursor { line: last, col: 0, desired_col: 0 });
+        self.command_window = Some(CommandWindow { prompt, previous });
+        self.mode = Mode::Normal;
+    }
+
+    // Keys the command-line window takes before the mode they are typed in:
+    // Enter in normal or insert mode runs the line, Ctrl-c puts it back in the
+    // prompt to go on typing, and other windows can't be reached. True when
+    // the key was taken.
+    pub(super) fn command_window_key(&mut self, key: KeyEvent) -> io::Result<bool> {
+        if self.command_window.is_none() || self.in_search || self.substitute_confirm.is_some() {
+            return Ok(false);
+        }
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Enter if self.mode == Mode::Normal && !self.normal_mode.is_pending() => {
+                self.run_command_window_line()?;
+            }
+            KeyCode::Enter if self.mode == Mode::Insert && self.completion.is_none() => {
+                self.process_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))?;
+                self.run_command_window_line()?;
+            }
+            KeyCode::Char('c') if control => {
+                if self.mode == Mode::Insert {
+                    self.process_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE))?;
+                }
+                if let Some((prompt, line)) = self.close_command_window() {
+                    match prompt {
+                        ':' => {
+                            self.mode = Mode::Command;
+                            self.command_mode.set_input(&line);
+                        }
+                        _ => {
+                            self.start_search(prompt == '/');
+                            self.search_input = line;
+                        }
+                    }
+                }
+            }
+            KeyCode::Char('w') if control && self.mode == Mode::Normal => {
+                self.message = Some(INVALID.to_string());
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Ex commands that would open or close other windows or buffers are
+    // refused, and :q and :close close the command-line window itself. True
+    // when the action was taken care of.
+    pub(super) fn command_window_action(&mut self, action: &CommandAction) -> bool {
+        if self.command_window.is_none() {
+            return false;
+        }
+        match action {
+            CommandAction::Quit | CommandAction::ForceQuit | CommandAction::CloseWindow => {
+                self.close_command_window();
+            }
+            CommandAction::Edit(_)
+            | CommandAction::SplitHorizontal(_)
+            | CommandAction::SplitVertical(_)
+            | CommandAction::CloseOtherWindows
+            | CommandAction::Buffer(_)
+            | CommandAction::Help(_) => self.message = Some(INVALID.to_string()),
+            _ => return false,
+        }
+        true
+    }
+
+    // Close the window and go back to the one it was opened from. The prompt
+    // and the line the cursor was on, if it was open.
+    fn close_command_window(&mut self) -> Option<(char, String)> {
+        let window = self.command_window.take()?;
+        let cursor = self.window_manager.get_active_cursor();
+        let line = self.window_manager.get_active_buffer().get_line(cursor.line).cloned().unwrap_or_default();
+        self.mode = Mode::Normal;
+        self.visual_mode = None;
+        if let Err(e) = self.window_manager.close_window() {
+            self.message = Some(e);
+        }
+        self.window_manager.set_active_window(window.previous);
+        Some((window.prompt, line))
+    }
+
+    // Enter: close the window and run the line as if typed at its prompt
+    fn run_command_window_line(&mut self) -> io::Result<()> {
+        let Some((prompt, line)) = self.close_command_window() else { return Ok(()) };
+        if line.is_empty() {
+            return Ok(());
+        }
+        match prompt {
+            ':' => {
+                self.histories.command.add(&line);
+                // The undo step handle_key opened is on the window's buffer, so
+                // the command gets one of its own, as if typed at the prompt
+                let buffer_id = self.window_manager.get_active_window().buffer_id;
+                self.window_manager.get_active_buffer_mut().begin_undo_group();
+                let result = self.execute_command_line(&line);
+                if let Some(buffer) = self.window_manager.get_buffers_mut().get_mut(buffer_id) {
+                    buffer.end_undo_group();
+                }
+                result?;
+            }
+            _ => {
+                self.search_state.forward = prompt == '/';
+                self.search(&line);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use super::Editor;
+use crate::completion::{Completion, CompletionKind};
+use crate::cursor::Cursor;
+use crate::ui::Popup;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::path::Path;
+
+impl Editor {
+    // Insert-mode completion keys. Returns true when the key was used up here,
+    // otherwise it goes on to InsertMode and may narrow the open popup.
+    pub(super) fn handle_completion_key(&mut self, key: KeyEvent, cursor: &mut Cursor) -> bool {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        if self.completion_ctrl_x {
+            self.completion_ctrl_x = false;
+            let kind = match key.code {
+                KeyCode::Char('l') if ctrl => CompletionKind::Line,
+                KeyCode::Char('f') if ctrl => CompletionKind::FilePath,
+                KeyCode::Char('n') | KeyCode::Char('p') if ctrl => CompletionKind::Keyword,
+                _ => return false,
+            };
+            self.start_completion(kind, key.code != KeyCode::Char('p'), cursor);
+            return true;
+        }
+
+        match key.code {
+            KeyCode::Char('x') if ctrl => {
+                self.completion = None;
+                self.completion_ctrl_x = true;
+                self.message = Some("-- ^X mode (^L lines, ^F file names, ^N/^P keywords)".to_string());
+                true
+            }
+            KeyCode::Char('n') | KeyCode::Char('p') if ctrl => {
+                let forward = key.code == KeyCode::Char('n');
+                match self.completion.as_mut() {
+                    Some(completion) => {
+                        let text = if forward { completion.select_next() } else { completion.select_prev() }.to_string();
+                        self.put_completion_text(&text, cursor);
+                    }
+                    None => self.start_completion(CompletionKind::Keyword, forward, cursor),
+                }
+                true
+            }
+            _ => {
+                let completion = match self.completion.as_ref() {
+                    Some(completion) => completion,
+                    None => return false,
+                };
+                match key.code {
+                    KeyCode::Char('y') if ctrl => {
+                        self.completion = None;
+                        true
+                    }
+                    KeyCode::Char('e') if ctrl => {
+                        // Back to what was typed before cycling
+                        let prefix = completion.prefix().to_string();
+                        self.put_completion_text(&prefix, cursor);
+                        self.completion = None;
+                        true
+                    }
+                    KeyCode::Enter if completion.selected().is_some() => {
+                        self.completion = None;
+                        true
+                    }
+                    KeyCode::Char(c) if !ctrl && completion.continues_with(c) => false,
+                    KeyCode::Backspace => false,
+                    _ => {
+                        self.completion = None;
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    // Re-filter the open popup against the text typed since completion started
+    pub(super) fn narrow_completion(&mut self, cursor: &Cursor) {
+        let buffer = self.window_manager.get_active_buffer();
+        if let Some(completion) = self.completion.as_mut() {
+            let text = buffer.get_line(cursor.line).map_or("", |v| v);
+            if cursor.line != completion.line() || cursor.col < completion.start_col() || cursor.col > text.len() {
+                self.completion = None;
+                return;
+            }
+            completion.narrow(&text[completion.start_col()..cursor.col]);
+            if completion.is_empty() {
+                self.completion = None;
+            }
+        }
+    }
+
+    pub(super) fn completion_popup(&self) -> Option<Popup> {
+        self.completion
+            .as_ref()
+            .map(|c| Popup::new(c.matches().to_vec(), c.selected()))
+    }
+
+    fn start_completion(&mut self, kind: CompletionKind, forward: bool, cursor: &mut Cursor) {
+        let current = self.window_manager.get_active_window().buffer_id;
+        let buffers = self.window_manager.get_buffers();
+        let mut completion = match kind {
+            CompletionKind::Keyword => {
+                let dictionary = self.options.string("dictionary");
+                let dictionary = (!dictionary.is_empty()).then(|| Path::new(&dictionary));
+                Completion::keywords(buffers, current, cursor.line, cursor.col, dictionary)
+            }
+            CompletionKind::Line => Completion::lines(buffers, current, cursor.line, cursor.col),
+            CompletionKind::FilePath => Completion::file_paths(&buffers[current], cursor.line, cursor.col),
+        };
+
+        if completion.is_empty() {
+            self.message = Some("Pattern not found".to_string());
+            return;
+        }
+
+        let text = if forward { completion.select_next() } else { completion.select_prev() }.to_string();
+        self.message = Some(format!(
+            "-- {} completion: {} matches",
+            match kind {
+                CompletionKind::Keyword => "Keyword",
+                CompletionKind::Line => "Whole line",
+                CompletionKind::FilePath => "File name",
+            },
+            completion.matches().len()
+        ));
+        self.completion = Some(completion);
+        self.put_completion_text(&text, cursor);
+    }
+
+    // Replace the text between the completion start and the cursor
+    fn put_completion_text(&mut self, text: &str, cursor: &mut Cursor) {
+        let (line, start_col) = match self.completion.as_ref() {
+            Some(completion) => (completion.line(), completion.start_col()),
+            None => return,
+        };
+        let buffer = self.window_manager.get_active_buffer_mut();
+        let mut content = buffer.get_line(line).cloned().unwrap_or_default();
+        let end = cursor.col.clamp(start_col, content.len());
+        content.replace_range(start_col..end, text);
+        buffer.set_line(line, content);
+        cursor.line = line;
+        cursor.col = start_col + text.len();
+        cursor.remember_col(buffer);
+    }
+}
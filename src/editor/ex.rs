@@ -0,0 +1,358 @@
+use super::Editor;
+use crate::buffer::{Buffer, BufferKind};
+use crate::command::{CommandAction, CommandContext, Preview, SubstituteCount, Substitution, REPORT};
+use crate::cursor::Cursor;
+use crate::help;
+use crate::indent;
+use crate::input::Key;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+
+impl Editor {
+    // Run a command line, each command between |s in turn until one fails.
+    // False when one did.
+    pub(super) fn execute_command_line(&mut self, line: &str) -> io::Result<bool> {
+        for command in self.commands.split(line) {
+            if command.trim().is_empty() {
+                continue;
+            }
+            let mut cursor = self.window_manager.get_active_cursor();
+            let mut context = CommandContext {
+                buffer: self.window_manager.get_active_buffer_mut(),
+                cursor: &mut cursor,
+                registers: &mut self.registers,
+                search: &mut self.search_state,
+                substitute: &mut self.last_substitute,
+                in_global: self.substitute_total.is_some(),
+            };
+            let query = context.search.query.clone();
+            let action = self.commands.execute(&command, &mut context);
+            self.window_manager.set_active_cursor(cursor);
+            // A pattern :s or :g used is highlighted again after :nohlsearch
+            if self.search_state.query != query {
+                self.hlsearch_hidden = false;
+            }
+
+            let failed = matches!(action, CommandAction::Error(_));
+            self.command_action(action)?;
+            if failed {
+                return Ok(false);
+            }
+            if self.quit {
+                break;
+            }
+        }
+        Ok(true)
+    }
+
+    fn command_action(&mut self, action: CommandAction) -> io::Result<()> {
+        if self.command_window_action(&action) {
+            return Ok(());
+        }
+        match action {
+            CommandAction::Quit => {
+                if self.window_manager.get_active_buffer().is_modified() {
+                    self.message = Some("No write since last change (use :q! to override)".to_string());
+                } else {
+                    self.quit = true;
+                }
+            }
+            CommandAction::ForceQuit => {
+                self.quit = true;
+            }
+            CommandAction::Edit(path) => {
+                match Buffer::from_file(&path) {
+                    Ok(new_buffer) => {
+                        // Replace current buffer with new one
+                        let buffer_count = self.window_manager.get_buffers().len();
+                        self.window_manager.get_buffers_mut().push(new_buffer);
+                        self.show_buffer(buffer_count);
+                        self.setup_buffer(buffer_count);
+                    }
+                    Err(e) => {
+                        self.message = Some(format!("Error: {}", e));
+                    }
+                }
+            }
+            CommandAction::SplitHorizontal(file_path) => {
+                match self.window_manager.split_horizontal(file_path.as_deref()) {
+                    Ok(()) if file_path.is_some() => {
+                        self.setup_buffer(self.window_manager.get_buffers().len() - 1);
+                    }
+                    Ok(()) => {}
+                    Err(e) => self.message = Some(e),
+                }
+            }
+            CommandAction::SplitVertical(file_path) => {
+                match self.window_manager.split_vertical(file_path.as_deref()) {
+                    Ok(()) if file_path.is_some() => {
+                        self.setup_buffer(self.window_manager.get_buffers().len() - 1);
+                    }
+                    Ok(()) => {}
+                    Err(e) => self.message = Some(e),
+                }
+            }
+            CommandAction::Buffer(name) => self.switch_buffer(&name),
+            CommandAction::Help(subject) => self.help(&subject),
+            CommandAction::CloseWindow => {
+                if let Err(e) = self.window_manager.close_window() {
+                    self.message = Some(e);
+                }
+            }
+            CommandAction::CloseOtherWindows => {
+                // TODO: Implement close other windows
+                self.message = Some("Close other windows not yet implemented".to_string());
+            }
+            CommandAction::Abbreviate(abbrev) => self.abbreviate(abbrev),
+            CommandAction::Map(map) => self.map(map),
+            CommandAction::Set { args, local } => self.set_options(&args, local),
+            CommandAction::Normal { lines, keys, remap } => self.normal_command(lines, &keys, remap)?,
+            CommandAction::ConfirmSubstitute(substitution) => {
+                self.substitute_confirm = Some(substitution);
+                self.confirm_next_match();
+            }
+            CommandAction::Substituted(count) => match self.substitute_total.as_mut() {
+                Some(total) => total.add(count),
+                None => self.message = Some(count.report()),
+            },
+            CommandAction::Global { lines, command } => self.global_command(lines, &command)?,
+            CommandAction::NoHighlight => self.hlsearch_hidden = true,
+            CommandAction::Message(msg) | CommandAction::Error(msg) => {
+                self.message = Some(msg);
+            }
+            CommandAction::None => {}
+        }
+        Ok(())
+    }
+
+    // Show another buffer in the current window, from its top
+    pub(super) fn show_buffer(&mut self, buffer_id: usize) {
+        let window = self.window_manager.get_active_window_mut();
+        window.buffer_id = buffer_id;
+        window.cursor_line = 0;
+        window.cursor_col = 0;
+        window.extra_cursors.clear();
+        window.viewport_offset = 0;
+    }
+
+    // :buffer N goes to the Nth buffer, :buffer {name} to the one whose name
+    // is {name} or, failing that, the only one with {name} in it
+    fn switch_buffer(&mut self, name: &str) {
+        let buffers = self.window_manager.get_buffers();
+        let found = match name.parse::<usize>() {
+            _ if name.is_empty() => return,
+            Ok(number) => (1..=buffers.len()).contains(&number).then_some(number - 1).ok_or(format!("Buffer {} does not exist", number)),
+            Err(_) => {
+                let names: Vec<String> = buffers.iter().map(Buffer::name).collect();
+                let matching: Vec<usize> = (0..names.len()).filter(|&i| names[i].contains(name)).collect();
+                match (names.iter().position(|other| other == name), matching.as_slice()) {
+                    (Some(exact), _) => Ok(exact),
+                    (None, [only]) => Ok(*only),
+                    (None, []) => Err(format!("No matching buffer for {}", name)),
+                    (None, _) => Err(format!("More than one match for {}", name)),
+                }
+            }
+        };
+        match found {
+            Ok(buffer_id) if buffer_id != self.window_manager.get_active_window().buffer_id => self.show_buffer(buffer_id),
+            Ok(_) => {}
+            Err(msg) => self.message = Some(msg),
+        }
+    }
+
+    // :help opens the help text in a split above, or moves to the tag in the
+    // window already showing it
+    fn help(&mut self, subject: &str) {
+        let line = match subject {
+            "" => 0,
+            subject => match help::find(subject) {
+                Ok(line) => line,
+                Err(msg) => {
+                    self.message = Some(msg);
+                    return;
+                }
+            },
+        };
+        if self.window_manager.get_active_buffer().kind() != BufferKind::Help {
+            let existing = self.window_manager.get_buffers().iter().position(|buffer| buffer.kind() == BufferKind::Help);
+            let buffer_id = match existing {
+                Some(buffer_id) => buffer_id,
+                None => {
+                    let mut buffer = Buffer::scratch(BufferKind::Help, help::lines());
+                    buffer.set_syntax(buffer.find_syntax("markdown"));
+                    self.window_manager.get_buffers_mut().push(buffer);
+                    let buffer_id = self.window_manager.get_buffers().len() - 1;
+                    self.setup_buffer(buffer_id);
+                    buffer_id
+                }
+            };
+            if let Err(e) = self.window_manager.split_horizontal(None) {
+                self.message = Some(e);
+                return;
+            }
+            self.window_manager.set_active_window(self.window_manager.get_window_count() - 1);
+            self.show_buffer(buffer_id);
+        }
+        self.window_manager.set_active_cursor(Cursor { line, col: 0, desired_col: 0 });
+        self.window_manager.set_viewport_offset(line);
+    }
+
+    // :normal: type `keys` in normal mode on each line, or once where the
+    // cursor is. A command the keys leave unfinished is ended as if by Esc.
+    fn normal_command(&mut self, lines: Option<(usize, usize)>, keys: &str, remap: bool) -> io::Result<()> {
+        let keys: Vec<Key> = keys
+            .chars()
+            .map(|c| Key::from_event(&KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)))
+            .collect();
+        let lines: Vec<Option<usize>> = match lines {
+            Some((first, last)) => (first..=last).map(Some).collect(),
+            None => vec![None],
+        };
+
+        // The keys make changes of their own, for ., inside the command line's undo step
+        let command_line = self.pending_change.take();
+        for line in lines {
+            if let Some(line) = line {
+                if line >= self.window_manager.get_active_buffer().line_count() {
+                    break;
+                }
+                let mut cursor = self.window_manager.get_active_cursor();
+                cursor.line = line;
+                cursor.col = 0;
+                cursor.desired_col = 0;
+                self.window_manager.set_active_cursor(cursor);
+            }
+            self.run_keys(&keys, remap, 0)?;
+            let esc = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+            for _ in 0..3 {
+                if self.at_command_start() {
+                    break;
+                }
+                self.handle_key(esc)?;
+            }
+            if let Some(change) = self.pending_change.take() {
+                self.finish_change(change);
+            }
+            if self.quit {
+                break;
+            }
+        }
+        self.pending_change = command_line;
+        Ok(())
+    }
+
+    // :g: run `command` with the cursor on each of `lines` in turn. The lines
+    // are tracked by the buffer, so they stay right as the commands add,
+    // delete or move lines, and are skipped once deleted.
+    fn global_command(&mut self, lines: Vec<usize>, command: &str) -> io::Result<()> {
+        let buffer_id = self.window_manager.get_active_window().buffer_id;
+        let line_count = self.window_manager.get_active_buffer().line_count();
+        self.window_manager.get_active_buffer_mut().track_lines(lines);
+        self.substitute_total = Some(SubstituteCount::default());
+        let mut printed = Vec::new();
+        loop {
+            if self.window_manager.get_active_window().buffer_id != buffer_id {
+                break;
+            }
+            let Some(line) = self.window_manager.get_active_buffer_mut().next_tracked_line() else { break };
+            let mut cursor = self.window_manager.get_active_cursor();
+            cursor.line = line.min(self.window_manager.get_active_buffer().line_count() - 1);
+            cursor.col = 0;
+            cursor.desired_col = 0;
+            self.window_manager.set_active_cursor(cursor);
+            self.message = None;
+            let succeeded = self.execute_command_line(command)?;
+            if !succeeded || self.quit {
+                break;
+            }
+            printed.extend(self.message.take());
+        }
+        if let Some(buffer) = self.window_manager.get_buffers_mut().get_mut(buffer_id) {
+            buffer.clear_tracked_lines();
+        }
+        let total = self.substitute_total.take().unwrap_or_default();
+        if self.message.is_some() {
+            return Ok(()); // An error, shown as it is
+        }
+
+        // Report what all the commands did together
+        let now = self.window_manager.get_active_buffer().line_count();
+        self.message = if total.count > 0 {
+            Some(total.report())
+        } else if now + REPORT < line_count {
+            Some(format!("{} fewer lines", line_count - now))
+        } else if line_count + REPORT < now {
+            Some(format!("{} more lines", now - line_count))
+        } else if !printed.is_empty() {
+            Some(printed.join(" | "))
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    // What the command line being typed would do, when inccommand is set
+    pub(super) fn command_preview(&mut self) -> Option<Preview> {
+        if self.options.string("inccommand").is_empty() {
+            return None;
+        }
+        let mut cursor = self.window_manager.get_active_cursor();
+        let context = CommandContext {
+            buffer: self.window_manager.get_active_buffer_mut(),
+            cursor: &mut cursor,
+            registers: &mut self.registers,
+            search: &mut self.search_state,
+            substitute: &mut self.last_substitute,
+            in_global: false,
+        };
+        self.commands.preview(self.command_mode.input(), &context)
+    }
+
+    // :s///c: y replaces the match and n skips it, a replaces it and all the
+    // rest, l replaces it and stops, and q or Esc stop
+    pub(super) fn confirm_substitute_key(&mut self, key: KeyEvent) {
+        let Some(mut substitution) = self.substitute_confirm.take() else { return };
+        let buffer = self.window_manager.get_active_buffer_mut();
+        match key.code {
+            KeyCode::Char('y') => substitution.replace(buffer),
+            KeyCode::Char('n') => substitution.skip(buffer),
+            KeyCode::Char('a') => substitution.run(buffer),
+            KeyCode::Char('l') => {
+                substitution.replace(buffer);
+                return self.finish_substitute(&substitution);
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return self.finish_substitute(&substitution),
+            _ => {}
+        }
+        self.substitute_confirm = Some(substitution);
+        self.confirm_next_match();
+    }
+
+    // Put the cursor on the next match and ask about it, or finish when there are none left
+    fn confirm_next_match(&mut self) {
+        let Some(mut substitution) = self.substitute_confirm.take() else { return };
+        match substitution.next_match(self.window_manager.get_active_buffer()) {
+            Some((line, start, _)) => {
+                let mut cursor = self.window_manager.get_active_cursor();
+                cursor.line = line;
+                cursor.col = start;
+                cursor.remember_col(self.window_manager.get_active_buffer());
+                self.window_manager.set_active_cursor(cursor);
+                self.message = Some(format!("replace with {} (y/n/a/q/l)?", substitution.replacement_text));
+                self.substitute_confirm = Some(substitution);
+            }
+            None => self.finish_substitute(&substitution),
+        }
+    }
+
+    fn finish_substitute(&mut self, substitution: &Substitution) {
+        if let Some(line) = substitution.last_changed() {
+            let mut cursor = self.window_manager.get_active_cursor();
+            cursor.line = line;
+            cursor.col = self.window_manager.get_active_buffer().get_line(line).map_or(0, |text| indent::indent_len(text));
+            cursor.remember_col(self.window_manager.get_active_buffer());
+            self.window_manager.set_active_cursor(cursor);
+            self.message = Some(substitution.total().report());
+        }
+    }
+}
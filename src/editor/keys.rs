@@ -0,0 +1,213 @@
+use super::Editor;
+use crate::config::{Config, KeyBinding};
+use crate::input::{format_keys, parse_keys, Key, KeyMap, MapCommand, MapMode, MapModes, Mapping};
+use crate::mode::{Mode, NormalMode};
+use crate::ui::KeyHints;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+
+// How deep mappings may expand into other mappings before giving up
+const MAX_MAP_DEPTH: usize = 1000;
+
+// Mappings from the config's [keybindings.<mode>] tables. They don't remap the
+// keys they produce. Errors go to `message`.
+pub(super) fn config_keymap(config: &Config, leader: &[Key], message: &mut Option<String>) -> KeyMap {
+    let mut keymap = KeyMap::new();
+    let mut tables: Vec<_> = config.keybindings.iter().collect();
+    tables.sort_by(|a, b| a.0.cmp(b.0));
+    for (mode, table) in tables {
+        let modes = match mode.as_str() {
+            "normal" => MapModes::NORMAL,
+            "visual" => MapModes::VISUAL,
+            "operator" => MapModes::OPERATOR_PENDING,
+            "insert" => MapModes::INSERT,
+            "command" => MapModes::COMMAND,
+            _ => {
+                *message = Some(format!("Config error in keybindings.{}: unknown mode", mode));
+                continue;
+            }
+        };
+        for (lhs, rhs) in table {
+            let lhs = parse_keys(lhs, leader);
+            if lhs.is_empty() {
+                *message = Some(format!("Config error in keybindings.{}: empty key", mode));
+            } else {
+                let (rhs, desc) = match rhs {
+                    KeyBinding::Keys(keys) => (keys, None),
+                    KeyBinding::Described { keys, desc } => (keys, Some(desc.as_str())),
+                };
+                keymap.define(modes, &lhs, &parse_keys(rhs, leader), true, desc);
+            }
+        }
+    }
+    keymap
+}
+
+impl Editor {
+    // A key from the terminal. Keys that may start a mapping are held until
+    // they complete one, can't, or `timeoutlen` runs out (`timed_out`).
+    pub(super) fn feed_key(&mut self, key: Option<KeyEvent>, timed_out: bool) -> io::Result<()> {
+        self.typed_keys.extend(key);
+        while !self.typed_keys.is_empty() && !self.quit {
+            let keys: Vec<Key> = self.typed_keys.iter().map(Key::from_event).collect();
+            let mode = self.map_mode();
+            if !timed_out && mode.is_some_and(|mode| self.keymap.has_longer(mode, &keys)) {
+                return Ok(());
+            }
+            match mode.and_then(|mode| self.keymap.longest_match(mode, &keys)).cloned() {
+                Some(mapping) => {
+                    self.typed_keys.drain(..mapping.lhs.len());
+                    self.run_mapping(&mapping, 0)?;
+                }
+                None => {
+                    let key = self.typed_keys.remove(0);
+                    self.handle_key(key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn has_typed_keys(&self) -> bool {
+        !self.typed_keys.is_empty()
+    }
+
+    fn run_mapping(&mut self, mapping: &Mapping, depth: usize) -> io::Result<()> {
+        if depth >= MAX_MAP_DEPTH {
+            self.message = Some("Recursive mapping".to_string());
+            return Ok(());
+        }
+        if mapping.noremap {
+            return self.run_keys(&mapping.rhs, false, depth);
+        }
+        // As in vim, a rhs that starts with its own lhs (nmap j jzz) runs those
+        // keys unmapped instead of expanding forever
+        match mapping.rhs.strip_prefix(mapping.lhs.as_slice()) {
+            Some(rest) => {
+                self.run_keys(&mapping.lhs, false, depth)?;
+                self.run_keys(rest, true, depth + 1)
+            }
+            None => self.run_keys(&mapping.rhs, true, depth + 1),
+        }
+    }
+
+    // Keys a mapping produced, mapped again if `remap`. They are all there, so
+    // unlike typed keys the longest mapping is taken without waiting.
+    pub(super) fn run_keys(&mut self, keys: &[Key], remap: bool, depth: usize) -> io::Result<()> {
+        let mut i = 0;
+        while i < keys.len() && !self.quit {
+            let mapping = match self.map_mode() {
+                Some(mode) if remap => self.keymap.longest_match(mode, &keys[i..]).cloned(),
+                _ => None,
+            };
+            match mapping {
+                Some(mapping) => {
+                    i += mapping.lhs.len();
+                    self.run_mapping(&mapping, depth)?;
+                }
+                None => {
+                    self.handle_key(keys[i].to_event())?;
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The mode whose mappings apply to the next key. Keys taken literally,
+    // like a register name or the key after Ctrl-w, aren't mapped.
+    fn map_mode(&self) -> Option<MapMode> {
+        if self.in_search {
+            return Some(MapMode::Command);
+        }
+        match self.mode {
+            Mode::Normal if self.window_command || self.normal_mode.awaiting_name() => None,
+            Mode::Normal if self.normal_mode.has_operator() => Some(MapMode::OperatorPending),
+            Mode::Normal => Some(MapMode::Normal),
+            Mode::Insert if self.insert_mode.is_pending() => None,
+            Mode::Insert => Some(MapMode::Insert),
+            Mode::Visual(_) => Some(MapMode::Visual),
+            Mode::Command => Some(MapMode::Command),
+        }
+    }
+
+    // Whether a prefix such as g, d, Ctrl-w or the start of a mapping is waiting
+    // for more keys in a mode where the key hint popup is shown
+    pub(super) fn hints_pending(&self) -> bool {
+        if !matches!(self.mode, Mode::Normal | Mode::Visual(_)) || self.in_search {
+            return false;
+        }
+        !self.typed_keys.is_empty() || self.window_command || self.normal_mode.pending_prefix().is_some()
+    }
+
+    // What the key hint popup lists: the keys that can follow the pending
+    // prefix, built in or mapped
+    pub(super) fn key_hints(&self) -> Option<KeyHints> {
+        if !self.show_key_hints || !self.hints_pending() {
+            return None;
+        }
+        let builtin = |prefix: KeyEvent| -> Vec<(String, String)> {
+            NormalMode::continuations(prefix).into_iter().map(|(key, desc)| (key, desc.to_string())).collect()
+        };
+        let mode = self.map_mode();
+        let (prefix, mut entries) = if !self.typed_keys.is_empty() {
+            let keys: Vec<Key> = self.typed_keys.iter().map(Key::from_event).collect();
+            let mut entries = match (mode, keys.as_slice()) {
+                (Some(MapMode::Normal), [key]) => builtin(key.to_event()),
+                _ => Vec::new(),
+            };
+            if let Some(mode) = mode {
+                let mapped = self.keymap.continuations(mode, &keys);
+                // A mapping hides the built-in command on the same keys
+                entries.retain(|(key, _)| !mapped.iter().any(|(mapped, _)| mapped == key));
+                entries.extend(mapped);
+            }
+            (format_keys(&keys), entries)
+        } else if self.window_command {
+            let ctrl_w = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+            ("<C-w>".to_string(), builtin(ctrl_w))
+        } else {
+            let prefix = self.normal_mode.pending_prefix()?;
+            let mut entries = builtin(KeyEvent::new(KeyCode::Char(prefix), KeyModifiers::NONE));
+            if mode == Some(MapMode::OperatorPending) {
+                entries.extend(self.keymap.continuations(MapMode::OperatorPending, &[]));
+            }
+            (prefix.to_string(), entries)
+        };
+        entries.dedup();
+        (!entries.is_empty()).then(|| KeyHints::new(prefix, entries))
+    }
+
+    // The keys <leader> stands for
+    fn leader(&self) -> Vec<Key> {
+        parse_keys(&self.options.string("mapleader"), &[])
+    }
+
+    pub(super) fn map(&mut self, command: MapCommand) {
+        let leader = self.leader();
+        match command {
+            MapCommand::Define { modes, noremap, lhs, rhs, desc } => {
+                let lhs = parse_keys(&lhs, &leader);
+                if lhs.is_empty() {
+                    self.message = Some("Invalid argument: empty key".to_string());
+                } else {
+                    self.keymap.define(modes, &lhs, &parse_keys(&rhs, &leader), noremap, desc.as_deref());
+                }
+            }
+            MapCommand::Remove { modes, lhs } => {
+                if !self.keymap.remove(modes, &parse_keys(&lhs, &leader)) {
+                    self.message = Some(format!("No such mapping: {}", lhs));
+                }
+            }
+            MapCommand::Clear { modes } => self.keymap.clear(modes),
+            MapCommand::List { modes, lhs } => {
+                let entries = self.keymap.list(modes, &parse_keys(&lhs, &leader));
+                self.message = Some(if entries.is_empty() {
+                    "No mapping found".to_string()
+                } else {
+                    entries.join(" | ")
+                });
+            }
+        }
+    }
+}
@@ -1,13 +1,30 @@
+mod cmdwin;
+mod completion;
+mod ex;
+mod keys;
+mod options;
+mod repeat;
+mod search;
 mod state;
 
 use crate::buffer::Buffer;
-use crate::mode::{Mode, NormalMode, InsertMode, VisualMode, CommandMode};
+use crate::cursor::{Cursor, CursorSet};
+use crate::mode::{Mode, NormalMode, InsertMode, InsertAction, InsertContext, VisualMode, VisualAction, BlockInsert, CommandMode};
 use crate::ui::Renderer;
-use crate::command::{execute_command, CommandAction, CommandResult};
-use crate::search::SearchState;
+use crate::command::{CommandExecutor, CommandLineContext, CommandResult, CompletionSources, LastSubstitute, SubstituteCount, Substitution};
+use crate::completion::Completion;
+use crate::config::Config;
+use crate::history::{Histories, Recall};
+use crate::indent;
+use crate::input::{parse_keys, AbbrevCommand, AbbrevModes, Abbreviations, AutoPairs, KeyMap};
+use crate::options::{Options, Scope, Settings, OPTIONS};
+use crate::register::Registers;
+use crate::search::{Match, SearchState};
 use crate::window::WindowManager;
-use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use std::collections::HashMap;
 use std::io;
+use std::time::Duration;
 
 pub use state::EditorState;
 
@@ -15,14 +32,43 @@ pub struct Editor {
     window_manager: WindowManager,
     mode: Mode,
     normal_mode: NormalMode,
+    insert_mode: InsertMode,
+    insert_one_shot: bool, // Ctrl-o from insert mode, go back after one normal command
     visual_mode: Option<VisualMode>,
+    block_insert: Option<BlockInsert>,
     command_mode: CommandMode,
+    registers: Registers,
     search_state: SearchState,
     search_input: String,
     in_search: bool,
+    search_start: Option<(Cursor, usize)>, // Cursor and viewport when / or ? was typed
+    incsearch: Option<(String, Match)>,    // Pattern being typed and the match it found (incsearch)
+    hlsearch_hidden: bool,                 // :nohlsearch until the next search
+    match_cache: search::MatchCache,
+    search_recall: Recall,
+    histories: Histories, // Loaded at startup and saved on exit
+    command_window: Option<cmdwin::CommandWindow>, // Open after q: or q/
     renderer: Renderer,
     quit: bool,
     message: Option<String>,
+    options: Options, // Global option values
+    completion: Option<Completion>,
+    completion_ctrl_x: bool,
+    abbreviations: Abbreviations,
+    filetype_abbreviations: HashMap<String, Abbreviations>,
+    filetype_pairs: HashMap<String, AutoPairs>, // From the config; other filetypes use the built-in pairs
+    filetype_options: Vec<(String, Settings)>,  // [filetype.<name>] sections, then ftplugin files
+    keymap: KeyMap,
+    commands: CommandExecutor,
+    last_substitute: Option<LastSubstitute>,
+    substitute_confirm: Option<Box<Substitution>>, // :s///c waiting for y/n/a/q/l
+    substitute_total: Option<SubstituteCount>,     // Added up while :g runs
+    typed_keys: Vec<KeyEvent>, // Keys that may be the start of a mapping
+    window_command: bool,      // Ctrl-w was typed, the next key picks the window command
+    show_key_hints: bool,      // A prefix has been pending for keyhintdelay
+    pending_change: Option<repeat::PendingChange>,
+    last_change: Option<Vec<KeyEvent>>, // Keys replayed by .
+    replaying: bool,
 }
 
 impl Editor {
@@ -36,21 +82,94 @@ impl Editor {
         let mut renderer = Renderer::new()?;
         renderer.enter()?;
 
-        let window_manager = WindowManager::new(buffer);
+        let mut message = None;
+        let config = match Config::default_path() {
+            Some(path) => match Config::load(&path) {
+                Ok(config) => config,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+                Err(e) => {
+                    message = Some(format!("Error in {}: {}", path.display(), e));
+                    Config::default()
+                }
+            },
+            None => Config::default(),
+        };
+        let options = Options::from_config(&config);
+
+        let mut window_manager = WindowManager::new(buffer);
+        window_manager.get_active_window_mut().options = options.local(Scope::Window);
+
+        let mut filetype_abbreviations = HashMap::new();
+        for (filetype, table) in &config.abbreviations {
+            let abbreviations: &mut Abbreviations = filetype_abbreviations.entry(filetype.to_lowercase()).or_default();
+            for (lhs, rhs) in table {
+                abbreviations.define(AbbrevModes { insert: true, command: false }, lhs, rhs);
+            }
+        }
+
+        let mut filetype_pairs = HashMap::new();
+        for (filetype, pairs) in &config.pairs {
+            match AutoPairs::parse(pairs) {
+                Ok(pairs) => {
+                    filetype_pairs.insert(filetype.to_lowercase(), pairs);
+                }
+                Err(e) => message = Some(format!("Config error in pairs.{}: {}", filetype, e)),
+            }
+        }
+
+        let filetype_options = options::filetype_options(&config, &mut message);
+        let leader = parse_keys(&options.string("mapleader"), &[]);
+        let keymap = keys::config_keymap(&config, &leader, &mut message);
+        let histories = Histories::load().unwrap_or_else(|e| {
+            message = Some(e);
+            Histories::default()
+        });
 
-        Ok(Self {
+        let mut editor = Self {
             window_manager,
             mode: Mode::Normal,
             normal_mode: NormalMode::new(),
+            insert_mode: InsertMode::new(),
+            insert_one_shot: false,
             visual_mode: None,
+            block_insert: None,
             command_mode: CommandMode::new(),
+            registers: Registers::new(),
             search_state: SearchState::new(),
             search_input: String::new(),
             in_search: false,
+            search_start: None,
+            incsearch: None,
+            hlsearch_hidden: false,
+            match_cache: search::MatchCache::default(),
+            search_recall: Recall::default(),
+            histories,
+            command_window: None,
             renderer,
             quit: false,
-            message: None,
-        })
+            message,
+            options,
+            completion: None,
+            completion_ctrl_x: false,
+            abbreviations: Abbreviations::new(),
+            filetype_abbreviations,
+            filetype_pairs,
+            filetype_options,
+            keymap,
+            commands: CommandExecutor::new(),
+            last_substitute: None,
+            substitute_confirm: None,
+            substitute_total: None,
+            typed_keys: Vec::new(),
+            window_command: false,
+            show_key_hints: false,
+            pending_change: None,
+            last_change: None,
+            replaying: false,
+        };
+        editor.setup_buffer(0);
+        editor.sync_search_options();
+        Ok(editor)
     }
 
     pub fn run(&mut self) -> io::Result<()> {
@@ -64,253 +183,501 @@ impl Editor {
                 self.message.as_deref().map(|s| s.to_string())
             };
 
-            self.renderer.render(
+            // A :s being typed is drawn as if it had run, from a copy of the
+            // lines swapped in just for this render (inccommand)
+            let mut preview = match self.mode {
+                Mode::Command => self.command_preview(),
+                _ => None,
+            };
+            let matches = match (&preview, &self.substitute_confirm) {
+                (Some(preview), _) => preview.matches.clone(),
+                (None, Some(substitution)) => substitution.current().into_iter().collect(),
+                (None, None) => self.search_highlights(),
+            };
+            let mut changed: Vec<usize> = match self.options.string("inccommand").as_str() {
+                "split" => matches.iter().map(|&(line, _, _)| line).collect(),
+                _ => Vec::new(),
+            };
+            changed.dedup();
+            self.renderer.set_matches(matches);
+            self.renderer.set_preview_lines(changed);
+            if let Some(preview) = preview.as_mut() {
+                self.window_manager.get_active_buffer_mut().swap_lines(&mut preview.lines);
+            }
+
+            let popup = self.completion_popup();
+            self.renderer.set_show_status(self.options.bool("showstatus"));
+            let search_count = self.search_count();
+            self.renderer.set_search_count(search_count);
+            self.renderer.set_key_hints(self.key_hints());
+            let rendered = self.renderer.render(
                 &self.window_manager,
                 &self.mode,
                 &self.command_mode,
                 self.visual_mode.as_ref(),
                 status_message.as_deref(),
-            )?;
+                popup.as_ref(),
+            );
+            if let Some(preview) = preview.as_mut() {
+                self.window_manager.get_active_buffer_mut().swap_lines(&mut preview.lines);
+            }
+            rendered?;
 
+            // Keys that may start a mapping wait for the rest of it until
+            // timeoutlen, and a pending prefix shows its key hints after
+            // keyhintdelay. While the hints are up, keys wait for as long as it takes.
+            let hints_wanted = self.options.bool("keyhints") && self.hints_pending();
+            if !self.show_key_hints && (hints_wanted || self.has_typed_keys()) {
+                let timeout = if self.has_typed_keys() { self.options.number("timeoutlen") } else { usize::MAX };
+                let delay = if hints_wanted { self.options.number("keyhintdelay") } else { usize::MAX };
+                if !event::poll(Duration::from_millis(timeout.min(delay) as u64))? {
+                    if delay < timeout {
+                        self.show_key_hints = true;
+                    } else {
+                        self.feed_key(None, true)?;
+                    }
+                    continue;
+                }
+            }
             if let Event::Key(key) = event::read()? {
                 // Filter out key release events to prevent double input
                 if matches!(key.kind, crossterm::event::KeyEventKind::Release) {
                     continue;
                 }
-                
-                self.message = None;
+                self.show_key_hints = false;
+                self.feed_key(Some(key), false)?;
+            }
+        }
 
-                // Handle Ctrl+C for quit in any mode
-                if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
-                    self.quit = true;
-                    continue;
-                }
+        self.renderer.exit()?;
+        if let Err(e) = self.histories.save() {
+            eprintln!("{}", e);
+        }
+        Ok(())
+    }
+
+    fn process_key(&mut self, key: KeyEvent) -> io::Result<()> {
+        self.message = None;
+
+        if self.command_window_key(key)? {
+            return Ok(());
+        }
+
+        // Handle Ctrl+C for quit in any mode
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return Ok(());
+        }
+
+        if self.in_search {
+            self.search_key(key);
+            return Ok(());
+        }
+
+        if self.substitute_confirm.is_some() {
+            self.confirm_substitute_key(key);
+            return Ok(());
+        }
+
+        if self.window_command {
+            self.window_command = false;
+            self.window_command(key);
+            return Ok(());
+        }
 
-                // Handle search input
-                if self.in_search {
-                    match key.code {
-                        KeyCode::Esc => {
-                            self.in_search = false;
-                            self.search_input.clear();
+        match self.mode {
+            Mode::Normal => {
+                use crate::mode::NormalAction;
+                let mut cursor = self.window_manager.get_active_cursor();
+                let extras = self.window_manager.get_extra_cursors().to_vec();
+                let action = if !extras.is_empty() && self.normal_mode.applies_at_every_cursor(&key) {
+                    // Replay the key at every cursor from the same pending state
+                    let snapshot = self.normal_mode.clone();
+                    let normal_mode = &mut self.normal_mode;
+                    let registers = &mut self.registers;
+                    let mut cursors = CursorSet::new(cursor, &extras);
+                    let buffer = self.window_manager.get_active_buffer_mut();
+                    buffer.begin_undo_group();
+                    let action = cursors.apply(buffer, |cursor, buffer| {
+                        *normal_mode = snapshot.clone();
+                        normal_mode.handle_key(key, cursor, buffer, registers)
+                    });
+                    buffer.end_undo_group();
+                    cursor = cursors.primary();
+                    self.window_manager.set_extra_cursors(cursors.extras());
+                    action
+                } else {
+                    if key.code == KeyCode::Esc {
+                        self.window_manager.set_extra_cursors(Vec::new());
+                    }
+                    self.normal_mode.handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut(), &mut self.registers)
+                };
+                match action {
+                    NormalAction::ModeChange(new_mode) => {
+                        self.mode = new_mode;
+                        if let Mode::Visual(vtype) = new_mode {
+                            self.visual_mode = Some(VisualMode::new(vtype, &cursor));
+                        }
+                    }
+                    NormalAction::StartSearch(forward) => self.start_search(forward),
+                    NormalAction::NextMatch | NormalAction::PrevMatch => {
+                        self.window_manager.set_active_cursor(cursor);
+                        self.jump_to_match(matches!(action, NormalAction::PrevMatch));
+                        cursor = self.window_manager.get_active_cursor();
+                    }
+                    NormalAction::SearchWord { forward, whole_word } => {
+                        self.window_manager.set_active_cursor(cursor);
+                        self.search_word(forward, whole_word);
+                        cursor = self.window_manager.get_active_cursor();
+                    }
+                    NormalAction::SelectMatch { forward, operator } => {
+                        self.window_manager.set_active_cursor(cursor);
+                        return self.select_match(forward, operator);
+                    }
+                    NormalAction::CommandWindow(prompt) => {
+                        self.window_manager.set_active_cursor(cursor);
+                        self.open_command_window(prompt);
+                        return Ok(());
+                    }
+                    NormalAction::WindowCommand => self.window_command = true,
+                    NormalAction::AddCursorAtNextMatch => self.add_cursor_at_next_match(&cursor),
+                    NormalAction::AddCursorAbove => self.add_cursor_vertical(&cursor, false),
+                    NormalAction::AddCursorBelow => self.add_cursor_vertical(&cursor, true),
+                    NormalAction::Reindent(start, end) => self.reindent(start, end, &mut cursor),
+                    NormalAction::Message(msg) => self.message = Some(msg),
+                    NormalAction::RepeatLastChange => {
+                        self.window_manager.set_active_cursor(cursor);
+                        return self.repeat_last_change();
+                    }
+                    NormalAction::ExCommand(command) => {
+                        self.window_manager.set_active_cursor(cursor);
+                        self.execute_command_line(&command)?;
+                        self.finish_one_shot_normal();
+                        return Ok(());
+                    }
+                    NormalAction::None => {}
+                }
+                self.window_manager.set_active_cursor(cursor);
+                self.finish_one_shot_normal();
+            }
+            Mode::Insert => {
+                let mut cursor = self.window_manager.get_active_cursor();
+                let extras = self.window_manager.get_extra_cursors().to_vec();
+                if extras.is_empty() && self.handle_completion_key(key, &mut cursor) {
+                    self.window_manager.set_active_cursor(cursor);
+                    return Ok(());
+                }
+                let filetype = self.window_manager.get_active_buffer().syntax_name().map(str::to_lowercase);
+                let abbreviations = abbreviation_layers(&self.abbreviations, &self.filetype_abbreviations, filetype.as_deref());
+                let pairs = self.auto_pairs(filetype.as_deref());
+                let context = InsertContext {
+                    registers: &self.registers,
+                    abbreviations: &abbreviations,
+                    pairs: &pairs,
+                };
+                let action = if extras.is_empty() {
+                    self.insert_mode.handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut(), &context)
+                } else {
+                    // Replay the key at every cursor from the same pending state
+                    let snapshot = self.insert_mode.clone();
+                    let insert_mode = &mut self.insert_mode;
+                    let mut cursors = CursorSet::new(cursor, &extras);
+                    let buffer = self.window_manager.get_active_buffer_mut();
+                    buffer.begin_undo_group();
+                    let action = cursors.apply(buffer, |cursor, buffer| {
+                        *insert_mode = snapshot.clone();
+                        insert_mode.handle_key(key, cursor, buffer, &context)
+                    });
+                    buffer.end_undo_group();
+                    cursor = cursors.primary();
+                    self.window_manager.set_extra_cursors(cursors.extras());
+                    action
+                };
+                self.narrow_completion(&cursor);
+                match action {
+                    InsertAction::ModeChange(new_mode) => {
+                        self.mode = new_mode;
+                        self.completion = None;
+                        self.registers.set_last_inserted(self.insert_mode.take_inserted());
+                        if let Some(block_insert) = self.block_insert.take() {
+                            // Repeat what was typed on the first line down the block
+                            let buffer = self.window_manager.get_active_buffer_mut();
+                            block_insert.apply(buffer);
+                            buffer.end_undo_group();
+                            cursor.line = block_insert.start_line;
+                            cursor.col = block_insert.col;
+                            cursor.remember_col(buffer);
                         }
-                        KeyCode::Enter => {
-                            let forward = self.search_state.forward;
-                            self.search_state.search(self.window_manager.get_active_buffer(), &self.search_input, forward);
-                            if let Some((line, col)) = self.search_state.current() {
-                                let mut cursor = self.window_manager.get_active_cursor();
-                                cursor.line = line;
-                                cursor.col = col;
-                                cursor.desired_col = col;
-                                self.window_manager.set_active_cursor(cursor);
-                                self.message = Some(format!(
-                                    "Match 1 of {} for '{}'",
-                                    self.search_state.match_count(),
-                                    self.search_input
-                                ));
+                    }
+                    InsertAction::OneShotNormal => {
+                        self.mode = Mode::Normal;
+                        self.completion = None;
+                        self.insert_one_shot = true;
+                    }
+                    InsertAction::ScrollPage(forward) => {
+                        let page = self.renderer.height().saturating_sub(3).max(1);
+                        let buffer = self.window_manager.get_active_buffer();
+                        for _ in 0..page {
+                            if forward {
+                                cursor.move_down(buffer);
                             } else {
-                                self.message = Some(format!("Pattern not found: {}", self.search_input));
+                                cursor.move_up(buffer);
                             }
-                            self.in_search = false;
-                            self.search_input.clear();
-                        }
-                        KeyCode::Backspace => {
-                            self.search_input.pop();
-                        }
-                        KeyCode::Char(c) => {
-                            self.search_input.push(c);
                         }
-                        _ => {}
                     }
-                    continue;
+                    InsertAction::None => {}
                 }
-
-                match self.mode {
-                    Mode::Normal => {
-                        use crate::mode::NormalAction;
-                        let mut cursor = self.window_manager.get_active_cursor();
-                        match self.normal_mode.handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut()) {
-                            NormalAction::ModeChange(new_mode) => {
-                                self.mode = new_mode;
-                                if let Mode::Visual(vtype) = new_mode {
-                                    self.visual_mode = Some(VisualMode::new(vtype, &cursor));
-                                }
-                            }
-                            NormalAction::StartSearch(forward) => {
-                                self.in_search = true;
-                                self.search_input.clear();
-                                self.search_state.forward = forward;
-                            }
-                            NormalAction::NextMatch => {
-                                if let Some((line, col)) = self.search_state.next_match() {
-                                    cursor.line = line;
-                                    cursor.col = col;
-                                    cursor.desired_col = col;
-                                    if let Some(current) = self.search_state.current_match {
-                                        self.message = Some(format!(
-                                            "Match {} of {}",
-                                            current + 1,
-                                            self.search_state.match_count()
-                                        ));
-                                    }
-                                } else {
-                                    self.message = Some("No search pattern".to_string());
-                                }
-                            }
-                            NormalAction::PrevMatch => {
-                                if let Some((line, col)) = self.search_state.prev_match() {
-                                    cursor.line = line;
-                                    cursor.col = col;
-                                    cursor.desired_col = col;
-                                    if let Some(current) = self.search_state.current_match {
-                                        self.message = Some(format!(
-                                            "Match {} of {}",
-                                            current + 1,
-                                            self.search_state.match_count()
-                                        ));
-                                    }
-                                } else {
-                                    self.message = Some("No search pattern".to_string());
-                                }
+                self.window_manager.set_active_cursor(cursor);
+            }
+            Mode::Visual(_) => {
+                if let Some(ref mut visual) = self.visual_mode {
+                    let mut cursor = self.window_manager.get_active_cursor();
+                    match visual.handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut(), &mut self.registers) {
+                        VisualAction::ModeChange(new_mode) => {
+                            self.mode = new_mode;
+                            if !matches!(new_mode, Mode::Visual(_)) {
+                                self.visual_mode = None;
                             }
-                            NormalAction::WindowCommand => {
-                                // Read next key for window command
-                                if let Event::Key(next_key) = event::read()? {
-                                    if let Some(cmd) = self.normal_mode.handle_window_command(next_key) {
-                                        match cmd.as_str() {
-                                            "next_window" => self.window_manager.next_window(),
-                                            "prev_window" => self.window_manager.prev_window(),
-                                            "split_horizontal" => {
-                                                if let Err(e) = self.window_manager.split_horizontal(None) {
-                                                    self.message = Some(e);
-                                                }
-                                            }
-                                            "split_vertical" => {
-                                                if let Err(e) = self.window_manager.split_vertical(None) {
-                                                    self.message = Some(e);
-                                                }
-                                            }
-                                            "close_window" => {
-                                                if let Err(e) = self.window_manager.close_window() {
-                                                    self.message = Some(e);
-                                                }
-                                            }
-                                            "close_other_windows" => {
-                                                self.message = Some("Close other windows not yet implemented".to_string());
-                                            }
-                                            "increase_height" | "decrease_height" | "increase_width" | "decrease_width" | "equal_size" => {
-                                                self.message = Some("Window resizing not yet implemented".to_string());
-                                            }
-                                            _ if cmd.starts_with("navigate_") => {
-                                                let direction = cmd.chars().last().unwrap_or('h');
-                                                self.window_manager.navigate_to_window(direction);
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
+                            if new_mode == Mode::Command {
+                                self.command_mode.set_input("'<,'>");
                             }
-                            NormalAction::None => {}
                         }
-                        self.window_manager.set_active_cursor(cursor);
-                    }
-                    Mode::Insert => {
-                        let mut cursor = self.window_manager.get_active_cursor();
-                        if let Some(new_mode) = InsertMode::handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut()) {
-                            self.mode = new_mode;
+                        VisualAction::BlockInsert(block_insert) => {
+                            self.block_insert = Some(block_insert);
+                            self.mode = Mode::Insert;
+                            self.visual_mode = None;
                         }
-                        self.window_manager.set_active_cursor(cursor);
-                    }
-                    Mode::Visual(_) => {
-                        if let Some(ref mut visual) = self.visual_mode {
-                            let mut cursor = self.window_manager.get_active_cursor();
-                            if let Some(new_mode) = visual.handle_key(key, &mut cursor, self.window_manager.get_active_buffer_mut()) {
-                                self.mode = new_mode;
-                                self.visual_mode = None;
-                            }
-                            self.window_manager.set_active_cursor(cursor);
+                        VisualAction::SplitCursors(cursors) => {
+                            cursor = cursors[0];
+                            self.message = Some(format!("{} cursors", cursors.len()));
+                            self.window_manager.set_extra_cursors(cursors[1..].to_vec());
+                            self.mode = Mode::Normal;
+                            self.visual_mode = None;
+                        }
+                        VisualAction::Reindent(start, end) => {
+                            self.mode = Mode::Normal;
+                            self.visual_mode = None;
+                            self.reindent(start, end, &mut cursor);
                         }
+                        VisualAction::None => {}
                     }
-                    Mode::Command => {
-                        if let Some(result) = self.command_mode.handle_key(key) {
-                            match result {
-                                CommandResult::Execute(cmd) => {
-                                    match execute_command(&cmd, self.window_manager.get_active_buffer_mut()) {
-                                        Ok(action) => {
-                                            match action {
-                                                CommandAction::Quit => {
-                                                    if self.window_manager.get_active_buffer().is_modified() {
-                                                        self.message = Some("No write since last change (use :q! to override)".to_string());
-                                                    } else {
-                                                        self.quit = true;
-                                                    }
-                                                }
-                                                CommandAction::ForceQuit => {
-                                                    self.quit = true;
-                                                }
-                                                CommandAction::Edit(path) => {
-                                                    match Buffer::from_file(&path) {
-                                                        Ok(new_buffer) => {
-                                                            // Replace current buffer with new one
-                                                            let buffer_count = self.window_manager.get_buffers().len();
-                                                            self.window_manager.get_buffers_mut().push(new_buffer);
-                                                            let current_window = self.window_manager.get_active_window_mut();
-                                                            current_window.buffer_id = buffer_count;
-                                                            current_window.cursor_line = 0;
-                                                            current_window.cursor_col = 0;
-                                                            current_window.viewport_offset = 0;
-                                                        }
-                                                        Err(e) => {
-                                                            self.message = Some(format!("Error: {}", e));
-                                                        }
-                                                    }
-                                                }
-                                                CommandAction::SplitHorizontal(file_path) => {
-                                                    if let Err(e) = self.window_manager.split_horizontal(file_path.as_deref()) {
-                                                        self.message = Some(e);
-                                                    }
-                                                }
-                                                CommandAction::SplitVertical(file_path) => {
-                                                    if let Err(e) = self.window_manager.split_vertical(file_path.as_deref()) {
-                                                        self.message = Some(e);
-                                                    }
-                                                }
-                                                CommandAction::CloseWindow => {
-                                                    if let Err(e) = self.window_manager.close_window() {
-                                                        self.message = Some(e);
-                                                    }
-                                                }
-                                                CommandAction::CloseOtherWindows => {
-                                                    // TODO: Implement close other windows
-                                                    self.message = Some("Close other windows not yet implemented".to_string());
-                                                }
-                                                CommandAction::Error(msg) => {
-                                                    self.message = Some(msg);
-                                                }
-                                                CommandAction::None => {}
-                                            }
-                                        }
-                                        Err(e) => {
-                                            self.message = Some(format!("Error: {}", e));
-                                        }
-                                    }
-                                    self.command_mode.clear();
-                                    self.mode = Mode::Normal;
-                                }
-                                CommandResult::Cancel => {
-                                    self.command_mode.clear();
-                                    self.mode = Mode::Normal;
-                                }
-                            }
+                    self.window_manager.set_active_cursor(cursor);
+                }
+                self.finish_one_shot_normal();
+            }
+            Mode::Command => {
+                let buffer = self.window_manager.get_active_buffer();
+                let filetype = buffer.syntax_name().map(str::to_lowercase);
+                let mut abbreviations = vec![buffer.abbreviations()];
+                abbreviations.extend(abbreviation_layers(&self.abbreviations, &self.filetype_abbreviations, filetype.as_deref()));
+                let cursor = self.window_manager.get_active_cursor();
+                let line = buffer.get_line(cursor.line).map_or("", String::as_str);
+                let sources = CompletionSources {
+                    buffers: self.window_manager.get_buffers().iter().map(Buffer::name).collect(),
+                    values: OPTIONS.iter().map(|def| (def.name, self.option(def).to_string())).collect(),
+                };
+                let context = CommandLineContext {
+                    registers: &self.registers,
+                    abbreviations: &abbreviations,
+                    history: &self.histories.command,
+                    word: buffer.word_at(cursor.line, cursor.col).map(|(start, end)| &line[start..end]),
+                    commands: &self.commands,
+                    sources: &sources,
+                };
+                if let Some(result) = self.command_mode.handle_key(key, &context) {
+                    match result {
+                        CommandResult::Execute(cmd) => {
+                            self.histories.command.add(&cmd);
+                            self.command_mode.clear();
+                            self.mode = Mode::Normal;
+                            self.execute_command_line(&cmd)?;
+                            self.finish_one_shot_normal();
+                        }
+                        CommandResult::Cancel => {
+                            self.command_mode.clear();
+                            self.mode = Mode::Normal;
+                            self.finish_one_shot_normal();
                         }
                     }
                 }
             }
         }
-
-        self.renderer.exit()?;
         Ok(())
     }
 
+    // The key after Ctrl-w
+    fn window_command(&mut self, key: KeyEvent) {
+        let cmd = match self.normal_mode.handle_window_command(key) {
+            Some(cmd) => cmd,
+            None => return,
+        };
+        match cmd.as_str() {
+            "next_window" => self.window_manager.next_window(),
+            "prev_window" => self.window_manager.prev_window(),
+            "split_horizontal" => {
+                if let Err(e) = self.window_manager.split_horizontal(None) {
+                    self.message = Some(e);
+                }
+            }
+            "split_vertical" => {
+                if let Err(e) = self.window_manager.split_vertical(None) {
+                    self.message = Some(e);
+                }
+            }
+            "close_window" => {
+                if let Err(e) = self.window_manager.close_window() {
+                    self.message = Some(e);
+                }
+            }
+            "close_other_windows" => {
+                self.message = Some("Close other windows not yet implemented".to_string());
+            }
+            "increase_height" | "decrease_height" | "increase_width" | "decrease_width" | "equal_size" => {
+                self.message = Some("Window resizing not yet implemented".to_string());
+            }
+            _ if cmd.starts_with("navigate_") => {
+                let direction = cmd.chars().last().unwrap_or('h');
+                self.window_manager.navigate_to_window(direction);
+            }
+            _ => {}
+        }
+    }
+
+    // Pairs to close in the current buffer, none when auto-pairing is off
+    fn auto_pairs(&self, filetype: Option<&str>) -> AutoPairs {
+        if !self.window_manager.get_active_buffer().options().bool("autopairs") {
+            return AutoPairs::default();
+        }
+        match filetype.and_then(|name| self.filetype_pairs.get(name)) {
+            Some(pairs) => pairs.clone(),
+            None => AutoPairs::builtin(filetype),
+        }
+    }
+
+    // = operator: reindent the lines and put the cursor on the first one's text
+    fn reindent(&mut self, start: usize, end: usize, cursor: &mut Cursor) {
+        let buffer = self.window_manager.get_active_buffer_mut();
+        indent::reindent(buffer, start, end);
+        cursor.line = start;
+        cursor.col = buffer.get_line(start).map_or(0, |line| indent::indent_len(line));
+        cursor.remember_col(buffer);
+        if end > start {
+            self.message = Some(format!("{} lines indented", end - start + 1));
+        }
+    }
+
+    fn abbreviate(&mut self, command: AbbrevCommand) {
+        match command {
+            AbbrevCommand::Define { modes, buffer_local, lhs, rhs } => {
+                self.abbreviations_mut(buffer_local).define(modes, &lhs, &rhs);
+            }
+            AbbrevCommand::Remove { modes, buffer_local, lhs } => {
+                if !self.abbreviations_mut(buffer_local).remove(modes, &lhs) {
+                    self.message = Some(format!("No such abbreviation: {}", lhs));
+                }
+            }
+            AbbrevCommand::Clear { modes, buffer_local } => {
+                self.abbreviations_mut(buffer_local).clear(modes);
+            }
+            AbbrevCommand::List { modes } => {
+                let mut entries = self.window_manager.get_active_buffer().abbreviations().list(modes);
+                for entry in entries.iter_mut() {
+                    entry.insert_str(1, " @");
+                }
+                entries.extend(self.abbreviations.list(modes));
+                self.message = Some(if entries.is_empty() {
+                    "No abbreviation found".to_string()
+                } else {
+                    entries.join(" | ")
+                });
+            }
+        }
+    }
+
+    fn abbreviations_mut(&mut self, buffer_local: bool) -> &mut Abbreviations {
+        if buffer_local {
+            self.window_manager.get_active_buffer_mut().abbreviations_mut()
+        } else {
+            &mut self.abbreviations
+        }
+    }
+
+    // After Ctrl-o, return to insert mode once the normal command is complete
+    fn finish_one_shot_normal(&mut self) {
+        if !self.insert_one_shot || self.normal_mode.is_pending() {
+            return;
+        }
+        match self.mode {
+            Mode::Normal => {
+                self.insert_one_shot = false;
+                self.mode = Mode::Insert;
+            }
+            Mode::Insert => self.insert_one_shot = false,
+            _ => {}
+        }
+    }
+
+    fn add_cursor_vertical(&mut self, primary: &Cursor, below: bool) {
+        let buffer = self.window_manager.get_active_buffer();
+        let mut cursors = CursorSet::new(*primary, self.window_manager.get_extra_cursors());
+        let lines = cursors.iter().map(|c| c.line);
+        let target = if below {
+            lines.max().map(|l| l + 1).filter(|&l| l < buffer.line_count())
+        } else {
+            lines.min().and_then(|l| l.checked_sub(1))
+        };
+
+        if let Some(line) = target {
+            // In the primary cursor's screen column, whatever tabs or wide characters come before it
+            let text = buffer.get_line(line).map_or("", |l| l.as_str());
+            let col = indent::byte_at_display_col(text, primary.desired_col, buffer.indent_settings().tab_stop);
+            cursors.add(Cursor { line, col, desired_col: primary.desired_col });
+            self.message = Some(format!("{} cursors", cursors.len()));
+            self.window_manager.set_extra_cursors(cursors.extras());
+        }
+    }
+
+    fn add_cursor_at_next_match(&mut self, primary: &Cursor) {
+        let buffer = self.window_manager.get_active_buffer();
+        let (word_start, word_end) = match buffer.word_at(primary.line, primary.col) {
+            Some(range) => range,
+            None => {
+                self.message = Some("No word under cursor".to_string());
+                return;
+            }
+        };
+        let word = buffer.get_line(primary.line).map_or("", |v| v)[word_start..word_end].to_string();
+        let offset = primary.col.saturating_sub(word_start);
+        let is_keyword = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+        // Continue from the most recently added cursor, wrapping around the buffer
+        let mut cursors = CursorSet::new(*primary, self.window_manager.get_extra_cursors());
+        let from = self.window_manager.get_extra_cursors().last().copied().unwrap_or(*primary);
+        let from_start = from.col.saturating_sub(offset);
+        let line_count = buffer.line_count();
+
+        for step in 0..=line_count {
+            let line_idx = (from.line + step) % line_count;
+            let text = buffer.get_line(line_idx).map_or("", |v| v);
+            for (start, _) in text.match_indices(&word) {
+                if (step == 0 && start <= from_start) || (step == line_count && start > from_start) {
+                    continue;
+                }
+                let end = start + word.len();
+                if is_keyword(text[..start].chars().next_back()) || is_keyword(text[end..].chars().next()) {
+                    continue;
+                }
+                let col = start + offset;
+                if cursors.add(Cursor::at(line_idx, col, buffer)) {
+                    self.message = Some(format!("{} cursors", cursors.len()));
+                    self.window_manager.set_extra_cursors(cursors.extras());
+                    return;
+                }
+            }
+        }
+        self.message = Some(format!("No more matches for '{}'", word));
+    }
+
     fn update_viewport(&mut self) {
         let terminal_height = self.renderer.height().saturating_sub(2); // Leave room for status line
         let cursor = self.window_manager.get_active_cursor();
@@ -324,3 +691,16 @@ impl Editor {
     }
 }
 
+// Abbreviations that apply besides a buffer's own: its filetype's, then the global ones
+fn abbreviation_layers<'a>(
+    global: &'a Abbreviations,
+    by_filetype: &'a HashMap<String, Abbreviations>,
+    filetype: Option<&str>,
+) -> Vec<&'a Abbreviations> {
+    let mut layers = Vec::with_capacity(2);
+    if let Some(abbreviations) = filetype.and_then(|name| by_filetype.get(name)) {
+        layers.push(abbreviations);
+    }
+    layers.push(global);
+    layers
+}
@@ -0,0 +1,221 @@
+use super::Editor;
+use crate::buffer::Buffer;
+use crate::config::{editorconfig, Config};
+use crate::indent;
+use crate::options::{self, OptionDef, OptionValue, Scope, SetArg, Settings, OPTIONS};
+
+// Buffer options for filetypes from the config's [filetype.<name>] sections and
+// then the ftplugin files, so those win. Errors go to `message`.
+pub(super) fn filetype_options(config: &Config, message: &mut Option<String>) -> Vec<(String, Settings)> {
+    let mut sections: Vec<_> = config.filetype.iter().collect();
+    sections.sort_by(|a, b| a.0.cmp(b.0));
+    let mut filetype_options = Vec::new();
+    for (filetype, table) in sections {
+        match options::parse_table(table) {
+            Ok(settings) => filetype_options.push((filetype.clone(), settings)),
+            Err(e) => *message = Some(format!("Config error in filetype.{}: {}", filetype, e)),
+        }
+    }
+    match Config::load_ftplugins() {
+        Ok(ftplugins) => {
+            for (filetype, table) in ftplugins {
+                match options::parse_table(&table) {
+                    Ok(settings) => filetype_options.push((filetype, settings)),
+                    Err(e) => *message = Some(format!("Error in ftplugin/{}.toml: {}", filetype, e)),
+                }
+            }
+        }
+        Err(e) => *message = Some(e),
+    }
+    filetype_options
+}
+
+// The settings of the modelines in a buffer, and the first error in them
+fn modeline_settings(buffer: &Buffer, modelines: usize) -> (Settings, Option<String>) {
+    let lines: Vec<&str> = (0..buffer.line_count()).filter_map(|i| buffer.get_line(i)).map(String::as_str).collect();
+    options::modeline_settings(&lines, modelines)
+}
+
+impl Editor {
+    // Give a buffer that was just opened, or changed filetype, its options: the
+    // global values, then its filetype's settings, then the indentation its
+    // text already uses, then any .editorconfig, then its own modelines
+    pub(super) fn setup_buffer(&mut self, buffer_id: usize) {
+        // A modeline's filetype switches the syntax before anything else, so
+        // the options that follow are that filetype's. The last one wins.
+        let modelines = self.modelines();
+        if let Some(buffer) = self.window_manager.get_buffers_mut().get_mut(buffer_id) {
+            let (settings, _) = modeline_settings(buffer, modelines);
+            if let Some((_, value)) = settings.iter().rev().find(|(def, _)| def.name == "filetype") {
+                let name = value.to_string();
+                match buffer.find_syntax(&name) {
+                    Some(syntax) if buffer.syntax_name() != Some(syntax.as_str()) => buffer.set_syntax(Some(syntax)),
+                    Some(_) => {}
+                    None => self.message = Some(format!("Unknown filetype: {}", name)),
+                }
+            }
+        }
+        self.apply_buffer_options(buffer_id);
+    }
+
+    // Everything but a modeline's filetype, which setup_buffer has seen to
+    // already and :set filetype overrides
+    fn apply_buffer_options(&mut self, buffer_id: usize) {
+        let detect_indent = self.options.bool("detectindent");
+        let modelines = self.modelines();
+        let color_scheme = self.options.string("colorscheme");
+        let buffer = match self.window_manager.get_buffers_mut().get_mut(buffer_id) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        *buffer.options_mut() = self.options.local(Scope::Buffer);
+        if let Some(on_disk) = buffer.on_disk() {
+            buffer.options_mut().set("fileformat", OptionValue::String(on_disk.format.to_string()));
+            buffer.options_mut().set("fileencoding", OptionValue::String(on_disk.encoding.to_string()));
+        }
+        let _ = buffer.set_color_scheme(&color_scheme);
+
+        if let Some(syntax) = buffer.syntax_name().map(str::to_string) {
+            buffer.options_mut().set("filetype", OptionValue::String(syntax.to_lowercase()));
+            for (filetype, settings) in &self.filetype_options {
+                if buffer.find_syntax(filetype).as_ref() == Some(&syntax) {
+                    for (def, value) in settings {
+                        buffer.options_mut().set(def.name, value.clone());
+                    }
+                }
+            }
+        }
+
+        if detect_indent {
+            if let Some(indentation) = indent::detect((0..buffer.line_count()).filter_map(|i| buffer.get_line(i))) {
+                let mut settings = buffer.indent_settings();
+                settings.adapt_to(indentation);
+                buffer.set_indent_settings(settings);
+            }
+        }
+
+        if let Some(path) = buffer.file_path() {
+            for (def, value) in editorconfig::settings_for(path) {
+                buffer.options_mut().set(def.name, value);
+            }
+        }
+
+        let (settings, error) = modeline_settings(buffer, modelines);
+        if let Some(e) = error {
+            self.message = Some(e);
+        }
+        for (def, value) in settings {
+            if def.name != "filetype" {
+                buffer.options_mut().set(def.name, value);
+            }
+        }
+    }
+
+    // How many lines at each end may hold a modeline
+    fn modelines(&self) -> usize {
+        if self.options.bool("modeline") {
+            self.options.number("modelines")
+        } else {
+            0
+        }
+    }
+
+    // :set and :setlocal. Values asked for are shown in the message line.
+    pub(super) fn set_options(&mut self, args: &str, local: bool) {
+        let args = match SetArg::parse_all(args) {
+            Ok(args) => args,
+            Err(msg) => {
+                self.message = Some(msg);
+                return;
+            }
+        };
+
+        let mut shown = Vec::new();
+        if args.is_empty() {
+            // Like vim, :set alone lists the options that differ from their built-in value
+            shown.extend(
+                OPTIONS
+                    .iter()
+                    .filter(|def| self.option(def) != def.initial.value())
+                    .map(|def| def.show(&self.option(def))),
+            );
+        }
+        for arg in args {
+            let result = match arg {
+                SetArg::All => {
+                    shown.extend(OPTIONS.iter().map(|def| def.show(&self.option(def))));
+                    Ok(())
+                }
+                SetArg::Show(def) => {
+                    shown.push(def.show(&self.option(def)));
+                    Ok(())
+                }
+                SetArg::Set(def, value) => self.set_option(def, value, local),
+                SetArg::Toggle(def) => {
+                    let value = OptionValue::Bool(self.option(def) != OptionValue::Bool(true));
+                    self.set_option(def, value, local)
+                }
+            };
+            if let Err(msg) = result {
+                self.message = Some(msg);
+                return;
+            }
+        }
+        self.message = (!shown.is_empty()).then(|| shown.join("  "));
+    }
+
+    // The value in effect for the current buffer and window
+    pub(super) fn option(&self, def: &'static OptionDef) -> OptionValue {
+        match def.scope {
+            Scope::Global => self.options.get(def),
+            Scope::Buffer => self.window_manager.get_active_buffer().options().get(def),
+            Scope::Window => self.window_manager.get_active_window().options.get(def),
+        }
+    }
+
+    // :set changes the local value and the global one that new buffers and
+    // windows start from, :setlocal only the local value
+    fn set_option(&mut self, def: &'static OptionDef, value: OptionValue, local: bool) -> Result<(), String> {
+        if def.name == "filetype" {
+            return self.set_filetype(&value.to_string());
+        }
+        if def.name == "colorscheme" {
+            let name = value.to_string();
+            for buffer in self.window_manager.get_buffers_mut() {
+                buffer.set_color_scheme(&name)?;
+            }
+        }
+        match def.scope {
+            Scope::Global => {}
+            Scope::Buffer => self.window_manager.get_active_buffer_mut().options_mut().set(def.name, value.clone()),
+            Scope::Window => self.window_manager.get_active_window_mut().options.set(def.name, value.clone()),
+        }
+        if def.scope == Scope::Global || !local {
+            self.options.set(def.name, value);
+        }
+        if def.name == "hlsearch" {
+            self.hlsearch_hidden = false;
+        }
+        self.sync_search_options();
+        Ok(())
+    }
+
+    // Searches, :s and :g read 'ignorecase' and 'smartcase' from the search state
+    pub(super) fn sync_search_options(&mut self) {
+        self.search_state.ignore_case = self.options.bool("ignorecase");
+        self.search_state.smart_case = self.options.bool("smartcase");
+    }
+
+    // :set filetype=python switches the syntax and sets the buffer's options up again
+    fn set_filetype(&mut self, name: &str) -> Result<(), String> {
+        let buffer_id = self.window_manager.get_active_window().buffer_id;
+        let buffer = self.window_manager.get_active_buffer_mut();
+        let syntax = match name {
+            "" => None,
+            name => Some(buffer.find_syntax(name).ok_or_else(|| format!("Unknown filetype: {}", name))?),
+        };
+        buffer.set_syntax(syntax);
+        self.apply_buffer_options(buffer_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use super::Editor;
+use crate::mode::Mode;
+use crossterm::event::{KeyCode, KeyEvent};
+use std::io;
+
+// Keys of the command being typed, from the first key in normal mode until the
+// editor is back in normal mode. Everything it changes is one undo step.
+pub(super) struct PendingChange {
+    buffer_id: usize,
+    changedtick: u64,
+    keys: Vec<KeyEvent>,
+}
+
+impl Editor {
+    pub(super) fn handle_key(&mut self, key: KeyEvent) -> io::Result<()> {
+        if self.pending_change.is_none() && self.at_command_start() {
+            let buffer_id = self.window_manager.get_active_window().buffer_id;
+            let buffer = self.window_manager.get_active_buffer_mut();
+            buffer.begin_undo_group();
+            self.pending_change = Some(PendingChange {
+                buffer_id,
+                changedtick: buffer.changedtick(),
+                keys: Vec::new(),
+            });
+        }
+        if let Some(change) = self.pending_change.as_mut() {
+            change.keys.push(key);
+        }
+
+        self.process_key(key)?;
+
+        if self.at_command_start() {
+            if let Some(change) = self.pending_change.take() {
+                self.finish_change(change);
+            }
+        }
+        Ok(())
+    }
+
+    // `.`: run the keys of the last change again at the cursor
+    pub(super) fn repeat_last_change(&mut self) -> io::Result<()> {
+        if self.replaying {
+            return Ok(());
+        }
+        let keys = match self.last_change.clone() {
+            Some(keys) => keys,
+            None => return Ok(()),
+        };
+        self.replaying = true;
+        for key in keys {
+            self.process_key(key)?;
+        }
+        self.replaying = false;
+        Ok(())
+    }
+
+    pub(super) fn finish_change(&mut self, change: PendingChange) {
+        if let Some(buffer) = self.window_manager.get_buffers_mut().get_mut(change.buffer_id) {
+            buffer.end_undo_group();
+            let changed = buffer.changedtick() != change.changedtick;
+            let is_repeat = matches!(change.keys.as_slice(), [key] if key.code == KeyCode::Char('.'));
+            let is_ex_command = change.keys.first().is_some_and(|key| key.code == KeyCode::Char(':'));
+            if changed && !is_repeat && !is_ex_command {
+                self.last_change = Some(change.keys);
+            }
+        }
+    }
+
+    // Between commands: in normal mode with nothing half typed
+    pub(super) fn at_command_start(&self) -> bool {
+        self.mode == Mode::Normal
+            && !self.normal_mode.is_pending()
+            && !self.in_search
+            && !self.insert_one_shot
+            && !self.window_command
+            && self.substitute_confirm.is_none()
+    }
+}
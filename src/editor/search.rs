@@ -0,0 +1,246 @@
+use super::Editor;
+use crate::cursor::Cursor;
+use crate::mode::{Mode, VisualMode, VisualType};
+use crate::search::{self, Found, Match, Offset};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+
+// Matches of a pattern in a buffer, found again once the buffer, its text
+// or the pattern changes
+#[derive(Default)]
+pub(super) struct MatchCache {
+    key: Option<(usize, u64, String, bool)>, // Buffer, changedtick, pattern, ignoring case
+    matches: Vec<Match>,
+}
+
+impl Editor {
+    // / or ?: start typing a pattern, remembering the view to go back to
+    pub(super) fn start_search(&mut self, forward: bool) {
+        self.in_search = true;
+        self.search_input.clear();
+        self.search_state.forward = forward;
+        self.search_start = Some((self.window_manager.get_active_cursor(), self.window_manager.get_viewport_offset()));
+    }
+
+    // A key typed at the / or ? prompt
+    pub(super) fn search_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Esc => self.end_search(),
+            KeyCode::Backspace if self.search_input.is_empty() => self.end_search(),
+            KeyCode::Enter => {
+                let input = std::mem::take(&mut self.search_input);
+                self.end_search();
+                self.search(&input);
+            }
+            // Older and newer searches that start with what was typed
+            KeyCode::Up | KeyCode::Down => {
+                let recalled = match key.code {
+                    KeyCode::Up => self.search_recall.older(&self.histories.search, &self.search_input),
+                    _ => self.search_recall.newer(&self.histories.search),
+                };
+                if let Some(pattern) = recalled {
+                    self.search_input = pattern;
+                    self.incremental_search();
+                }
+            }
+            KeyCode::Backspace => {
+                self.search_recall.reset();
+                self.search_input.pop();
+                self.incremental_search();
+            }
+            KeyCode::Char(c) => {
+                self.search_recall.reset();
+                self.search_input.push(c);
+                self.incremental_search();
+            }
+            _ => {}
+        }
+    }
+
+    // Leave the prompt with the cursor and view where they were before it
+    fn end_search(&mut self) {
+        if let Some((cursor, offset)) = self.search_start.take() {
+            self.window_manager.set_active_cursor(cursor);
+            self.window_manager.set_viewport_offset(offset);
+        }
+        self.in_search = false;
+        self.search_input.clear();
+        self.search_recall.reset();
+        self.incsearch = None;
+    }
+
+    // incsearch: show where the pattern typed so far goes, from where the
+    // search started, without making it the last search
+    fn incremental_search(&mut self) {
+        let Some((cursor, offset)) = self.search_start else { return };
+        self.window_manager.set_active_cursor(cursor);
+        self.window_manager.set_viewport_offset(offset);
+        self.incsearch = None;
+        if !self.options.bool("incsearch") || self.search_input.is_empty() {
+            return;
+        }
+        let mut state = self.search_state.clone();
+        let buffer = self.window_manager.get_active_buffer();
+        if let Ok(found) = state.search(buffer, &self.search_input, state.forward, (cursor.line, cursor.col)) {
+            let (line, col) = found.cursor;
+            let cursor = Cursor::at(line, col, buffer);
+            self.window_manager.set_active_cursor(cursor);
+            self.incsearch = Some((state.query, found.range));
+        }
+    }
+
+    pub(super) fn search(&mut self, input: &str) {
+        let forward = self.search_state.forward;
+        let cursor = self.window_manager.get_active_cursor();
+        let buffer = self.window_manager.get_active_buffer();
+        self.histories.search.add(input);
+        let found = self.search_state.search(buffer, input, forward, (cursor.line, cursor.col));
+        self.go_to_match(found);
+    }
+
+    // Put the cursor where a search found a match, or report why it didn't
+    fn go_to_match(&mut self, found: Result<Found, String>) {
+        match found {
+            Ok(found) => {
+                let (line, col) = found.cursor;
+                let cursor = Cursor::at(line, col, self.window_manager.get_active_buffer());
+                self.window_manager.set_active_cursor(cursor);
+                self.hlsearch_hidden = false;
+                let prefix = if self.search_state.forward { '/' } else { '?' };
+                self.message = Some(format!("{}{}", prefix, self.search_state.query));
+            }
+            Err(msg) => self.message = Some(msg),
+        }
+    }
+
+    // n, and N with `reverse`, from the cursor
+    pub(super) fn jump_to_match(&mut self, reverse: bool) {
+        let cursor = self.window_manager.get_active_cursor();
+        let buffer = self.window_manager.get_active_buffer();
+        let found = self.search_state.next_match(buffer, (cursor.line, cursor.col), reverse);
+        self.go_to_match(found);
+    }
+
+    // * and # search for the keyword under the cursor as a whole word, g* and
+    // g# for it anywhere
+    pub(super) fn search_word(&mut self, forward: bool, whole_word: bool) {
+        let mut cursor = self.window_manager.get_active_cursor();
+        let buffer = self.window_manager.get_active_buffer();
+        let Some((start, end)) = buffer.word_at(cursor.line, cursor.col) else {
+            self.message = Some("No string under cursor".to_string());
+            return;
+        };
+        let word = &buffer.get_line(cursor.line).map_or("", String::as_str)[start..end];
+        let pattern = match whole_word {
+            true => format!("\\<{}\\>", word),
+            false => word.to_string(),
+        };
+        self.search_state.set_pattern(&pattern, forward, Offset::None);
+        self.histories.search.add(&pattern);
+        // From the start of the word, so # doesn't find the word itself
+        cursor.col = start;
+        let found = self.search_state.next_match(buffer, (cursor.line, cursor.col), false);
+        self.go_to_match(found);
+    }
+
+    // gn and gN: select the match under the cursor or the next (previous)
+    // one, and apply `operator` to the selection if there is one
+    pub(super) fn select_match(&mut self, forward: bool, operator: Option<char>) -> io::Result<()> {
+        let cursor = self.window_manager.get_active_cursor();
+        let buffer = self.window_manager.get_active_buffer();
+        let found = match self.search_state.match_object(buffer, (cursor.line, cursor.col), forward) {
+            Ok(found) => found,
+            Err(msg) => {
+                self.message = Some(msg);
+                return Ok(());
+            }
+        };
+        let (line, col) = found.start;
+        let (last_line, last_col) = found.last(buffer);
+        // gN leaves the cursor at the start of the match
+        let (anchor, end) = match forward {
+            true => (Cursor::at(line, col, buffer), Cursor::at(last_line, last_col, buffer)),
+            false => (Cursor::at(last_line, last_col, buffer), Cursor::at(line, col, buffer)),
+        };
+        self.hlsearch_hidden = false;
+        self.mode = Mode::Visual(VisualType::Character);
+        self.visual_mode = Some(VisualMode::new(VisualType::Character, &anchor));
+        self.window_manager.set_active_cursor(end);
+        match operator {
+            Some(operator) => self.process_key(KeyEvent::new(KeyCode::Char(operator), KeyModifiers::NONE)),
+            None => Ok(()),
+        }
+    }
+
+    // Every match of `pattern` in the active buffer
+    fn search_matches(&mut self, pattern: &str) -> &[Match] {
+        let buffer_id = self.window_manager.get_active_window().buffer_id;
+        let buffer = self.window_manager.get_active_buffer();
+        let key = (buffer_id, buffer.changedtick(), pattern.to_string(), self.search_state.ignores_case(pattern));
+        if self.match_cache.key.as_ref() != Some(&key) {
+            self.match_cache.matches = match self.search_state.regex(pattern) {
+                Ok(regex) => search::find_matches(buffer, &regex),
+                Err(_) => Vec::new(),
+            };
+            self.match_cache.key = Some(key);
+        }
+        &self.match_cache.matches
+    }
+
+    // Whether the last search is still shown, until :nohlsearch
+    fn last_search_shown(&self) -> bool {
+        self.search_state.is_active() && !self.hlsearch_hidden && !self.in_search
+    }
+
+    // What to highlight on screen, as (line, start, end) for each line a
+    // match covers: the pattern being typed with incsearch, or with hlsearch
+    // every match of the last search
+    pub(super) fn search_highlights(&mut self) -> Vec<(usize, usize, usize)> {
+        let hlsearch = self.options.bool("hlsearch");
+        let matches: Vec<Match> = if let Some((pattern, found)) = self.incsearch.clone() {
+            match hlsearch {
+                true => self.search_matches(&pattern).to_vec(),
+                false => vec![found],
+            }
+        } else if hlsearch && self.last_search_shown() {
+            let pattern = self.search_state.query.clone();
+            self.search_matches(&pattern).to_vec()
+        } else {
+            Vec::new()
+        };
+
+        let top = self.window_manager.get_viewport_offset();
+        let bottom = top + self.renderer.height();
+        let buffer = self.window_manager.get_active_buffer();
+        let line_len = |line: usize| buffer.get_line(line).map_or(0, |text| text.len());
+        let first = matches.partition_point(|m| m.end.0 < top);
+        let mut highlights = Vec::new();
+        for m in matches[first..].iter().take_while(|m| m.start.0 < bottom) {
+            if m.start.0 == m.end.0 {
+                highlights.push((m.start.0, m.start.1, m.end.1));
+                continue;
+            }
+            // A line break in the match is drawn as a space after the line
+            highlights.push((m.start.0, m.start.1, line_len(m.start.0) + 1));
+            highlights.extend((m.start.0 + 1..m.end.0).map(|line| (line, 0, line_len(line) + 1)));
+            if m.end.1 > 0 {
+                highlights.push((m.end.0, 0, m.end.1));
+            }
+        }
+        highlights.retain(|&(line, _, _)| line >= top && line < bottom);
+        highlights
+    }
+
+    // The "[3/17]" in the status line: which match the cursor is on (or
+    // after) and how many there are
+    pub(super) fn search_count(&mut self) -> Option<(usize, usize)> {
+        if !self.last_search_shown() {
+            return None;
+        }
+        let cursor = self.window_manager.get_active_cursor();
+        let pattern = self.search_state.query.clone();
+        let matches = self.search_matches(&pattern);
+        let current = matches.partition_point(|m| m.start <= (cursor.line, cursor.col));
+        (!matches.is_empty()).then_some((current, matches.len()))
+    }
+}
@@ -0,0 +1,76 @@
+// :help shows the README, with tags taken from its headings and from the
+// keys and commands at the start of its list items and table rows
+use std::sync::OnceLock;
+
+pub const TEXT: &str = include_str!("../../README.md");
+
+// Tags of the keys in these sections get a prefix, as in vim, so that
+// `Esc` in normal mode and `i_Esc` in insert mode are both found
+const PREFIXES: &[(&str, &str)] = &[
+    ("Insert Mode", "i_"),
+    ("Visual Mode", "v_"),
+    ("Visual Block Mode", "v_"),
+    ("Command-line Editing", "c_"),
+];
+
+pub fn lines() -> Vec<String> {
+    TEXT.lines().map(str::to_string).collect()
+}
+
+// Every tag and the line it is on, in the order they appear. A tag that
+// comes up again later is kept only where it is first.
+pub fn tags() -> &'static [(String, usize)] {
+    static TAGS: OnceLock<Vec<(String, usize)>> = OnceLock::new();
+    TAGS.get_or_init(|| {
+        let mut tags: Vec<(String, usize)> = Vec::new();
+        let mut prefix = "";
+        let mut in_code = false; // Inside a ``` block, where # starts a comment
+        for (line, text) in TEXT.lines().enumerate() {
+            if text.starts_with("```") {
+                in_code = !in_code;
+            }
+            let tag = if in_code {
+                None
+            } else if text.starts_with('#') {
+                let heading = text.trim_start_matches('#').trim();
+                prefix = PREFIXES.iter().find(|&&(section, _)| section == heading).map_or("", |&(_, prefix)| prefix);
+                Some(heading.to_string())
+            } else {
+                let item = text.strip_prefix("- ").or_else(|| text.strip_prefix("| ")).unwrap_or(text);
+                item.strip_prefix('`')
+                    .and_then(|item| item.split_once('`'))
+                    .filter(|(span, _)| !span.is_empty())
+                    .map(|(span, _)| command_tag(span).unwrap_or_else(|| format!("{}{}", prefix, span)))
+            };
+            if let Some(tag) = tag.filter(|tag| !tag.is_empty() && !tags.iter().any(|(old, _)| old == tag)) {
+                tags.push((tag, line));
+            }
+        }
+        tags
+    })
+}
+
+// `:[range]s/{pattern}/...` and `:set {option}` are tagged :s and :set
+fn command_tag(span: &str) -> Option<String> {
+    let rest = span.strip_prefix(':')?;
+    let rest = rest.strip_prefix("[range]").unwrap_or(rest);
+    let len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+        Some(len) => len,
+        None => rest.len(),
+    };
+    Some(format!(":{}", &rest[..len]))
+}
+
+// The line of the tag for `subject`: an exact match, then one ignoring
+// case, then the first tag starting with it
+pub fn find(subject: &str) -> Result<usize, String> {
+    let tags = tags();
+    let lower = subject.to_lowercase();
+    tags.iter()
+        .find(|(tag, _)| tag == subject)
+        .or_else(|| tags.iter().find(|(tag, _)| tag.to_lowercase() == lower))
+        .or_else(|| tags.iter().find(|(tag, _)| tag.to_lowercase().starts_with(&lower)))
+        .map(|&(_, line)| line)
+        .ok_or_else(|| format!("Sorry, no help for {}", subject))
+}
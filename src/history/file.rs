@@ -0,0 +1,230 @@
+use super::{Entry, Histories, History, HISTORY_SIZE, KINDS};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+// How long a lock file may sit before it is taken to be left by an instance that died
+const STALE_LOCK: Duration = Duration::from_secs(10);
+
+// $XDG_STATE_HOME/avim/history, falling back to ~/.local/state/avim/history
+fn path() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_home.join("avim").join("history"))
+}
+
+impl Histories {
+    // The histories saved by earlier sessions
+    pub fn load() -> Result<Self, String> {
+        let mut histories = Self::default();
+        if let Some(path) = path() {
+            histories.read(&path)?;
+        }
+        Ok(histories)
+    }
+
+    // Write the histories to the history file, merged with what other
+    // instances have written to it since it was loaded. The most recently
+    // used entries are kept.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let error = |e: io::Error| format!("Error writing {}: {}", path.display(), e);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(error)?;
+        }
+        let _lock = Lock::acquire(&path.with_extension("lock")).map_err(error)?;
+
+        let mut merged = Self::default();
+        merged.read(&path)?;
+        for &(kind, _) in KINDS {
+            merged.get_mut(kind).merge(self.get(kind));
+        }
+
+        let mut text = String::from("# avim history: lines typed at the : / and input prompts\n");
+        for &(kind, marker) in KINDS {
+            for entry in &merged.get(kind).entries {
+                text.push_str(&format!("{}{} {}\n", marker, entry.time, escape(&entry.text)));
+            }
+        }
+        // Written next to the file and renamed over it, so it is never half written
+        let temp = path.with_extension(format!("tmp{}", process::id()));
+        fs::write(&temp, text).and_then(|_| fs::rename(&temp, &path)).map_err(error)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+        };
+        for line in text.lines() {
+            let mut chars = line.chars();
+            let Some(kind) = chars.next().and_then(|marker| KINDS.iter().find(|&&(_, m)| m == marker)) else { continue };
+            // Lines that don't parse are skipped, like comments
+            let Some((time, text)) = chars.as_str().split_once(' ') else { continue };
+            if let Ok(time) = time.parse() {
+                self.get_mut(kind.0).push(Entry { text: unescape(text), time });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl History {
+    // Take in the entries of another history, keeping the latest use of each
+    // line and the most recently used lines
+    fn merge(&mut self, other: &History) {
+        for entry in &other.entries {
+            match self.entries.iter_mut().find(|old| old.text == entry.text) {
+                Some(old) => old.time = old.time.max(entry.time),
+                None => self.entries.push(entry.clone()),
+            }
+        }
+        // Stable, so lines used in the same second keep their order
+        self.entries.sort_by_key(|entry| entry.time);
+        let extra = self.entries.len().saturating_sub(HISTORY_SIZE);
+        self.entries.drain(..extra);
+    }
+}
+
+// Held while the history file is rewritten, and removed when dropped
+struct Lock(PathBuf);
+
+impl Lock {
+    fn acquire(path: &Path) -> io::Result<Self> {
+        for _ in 0..100 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    let _ = write!(file, "{}", process::id());
+                    return Ok(Self(path.to_path_buf()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(path).and_then(|meta| meta.modified()).ok().and_then(|time| time.elapsed().ok());
+                    if age.is_some_and(|age| age > STALE_LOCK) {
+                        let _ = fs::remove_file(path);
+                    } else {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::WouldBlock, "the history file is locked by another instance"))
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// One entry per line: a backslash and line breaks are written as \\ and \n
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some(next) => result.push(next),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[(&str, u64)]) -> History {
+        let mut history = History::default();
+        for &(text, time) in entries {
+            history.push(Entry { text: text.to_string(), time });
+        }
+        history
+    }
+
+    fn texts(history: &History) -> Vec<&str> {
+        history.iter().collect()
+    }
+
+    // A fresh directory under the system's temporary one
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("avim-history-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn merge_keeps_the_latest_use() {
+        let mut ours = history(&[("w", 10), ("e foo", 20), ("q", 30)]);
+        let theirs = history(&[("e foo", 40), ("make", 25), ("w", 5)]);
+        ours.merge(&theirs);
+        assert_eq!(texts(&ours), vec!["w", "make", "q", "e foo"]);
+    }
+
+    #[test]
+    fn merge_keeps_the_newest_entries() {
+        let old: Vec<(String, u64)> = (0..HISTORY_SIZE as u64).map(|i| (format!("old {}", i), i)).collect();
+        let old: Vec<(&str, u64)> = old.iter().map(|(text, time)| (text.as_str(), *time)).collect();
+        let mut ours = history(&old);
+        ours.merge(&history(&[("new", 1000)]));
+        assert_eq!(ours.len(), HISTORY_SIZE);
+        assert_eq!(ours.get(0), Some("old 1"));
+        assert_eq!(ours.get(HISTORY_SIZE - 1), Some("new"));
+    }
+
+    #[test]
+    fn merge_two_files() {
+        let dir = temp_dir("merge");
+        let first = dir.join("first");
+        let second = dir.join("second");
+        fs::write(&first, "# comment\n:10 w\n:30 s/a/b/\n/15 foo\\\\bar\n@12 yes\nbroken line\n:x 1\n").unwrap();
+        fs::write(&second, ":20 e file\n:40 w\n/5 foo\\\\bar\n/25 two\\nlines\n@8 name\n").unwrap();
+
+        let mut merged = Histories::default();
+        merged.read(&first).unwrap();
+        let mut other = Histories::default();
+        other.read(&second).unwrap();
+        for &(kind, _) in KINDS {
+            merged.get_mut(kind).merge(other.get(kind));
+        }
+        assert_eq!(texts(&merged.command), vec!["e file", "s/a/b/", "w"]);
+        assert_eq!(texts(&merged.search), vec!["foo\\bar", "two\nlines"]);
+        assert_eq!(texts(&merged.input), vec!["name", "yes"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let mut histories = Histories::default();
+        assert!(histories.read(Path::new("/nonexistent/avim/history")).is_ok());
+        assert!(histories.command.is_empty());
+    }
+
+    #[test]
+    fn escapes() {
+        for text in ["plain", "a\\b", "two\nlines", "\\n", "ends with \\"] {
+            assert_eq!(unescape(&escape(text)), text);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Lines typed at the : and / prompts and at input prompts, kept across sessions
+mod file;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Most entries a history keeps
+pub const HISTORY_SIZE: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    text: String,
+    time: u64, // Seconds since the epoch it was last used, to merge histories by
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: Vec<Entry>, // Oldest first, no two the same
+}
+
+impl History {
+    // Add a line, or move it to the end if it's there already
+    pub fn add(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        self.push(Entry { text: text.to_string(), time });
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.retain(|old| old.text != entry.text);
+        self.entries.push(entry);
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.text.as_str())
+    }
+
+    // The entries, oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.text.as_str())
+    }
+}
+
+// Which prompt a history is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKind {
+    Command,
+    Search,
+    Input,
+}
+
+// The character each history's lines start with in the history file, as in viminfo
+const KINDS: &[(HistoryKind, char)] = &[(HistoryKind::Command, ':'), (HistoryKind::Search, '/'), (HistoryKind::Input, '@')];
+
+#[derive(Debug, Clone, Default)]
+pub struct Histories {
+    pub command: History,
+    pub search: History,
+    pub input: History,
+}
+
+impl Histories {
+    pub fn get(&self, kind: HistoryKind) -> &History {
+        match kind {
+            HistoryKind::Command => &self.command,
+            HistoryKind::Search => &self.search,
+            HistoryKind::Input => &self.input,
+        }
+    }
+
+    pub fn get_mut(&mut self, kind: HistoryKind) -> &mut History {
+        match kind {
+            HistoryKind::Command => &mut self.command,
+            HistoryKind::Search => &mut self.search,
+            HistoryKind::Input => &mut self.input,
+        }
+    }
+}
+
+// Up and Down at a prompt: older and newer entries that start with what was
+// typed before the first Up
+#[derive(Debug, Clone, Default)]
+pub struct Recall {
+    typed: Option<String>, // None until Up is pressed
+    index: usize,          // Entry shown, or the history's length while the typed text is
+}
+
+impl Recall {
+    // The next older entry with the prefix, None when there is no other
+    pub fn older(&mut self, history: &History, current: &str) -> Option<String> {
+        if self.typed.is_none() {
+            self.typed = Some(current.to_string());
+            self.index = history.len();
+        }
+        let typed = self.typed.as_deref().unwrap_or("");
+        let index = (0..self.index.min(history.len())).rev().find(|&i| history.get(i).is_some_and(|text| text.starts_with(typed)))?;
+        self.index = index;
+        history.get(index).map(str::to_string)
+    }
+
+    // The next newer entry with the prefix, or what was typed after the newest
+    pub fn newer(&mut self, history: &History) -> Option<String> {
+        let typed = self.typed.as_deref()?;
+        let found = (self.index + 1..history.len()).find(|&i| history.get(i).is_some_and(|text| text.starts_with(typed)));
+        match found {
+            Some(index) => {
+                self.index = index;
+                history.get(index).map(str::to_string)
+            }
+            None => self.typed.take(),
+        }
+    }
+
+    // Forget the browsing, once something else is typed
+    pub fn reset(&mut self) {
+        self.typed = None;
+        self.index = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::default();
+        lines.iter().for_each(|line| history.add(line));
+        history
+    }
+
+    #[test]
+    fn add_moves_a_line_to_the_end() {
+        let history = history(&["w", "q", "", "w"]);
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec!["q", "w"]);
+    }
+
+    #[test]
+    fn recall_by_prefix() {
+        let history = history(&["s/a/b/", "w", "set list", "s/c/d/"]);
+        let mut recall = Recall::default();
+        assert_eq!(recall.older(&history, "s").as_deref(), Some("s/c/d/"));
+        assert_eq!(recall.older(&history, "s/c/d/").as_deref(), Some("set list"));
+        assert_eq!(recall.older(&history, "set list").as_deref(), Some("s/a/b/"));
+        assert_eq!(recall.older(&history, "s/a/b/"), None);
+        assert_eq!(recall.newer(&history).as_deref(), Some("set list"));
+        assert_eq!(recall.newer(&history).as_deref(), Some("s/c/d/"));
+        // Past the newest comes back what was typed
+        assert_eq!(recall.newer(&history).as_deref(), Some("s"));
+        assert_eq!(recall.newer(&history), None);
+    }
+}
@@ -0,0 +1,68 @@
+// Lines looked at when guessing a file's indentation
+const SAMPLE_LINES: usize = 1000;
+
+// How a file is indented, as guessed from its leading whitespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indentation {
+    Tabs,
+    Spaces(usize),
+}
+
+// Guess tabs or spaces from how lines start, and the width of one level from the
+// most common step between the space indents of neighbouring lines. None when
+// nothing in the file is indented.
+pub fn detect<'a>(lines: impl IntoIterator<Item = &'a String>) -> Option<Indentation> {
+    let mut tab_lines = 0;
+    let mut space_lines = 0;
+    let mut steps = [0usize; 9]; // Count of each indent step from 1 to 8 spaces
+    let mut previous = 0;
+
+    for line in lines.into_iter().take(SAMPLE_LINES) {
+        let text = line.trim_start_matches([' ', '\t']);
+        if text.is_empty() {
+            continue;
+        }
+        let indent = &line[..line.len() - text.len()];
+        if indent.starts_with('\t') {
+            tab_lines += 1;
+            continue;
+        }
+        // Block comment continuations like " * text" sit one space off the grid
+        if text.starts_with('*') && indent.len() % 2 == 1 {
+            continue;
+        }
+        if indent.is_empty() {
+            previous = 0;
+            continue;
+        }
+        if indent.contains('\t') {
+            continue;
+        }
+        space_lines += 1;
+        let width = indent.len();
+        let step = width.abs_diff(previous);
+        if (1..steps.len()).contains(&step) {
+            steps[step] += 1;
+        }
+        previous = width;
+    }
+
+    if tab_lines == 0 && space_lines == 0 {
+        return None;
+    }
+    if tab_lines > space_lines {
+        return Some(Indentation::Tabs);
+    }
+    // Ties go to the wider step, so 4-space files with some 2-space alignment stay at 4
+    let (width, count) = steps.iter().enumerate().skip(2).fold((0, 0), |best, (width, &count)| {
+        if count >= best.1 && count > 0 {
+            (width, count)
+        } else {
+            best
+        }
+    });
+    if count == 0 {
+        return None;
+    }
+    Some(Indentation::Spaces(width))
+}
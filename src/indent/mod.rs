@@ -0,0 +1,284 @@
+mod detect;
+mod rules;
+
+use crate::buffer::Buffer;
+use crate::options::Options;
+
+pub use detect::{detect, Indentation};
+pub use rules::{rules_for, IndentRules};
+
+// shiftwidth, tabstop, softtabstop and expandtab as they apply to one buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndentSettings {
+    pub shift_width: usize,
+    pub tab_stop: usize,
+    pub soft_tab_stop: usize, // 0 when off
+    pub expand_tabs: bool,
+}
+
+impl IndentSettings {
+    pub fn from_options(options: &Options) -> Self {
+        let tab_stop = options.number("tabstop").max(1);
+        let shift_width = options.number("shiftwidth");
+        Self {
+            // Like vim, a shiftwidth of 0 follows the tabstop
+            shift_width: if shift_width == 0 { tab_stop } else { shift_width },
+            tab_stop,
+            soft_tab_stop: options.number("softtabstop"),
+            expand_tabs: options.bool("expandtab"),
+        }
+    }
+
+    // Follow the indentation a file already uses
+    pub fn adapt_to(&mut self, indentation: Indentation) {
+        match indentation {
+            Indentation::Tabs => {
+                self.expand_tabs = false;
+                self.shift_width = self.tab_stop;
+                self.soft_tab_stop = 0;
+            }
+            Indentation::Spaces(width) => {
+                self.expand_tabs = true;
+                self.shift_width = width;
+                if self.soft_tab_stop > 0 {
+                    self.soft_tab_stop = width;
+                }
+            }
+        }
+    }
+
+    // Short description for the status line, like `spaces:2` or `tabs:8`
+    pub fn describe(&self) -> String {
+        if self.expand_tabs {
+            format!("spaces:{}", self.shift_width)
+        } else {
+            format!("tabs:{}", self.tab_stop)
+        }
+    }
+
+    // Columns the Tab key moves to the next multiple of when tabs are expanded
+    pub fn tab_width(&self) -> usize {
+        if self.soft_tab_stop > 0 {
+            self.soft_tab_stop
+        } else {
+            self.shift_width
+        }
+    }
+
+    // Whitespace covering display columns `start..end`: spaces, or tabs up to the
+    // last tabstop before `end` and spaces after it
+    pub fn fill(&self, start: usize, end: usize) -> String {
+        if self.expand_tabs {
+            return " ".repeat(end.saturating_sub(start));
+        }
+        let mut fill = String::new();
+        let mut col = start;
+        while (col / self.tab_stop + 1) * self.tab_stop <= end {
+            fill.push('\t');
+            col = (col / self.tab_stop + 1) * self.tab_stop;
+        }
+        fill.push_str(&" ".repeat(end.saturating_sub(col)));
+        fill
+    }
+
+    // Leading whitespace that is `width` columns wide: spaces, or as many tabs
+    // as fit followed by spaces when tabs are not expanded
+    pub fn indent_string(&self, width: usize) -> String {
+        self.fill(0, width)
+    }
+
+    // The width one level deeper (or shallower) than `width`, on a shiftwidth multiple
+    pub fn shift(&self, width: usize, deeper: bool) -> usize {
+        if deeper {
+            (width / self.shift_width + 1) * self.shift_width
+        } else if width == 0 {
+            0
+        } else {
+            (width - 1) / self.shift_width * self.shift_width
+        }
+    }
+}
+
+impl Default for IndentSettings {
+    fn default() -> Self {
+        Self::from_options(&Options::new())
+    }
+}
+
+// Rules for a buffer: the ones named by its indentrules option, or else its filetype's
+pub fn buffer_rules(buffer: &Buffer) -> &'static dyn IndentRules {
+    let name = buffer.options().string("indentrules");
+    if name.is_empty() {
+        rules_for(buffer.syntax_name())
+    } else {
+        rules_for(Some(&name))
+    }
+}
+
+// Bytes of leading whitespace
+pub fn indent_len(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+// Display width of the leading whitespace, with tabs to the next tabstop
+pub fn indent_width(line: &str, tab_stop: usize) -> usize {
+    display_col(line, indent_len(line), tab_stop)
+}
+
+// Screen column of byte `col`, counting tabs up to the next tabstop
+pub fn display_col(line: &str, col: usize, tab_stop: usize) -> usize {
+    line.char_indices().take_while(|&(i, _)| i < col).fold(0, |width, (_, c)| match c {
+        '\t' => (width / tab_stop + 1) * tab_stop,
+        _ => width + 1,
+    })
+}
+
+// Byte offset of the character covering screen column `target`
+pub fn byte_at_display_col(line: &str, target: usize, tab_stop: usize) -> usize {
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        width = match c {
+            '\t' => (width / tab_stop + 1) * tab_stop,
+            _ => width + 1,
+        };
+        if width > target {
+            return i;
+        }
+    }
+    line.len()
+}
+
+// Byte offset of the first character that starts at or after screen column
+// `target`, so one a tab stretches over from before it is passed
+pub fn byte_from_display_col(line: &str, target: usize, tab_stop: usize) -> usize {
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        if width >= target {
+            return i;
+        }
+        width = match c {
+            '\t' => (width / tab_stop + 1) * tab_stop,
+            _ => width + 1,
+        };
+    }
+    line.len()
+}
+
+// :retab: rewrite whitespace runs that contain tabs (every run with `all`) for
+// the buffer's expandtab, optionally switching to a new tabstop. Returns the
+// number of lines changed.
+pub fn retab(buffer: &mut Buffer, new_tab_stop: Option<usize>, all: bool) -> usize {
+    let old = buffer.indent_settings();
+    let mut new = old;
+    if let Some(tab_stop) = new_tab_stop.filter(|&ts| ts > 0) {
+        new.tab_stop = tab_stop;
+    }
+
+    let mut changed = 0;
+    buffer.begin_undo_group();
+    for line_idx in 0..buffer.line_count() {
+        let line = buffer.get_line(line_idx).cloned().unwrap_or_default();
+        let mut result = String::with_capacity(line.len());
+        let mut col = 0;
+        let mut run: Option<(usize, bool)> = None; // Start column and whether it holds a tab
+        for c in line.chars().chain(std::iter::once('\n')) {
+            if c == ' ' || c == '\t' {
+                let (start, has_tab) = run.unwrap_or((col, false));
+                run = Some((start, has_tab || c == '\t'));
+                col = if c == '\t' { (col / old.tab_stop + 1) * old.tab_stop } else { col + 1 };
+                continue;
+            }
+            if let Some((start, has_tab)) = run.take() {
+                if has_tab || (all && !new.expand_tabs && col - start > 1) {
+                    result.push_str(&new.fill(start, col));
+                } else {
+                    result.push_str(&" ".repeat(col - start));
+                }
+            }
+            if c != '\n' {
+                result.push(c);
+                col += 1;
+            }
+        }
+        if result != line {
+            buffer.set_line(line_idx, result);
+            changed += 1;
+        }
+    }
+    buffer.end_undo_group();
+    buffer.set_indent_settings(new);
+    changed
+}
+
+// Replace a line's leading whitespace, returning how many bytes it grew or shrank by
+pub fn set_indent(buffer: &mut Buffer, line: usize, width: usize, settings: &IndentSettings) -> isize {
+    let text = match buffer.get_line(line) {
+        Some(text) => text,
+        None => return 0,
+    };
+    let old_len = indent_len(text);
+    let indent = settings.indent_string(width);
+    if text[..old_len] == indent {
+        return 0;
+    }
+    let delta = indent.len() as isize - old_len as isize;
+    let new_text = format!("{}{}", indent, &text[old_len..]);
+    buffer.set_line(line, new_text);
+    delta
+}
+
+// The indent `current` should get when it follows `previous`, the closest
+// non-blank line above it
+pub fn indent_after(previous: Option<&str>, current: &str, rules: &dyn IndentRules, settings: &IndentSettings) -> usize {
+    let mut width = match previous {
+        Some(previous) => {
+            let width = indent_width(previous, settings.tab_stop);
+            match rules.after(previous.trim()) {
+                Shift::Indent => width + settings.shift_width,
+                Shift::Dedent => width.saturating_sub(settings.shift_width),
+                Shift::Keep => width,
+            }
+        }
+        None => 0,
+    };
+    if rules.dedents(current.trim()) {
+        width = width.saturating_sub(settings.shift_width);
+    }
+    width
+}
+
+// How the line after a given one is indented relative to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    Indent,
+    Dedent,
+    Keep,
+}
+
+// The `=` operator: indent lines `start..=end` from the line above the range
+pub fn reindent(buffer: &mut Buffer, start: usize, end: usize) {
+    let settings = &buffer.indent_settings();
+    let rules = buffer_rules(buffer);
+    let end = end.min(buffer.line_count().saturating_sub(1));
+    let mut previous = (0..start)
+        .rev()
+        .filter_map(|i| buffer.get_line(i))
+        .find(|line| !line.trim().is_empty())
+        .cloned();
+
+    buffer.begin_undo_group();
+    for line in start..=end {
+        let text = buffer.get_line(line).cloned().unwrap_or_default();
+        if text.trim().is_empty() {
+            // Blank lines lose their whitespace rather than getting an indent
+            if !text.is_empty() {
+                buffer.set_line(line, String::new());
+            }
+            continue;
+        }
+        let width = indent_after(previous.as_deref(), &text, rules, settings);
+        set_indent(buffer, line, width, settings);
+        previous = buffer.get_line(line).cloned();
+    }
+    buffer.end_undo_group();
+}
@@ -0,0 +1,249 @@
+use super::Shift;
+
+// Indentation rules for one kind of file. Lines passed in are trimmed.
+pub trait IndentRules {
+    // How the line after `line` is indented relative to it
+    fn after(&self, line: &str) -> Shift;
+
+    // Whether `line` goes one level left of the line before it, like a closing brace
+    fn dedents(&self, line: &str) -> bool;
+}
+
+// Rules for a filetype, as named by the syntax highlighter, or for the name of a
+// rule set: braces, python, shell, ruby, lua, markup, yaml or plain
+pub fn rules_for(filetype: Option<&str>) -> &'static dyn IndentRules {
+    let name = filetype.unwrap_or("").to_lowercase();
+    if name == "python" {
+        &Python
+    } else if name.contains("shell") || name.contains("bash") || name.contains("zsh") {
+        &Shell
+    } else if name == "ruby" {
+        &Ruby
+    } else if name == "lua" {
+        &Lua
+    } else if name.contains("html") || name.contains("xml") || name == "markup" {
+        &Markup
+    } else if name == "yaml" {
+        &Yaml
+    } else if name.is_empty() || name == "plain" || name == "plain text" || name == "markdown" {
+        &Plain
+    } else {
+        &Braces
+    }
+}
+
+// Keep the previous line's indent
+struct Plain;
+
+impl IndentRules for Plain {
+    fn after(&self, _line: &str) -> Shift {
+        Shift::Keep
+    }
+
+    fn dedents(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+// C-like languages: indent inside {}, () and [] that are left open at the end of a line
+struct Braces;
+
+impl IndentRules for Braces {
+    fn after(&self, line: &str) -> Shift {
+        if ends_with_opener(strip_comment(line, "//")) {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        starts_with_closer(line)
+    }
+}
+
+struct Python;
+
+impl IndentRules for Python {
+    fn after(&self, line: &str) -> Shift {
+        let code = strip_comment(line, "#");
+        if code.ends_with(':') || ends_with_opener(code) {
+            Shift::Indent
+        } else if matches!(first_word(code), "return" | "pass" | "break" | "continue" | "raise") {
+            Shift::Dedent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        starts_with_closer(line) || matches!(first_word(line), "else" | "elif" | "except" | "finally")
+    }
+}
+
+struct Shell;
+
+impl IndentRules for Shell {
+    fn after(&self, line: &str) -> Shift {
+        let code = strip_comment(line, "#");
+        let opens = matches!(last_word(code), "then" | "do" | "else")
+            || ends_with_opener(code)
+            || (first_word(code) == "case" && last_word(code) == "in");
+        if opens {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        starts_with_closer(line) || matches!(first_word(line), "fi" | "done" | "esac" | "else" | "elif")
+    }
+}
+
+struct Ruby;
+
+impl IndentRules for Ruby {
+    fn after(&self, line: &str) -> Shift {
+        let code = strip_comment(line, "#");
+        let opens = matches!(
+            first_word(code),
+            "def" | "class" | "module" | "if" | "unless" | "while" | "until" | "case" | "begin"
+                | "else" | "elsif" | "when" | "rescue" | "ensure"
+        ) || last_word(code) == "do"
+            || (code.ends_with('|') && code.contains(" do |"))
+            || ends_with_opener(code);
+        if opens && last_word(code) != "end" {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        starts_with_closer(line)
+            || matches!(first_word(line), "end" | "else" | "elsif" | "when" | "rescue" | "ensure")
+    }
+}
+
+struct Lua;
+
+impl IndentRules for Lua {
+    fn after(&self, line: &str) -> Shift {
+        let code = strip_comment(line, "--");
+        // Blocks opened and closed on one line cancel out
+        let words: Vec<&str> = code.split(|c: char| !is_word_char(c)).collect();
+        let opened = words.iter().filter(|w| matches!(**w, "then" | "do" | "function" | "repeat")).count();
+        let closed = words.iter().filter(|w| matches!(**w, "end" | "until")).count();
+        let continues = matches!(first_word(code), "else" | "elseif") && closed == 0;
+        if opened > closed || continues || ends_with_opener(code) {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        starts_with_closer(line) || matches!(first_word(line), "end" | "else" | "elseif" | "until")
+    }
+}
+
+// HTML and XML: indent inside tags left open at the end of a line
+struct Markup;
+
+impl IndentRules for Markup {
+    fn after(&self, line: &str) -> Shift {
+        let mut depth = 0isize;
+        let mut rest = line;
+        while let Some(start) = rest.find('<') {
+            let tag = &rest[start + 1..];
+            let end = tag.find('>').unwrap_or(tag.len());
+            let inner = &tag[..end];
+            if let Some(name) = inner.strip_prefix('/') {
+                if !name.is_empty() {
+                    depth -= 1;
+                }
+            } else if !inner.ends_with('/') && !inner.starts_with(['!', '?']) && !is_void_element(inner) {
+                depth += 1;
+            }
+            rest = &tag[end..];
+        }
+        if depth > 0 {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, line: &str) -> bool {
+        line.starts_with("</")
+    }
+}
+
+struct Yaml;
+
+impl IndentRules for Yaml {
+    fn after(&self, line: &str) -> Shift {
+        if strip_comment(line, "#").ends_with(':') {
+            Shift::Indent
+        } else {
+            Shift::Keep
+        }
+    }
+
+    fn dedents(&self, _line: &str) -> bool {
+        false
+    }
+}
+
+// HTML elements that never have a closing tag
+fn is_void_element(tag: &str) -> bool {
+    let name = tag.split(|c: char| c.is_whitespace()).next().unwrap_or("").to_lowercase();
+    matches!(
+        name.as_str(),
+        "area" | "base" | "br" | "col" | "embed" | "hr" | "img" | "input" | "link" | "meta" | "source" | "track" | "wbr"
+    )
+}
+
+fn ends_with_opener(code: &str) -> bool {
+    code.ends_with(['{', '(', '['])
+}
+
+fn starts_with_closer(line: &str) -> bool {
+    line.starts_with(['}', ')', ']'])
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn first_word(line: &str) -> &str {
+    line.split(|c: char| !is_word_char(c)).next().unwrap_or("")
+}
+
+fn last_word(line: &str) -> &str {
+    line.rsplit(|c: char| !is_word_char(c)).next().unwrap_or("")
+}
+
+// The code before a line comment, skipping comment markers inside quotes
+fn strip_comment<'a>(line: &'a str, marker: &str) -> &'a str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if line[i..].starts_with(marker) => return line[..i].trim_end(),
+            None => {}
+        }
+    }
+    line
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbbrevMode {
+    Insert,
+    Command,
+}
+
+// Which modes an :abbreviate style command applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbbrevModes {
+    pub insert: bool,
+    pub command: bool,
+}
+
+impl AbbrevModes {
+    fn includes(&self, mode: AbbrevMode) -> bool {
+        match mode {
+            AbbrevMode::Insert => self.insert,
+            AbbrevMode::Command => self.command,
+        }
+    }
+}
+
+const BOTH: AbbrevModes = AbbrevModes { insert: true, command: true };
+const INSERT: AbbrevModes = AbbrevModes { insert: true, command: false };
+const COMMAND: AbbrevModes = AbbrevModes { insert: false, command: true };
+
+// The abbreviation commands: full name, shortest vim abbreviation, kind, modes
+pub const ABBREV_COMMANDS: &[(&str, usize, &str, AbbrevModes)] = &[
+    ("abbreviate", 2, "define", BOTH),
+    ("iabbrev", 2, "define", INSERT),
+    ("cabbrev", 2, "define", COMMAND),
+    ("unabbreviate", 3, "remove", BOTH),
+    ("iunabbrev", 4, "remove", INSERT),
+    ("cunabbrev", 4, "remove", COMMAND),
+    ("abclear", 3, "clear", BOTH),
+    ("iabclear", 4, "clear", INSERT),
+    ("cabclear", 4, "clear", COMMAND),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbbrevCommand {
+    Define { modes: AbbrevModes, buffer_local: bool, lhs: String, rhs: String },
+    Remove { modes: AbbrevModes, buffer_local: bool, lhs: String },
+    Clear { modes: AbbrevModes, buffer_local: bool },
+    List { modes: AbbrevModes },
+}
+
+impl AbbrevCommand {
+    // Parse :abbreviate, :iabbrev, :cabbrev, :unabbreviate, :abclear and their
+    // i/c variants. Returns None when `cmd` is not an abbreviation command.
+    pub fn parse(cmd: &str) -> Option<Result<Self, String>> {
+        let (name, args) = match cmd.find(char::is_whitespace) {
+            Some(i) => (&cmd[..i], cmd[i..].trim()),
+            None => (cmd, ""),
+        };
+
+        // Any prefix at least as long as vim's shortest form, :iab for :iabbrev
+        let (kind, modes) = ABBREV_COMMANDS
+            .iter()
+            .find(|(full, min, _, _)| name.len() >= *min && full.starts_with(name))
+            .map(|&(_, _, kind, modes)| (kind, modes))?;
+
+        let (buffer_local, args) = match args.strip_prefix("<buffer>") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, args),
+        };
+
+        let parsed = match kind {
+            "define" if args.is_empty() => Ok(AbbrevCommand::List { modes }),
+            "define" => match args.split_once(char::is_whitespace) {
+                Some((lhs, rhs)) if is_valid_lhs(lhs) && !rhs.trim().is_empty() => Ok(AbbrevCommand::Define {
+                    modes,
+                    buffer_local,
+                    lhs: lhs.to_string(),
+                    rhs: rhs.trim().replace("<CR>", "\n"),
+                }),
+                Some((lhs, _)) if !is_valid_lhs(lhs) => Err(format!("Invalid abbreviation: {}", lhs)),
+                _ => Err(format!("No abbreviation found: {}", args)),
+            },
+            "remove" if args.is_empty() => Err("Argument required".to_string()),
+            "remove" => Ok(AbbrevCommand::Remove { modes, buffer_local, lhs: args.to_string() }),
+            _ => Ok(AbbrevCommand::Clear { modes, buffer_local }),
+        };
+        Some(parsed)
+    }
+}
+
+// Abbreviations are whole keywords, expanded when a non-keyword character follows them
+fn is_valid_lhs(lhs: &str) -> bool {
+    !lhs.is_empty() && lhs.chars().all(is_keyword)
+}
+
+pub fn is_keyword(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Clone, Default)]
+pub struct Abbreviations {
+    insert: HashMap<String, String>,
+    command: HashMap<String, String>,
+}
+
+impl Abbreviations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, modes: AbbrevModes, lhs: &str, rhs: &str) {
+        for (mode, map) in [(AbbrevMode::Insert, &mut self.insert), (AbbrevMode::Command, &mut self.command)] {
+            if modes.includes(mode) {
+                map.insert(lhs.to_string(), rhs.to_string());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, modes: AbbrevModes, lhs: &str) -> bool {
+        let mut removed = false;
+        for (mode, map) in [(AbbrevMode::Insert, &mut self.insert), (AbbrevMode::Command, &mut self.command)] {
+            if modes.includes(mode) {
+                removed |= map.remove(lhs).is_some();
+            }
+        }
+        removed
+    }
+
+    pub fn clear(&mut self, modes: AbbrevModes) {
+        if modes.insert {
+            self.insert.clear();
+        }
+        if modes.command {
+            self.command.clear();
+        }
+    }
+
+    pub fn get(&self, mode: AbbrevMode, lhs: &str) -> Option<&str> {
+        match mode {
+            AbbrevMode::Insert => self.insert.get(lhs),
+            AbbrevMode::Command => self.command.get(lhs),
+        }
+        .map(|s| s.as_str())
+    }
+
+    // Sorted "i  lhs  rhs" lines for :abbreviate without arguments
+    pub fn list(&self, modes: AbbrevModes) -> Vec<String> {
+        let mut entries = Vec::new();
+        if modes.insert {
+            entries.extend(self.insert.iter().map(|(l, r)| format!("i  {}  {}", l, r.replace('\n', "<CR>"))));
+        }
+        if modes.command {
+            entries.extend(self.command.iter().map(|(l, r)| format!("c  {}  {}", l, r.replace('\n', "<CR>"))));
+        }
+        entries.sort();
+        entries
+    }
+
+    // Find the abbreviation ending right before `col`, looking through `layers`
+    // in order (buffer-local first). Returns where it starts and its expansion.
+    pub fn find_before(layers: &[&Abbreviations], mode: AbbrevMode, text: &str, col: usize) -> Option<(usize, String)> {
+        let before = text.get(..col)?;
+        let start = before
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_keyword(c))
+            .last()
+            .map(|(i, _)| i)?;
+        let lhs = &before[start..];
+        layers
+            .iter()
+            .find_map(|layer| layer.get(mode, lhs))
+            .map(|rhs| (start, rhs.to_string()))
+    }
+}
@@ -1,28 +1,440 @@
-use std::collections::HashMap;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
+// A key as mappings compare it: a code and its modifiers, without the event
+// kind, and with Shift left out of characters since it shows in the character
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let (code, modifiers) = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) => {
+                (KeyCode::Char(c.to_ascii_lowercase()), modifiers - KeyModifiers::SHIFT)
+            }
+            KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+            KeyCode::BackTab => (KeyCode::BackTab, modifiers | KeyModifiers::SHIFT),
+            _ => (code, modifiers),
+        };
+        Self { code, modifiers }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+
+    pub fn to_event(self) -> KeyEvent {
+        KeyEvent::new(self.code, self.modifiers)
+    }
+}
+
+// Key names usable inside <>, as the first spelling is shown
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Enter", KeyCode::Enter),
+    ("Return", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Backspace", KeyCode::Backspace),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+    ("Bar", KeyCode::Char('|')),
+    ("Bslash", KeyCode::Char('\\')),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+// Keys written in vim's notation: plain characters, and names such as <Esc>,
+// <C-w>, <M-x>, <S-Tab> or <F5>. <leader> stands for `leader`, <Nop> for no key.
+// A `<` that doesn't start a key name is just that character.
+pub fn parse_keys(text: &str, leader: &[Key]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>').filter(|&end| end > 1) {
+                let name = &rest[1..end];
+                if name.eq_ignore_ascii_case("leader") {
+                    keys.extend_from_slice(leader);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                if name.eq_ignore_ascii_case("nop") {
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                if let Some(key) = parse_key_name(name) {
+                    keys.push(key);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(Key::new(KeyCode::Char(c), KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+// What's inside <>: modifiers like C- and M- (or A-) and S-, then a name or a character
+fn parse_key_name(name: &str) -> Option<Key> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = name;
+    while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+        modifiers |= match rest.as_bytes()[0].to_ascii_uppercase() {
+            b'C' => KeyModifiers::CONTROL,
+            b'M' | b'A' => KeyModifiers::ALT,
+            b'S' => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        rest = &rest[2..];
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if !modifiers.is_empty() => KeyCode::Char(c),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(key_name, _)| key_name.eq_ignore_ascii_case(rest))
+            .map(|&(_, code)| code)
+            .or_else(|| {
+                let number = rest.strip_prefix(['F', 'f'])?.parse().ok()?;
+                (1..=12).contains(&number).then_some(KeyCode::F(number))
+            })?,
+    };
+    let code = match code {
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) && !modifiers.contains(KeyModifiers::CONTROL) => {
+            KeyCode::Char(c.to_ascii_uppercase())
+        }
+        code => code,
+    };
+    Some(Key::new(code, modifiers))
+}
+
+// Keys back in the notation parse_keys reads
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter().map(|&key| format_key(key)).collect()
+}
+
+fn format_key(key: Key) -> String {
+    let mut prefix = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        prefix.push_str("C-");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        prefix.push_str("M-");
+    }
+    let name = match key.code {
+        KeyCode::BackTab => return format!("<{}S-Tab>", prefix),
+        KeyCode::Char(c) if prefix.is_empty() && !matches!(c, ' ' | '<' | '|') => return c.to_string(),
+        KeyCode::Char(c) if !prefix.is_empty() && c != ' ' => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        code => KEY_NAMES
+            .iter()
+            .find(|&&(_, key_code)| key_code == code)
+            .map_or_else(|| format!("{:?}", code), |(name, _)| name.to_string()),
+    };
+    if key.modifiers.contains(KeyModifiers::SHIFT) && !matches!(key.code, KeyCode::Char(_)) {
+        prefix.push_str("S-");
+    }
+    format!("<{}{}>", prefix, name)
+}
+
+// The modes a mapping can apply in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    OperatorPending,
+    Insert,
+    Command,
+}
+
+const MAP_MODES: [(MapMode, char); 5] = [
+    (MapMode::Normal, 'n'),
+    (MapMode::Visual, 'v'),
+    (MapMode::OperatorPending, 'o'),
+    (MapMode::Insert, 'i'),
+    (MapMode::Command, 'c'),
+];
+
+// Which modes a :map style command applies to, as vim's mode letters:
+// "nvo" for :map, "n" for :nmap, "ic" for :map! and so on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapModes(&'static str);
+
+impl MapModes {
+    pub const NORMAL: Self = Self("n");
+    pub const VISUAL: Self = Self("v");
+    pub const OPERATOR_PENDING: Self = Self("o");
+    pub const INSERT: Self = Self("i");
+    pub const COMMAND: Self = Self("c");
+
+    pub fn includes(&self, mode: MapMode) -> bool {
+        MAP_MODES.iter().any(|&(m, letter)| m == mode && self.0.contains(letter))
+    }
+}
+
+// The mapping commands: full name, shortest vim abbreviation, kind, modes (and
+// modes with !)
+pub const MAP_COMMANDS: &[(&str, usize, &str, &str)] = &[
+    ("map", 3, "map", "nvo"),
+    ("nmap", 2, "map", "n"),
+    ("vmap", 2, "map", "v"),
+    ("xmap", 2, "map", "v"),
+    ("omap", 2, "map", "o"),
+    ("imap", 2, "map", "i"),
+    ("cmap", 2, "map", "c"),
+    ("noremap", 2, "noremap", "nvo"),
+    ("nnoremap", 2, "noremap", "n"),
+    ("vnoremap", 2, "noremap", "v"),
+    ("xnoremap", 2, "noremap", "v"),
+    ("onoremap", 3, "noremap", "o"),
+    ("inoremap", 3, "noremap", "i"),
+    ("cnoremap", 3, "noremap", "c"),
+    ("unmap", 3, "unmap", "nvo"),
+    ("nunmap", 3, "unmap", "n"),
+    ("vunmap", 2, "unmap", "v"),
+    ("xunmap", 2, "unmap", "v"),
+    ("ounmap", 2, "unmap", "o"),
+    ("iunmap", 2, "unmap", "i"),
+    ("cunmap", 2, "unmap", "c"),
+    ("mapclear", 4, "mapclear", "nvo"),
+    ("nmapclear", 5, "mapclear", "n"),
+    ("vmapclear", 5, "mapclear", "v"),
+    ("xmapclear", 5, "mapclear", "v"),
+    ("omapclear", 5, "mapclear", "o"),
+    ("imapclear", 5, "mapclear", "i"),
+    ("cmapclear", 5, "mapclear", "c"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapCommand {
+    Define { modes: MapModes, noremap: bool, lhs: String, rhs: String, desc: Option<String> },
+    Remove { modes: MapModes, lhs: String },
+    Clear { modes: MapModes },
+    List { modes: MapModes, lhs: String }, // Mappings starting with `lhs`, all when empty
+}
+
+impl MapCommand {
+    // Parse :map, :noremap, :unmap, :mapclear and their n/v/x/o/i/c variants, and
+    // the ! forms for insert and command-line mode. Returns None when `cmd` is not
+    // a mapping command.
+    pub fn parse(cmd: &str) -> Option<Result<Self, String>> {
+        let name_len = cmd.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(cmd.len());
+        let (name, rest) = cmd.split_at(name_len);
+        let (bang, args) = match rest.strip_prefix('!') {
+            Some(args) => (true, args),
+            None => (false, rest),
+        };
+        if !args.is_empty() && !args.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let args = args.trim_start();
+
+        let &(_, _, kind, modes) = MAP_COMMANDS
+            .iter()
+            .find(|(full, min, _, _)| name.len() >= *min && full.starts_with(name))?;
+        // Only the commands for all of normal, visual and operator-pending mode take a !
+        let modes = match (bang, modes) {
+            (false, modes) => MapModes(modes),
+            (true, "nvo") => MapModes("ic"),
+            (true, _) => return Some(Err(format!("Trailing characters: {}", cmd))),
+        };
+
+        // `<desc:text>` before the keys describes the mapping in the key hint popup
+        let (desc, args) = match args.strip_prefix("<desc:").and_then(|rest| rest.split_once('>')) {
+            Some((desc, rest)) => (Some(desc.trim().to_string()), rest.trim_start()),
+            None => (None, args),
+        };
+
+        let parsed = match kind {
+            "map" | "noremap" => match args.split_once(char::is_whitespace) {
+                None if desc.is_some() => Err("Argument required".to_string()),
+                None => Ok(MapCommand::List { modes, lhs: args.to_string() }),
+                Some((lhs, rhs)) => Ok(MapCommand::Define {
+                    modes,
+                    noremap: kind == "noremap",
+                    lhs: lhs.to_string(),
+                    rhs: rhs.trim_start().to_string(),
+                    desc,
+                }),
+            },
+            "unmap" if args.is_empty() => Err("Argument required".to_string()),
+            "unmap" => Ok(MapCommand::Remove { modes, lhs: args.to_string() }),
+            _ if !args.is_empty() => Err(format!("Trailing characters: {}", args)),
+            _ => Ok(MapCommand::Clear { modes }),
+        };
+        Some(parsed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub mode: MapMode,
+    pub lhs: Vec<Key>,
+    pub rhs: Vec<Key>,
+    pub noremap: bool, // The keys it produces aren't mapped again
+    pub desc: Option<String>,
+}
+
+impl Mapping {
+    fn rhs_text(&self) -> String {
+        if self.rhs.is_empty() {
+            "<Nop>".to_string()
+        } else {
+            format_keys(&self.rhs)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct KeyMap {
-    mappings: HashMap<String, String>,
+    mappings: Vec<Mapping>,
 }
 
 impl KeyMap {
     pub fn new() -> Self {
-        Self {
-            mappings: HashMap::new(),
+        Self::default()
+    }
+
+    pub fn define(&mut self, modes: MapModes, lhs: &[Key], rhs: &[Key], noremap: bool, desc: Option<&str>) {
+        for &(mode, _) in MAP_MODES.iter().filter(|&&(mode, _)| modes.includes(mode)) {
+            self.mappings.retain(|m| !(m.mode == mode && m.lhs == lhs));
+            self.mappings.push(Mapping {
+                mode,
+                lhs: lhs.to_vec(),
+                rhs: rhs.to_vec(),
+                noremap,
+                desc: desc.map(str::to_string),
+            });
         }
     }
 
-    pub fn add_mapping(&mut self, from: &str, to: &str) {
-        self.mappings.insert(from.to_string(), to.to_string());
+    pub fn remove(&mut self, modes: MapModes, lhs: &[Key]) -> bool {
+        let count = self.mappings.len();
+        self.mappings.retain(|m| !(modes.includes(m.mode) && m.lhs == lhs));
+        self.mappings.len() != count
     }
 
-    pub fn get_mapping(&self, key: &str) -> Option<&String> {
-        self.mappings.get(key)
+    pub fn clear(&mut self, modes: MapModes) {
+        self.mappings.retain(|m| !modes.includes(m.mode));
+    }
+
+    // The longest mapping in `mode` that `keys` start with
+    pub fn longest_match(&self, mode: MapMode, keys: &[Key]) -> Option<&Mapping> {
+        self.mappings
+            .iter()
+            .filter(|m| m.mode == mode && keys.starts_with(&m.lhs))
+            .max_by_key(|m| m.lhs.len())
+    }
+
+    // Whether some mapping in `mode` is longer than `keys` and starts with them,
+    // so more keys should be waited for
+    pub fn has_longer(&self, mode: MapMode, keys: &[Key]) -> bool {
+        self.mappings
+            .iter()
+            .any(|m| m.mode == mode && m.lhs.len() > keys.len() && m.lhs.starts_with(keys))
     }
-}
 
-impl Default for KeyMap {
-    fn default() -> Self {
-        Self::new()
+    // The rest of the keys of every mapping in `mode` that `prefix` is the start
+    // of, with its description or else its keys, sorted
+    pub fn continuations(&self, mode: MapMode, prefix: &[Key]) -> Vec<(String, String)> {
+        let mut entries: Vec<(String, String)> = self
+            .mappings
+            .iter()
+            .filter(|m| m.mode == mode && m.lhs.len() > prefix.len() && m.lhs.starts_with(prefix))
+            .map(|m| (format_keys(&m.lhs[prefix.len()..]), m.desc.clone().unwrap_or_else(|| m.rhs_text())))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    // Sorted "n  lhs  * rhs" lines for :map, `*` marking a non-recursive mapping
+    pub fn list(&self, modes: MapModes, prefix: &[Key]) -> Vec<String> {
+        let mut entries: Vec<String> = self
+            .mappings
+            .iter()
+            .filter(|m| modes.includes(m.mode) && m.lhs.starts_with(prefix))
+            .map(|m| {
+                let letter = MAP_MODES.iter().find(|&&(mode, _)| mode == m.mode).map_or(' ', |&(_, letter)| letter);
+                let desc = m.desc.as_ref().map(|desc| format!("  \"{}\"", desc)).unwrap_or_default();
+                format!("{}  {}  {}{}{}", letter, format_keys(&m.lhs), if m.noremap { "* " } else { "" }, m.rhs_text(), desc)
+            })
+            .collect();
+        entries.sort();
+        entries
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        Key::new(code, modifiers)
+    }
+
+    fn plain(c: char) -> Key {
+        key(KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn plain_characters() {
+        assert_eq!(parse_keys("dd", &[]), vec![plain('d'), plain('d')]);
+        assert_eq!(parse_keys("é<", &[]), vec![plain('é'), plain('<')]);
+        assert_eq!(parse_keys("<>", &[]), vec![plain('<'), plain('>')]);
+        assert_eq!(parse_keys("<nokey>", &[]), "<nokey>".chars().map(plain).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse_keys("<Esc><CR><cr><Enter>", &[]), vec![
+            key(KeyCode::Esc, KeyModifiers::NONE),
+            key(KeyCode::Enter, KeyModifiers::NONE),
+            key(KeyCode::Enter, KeyModifiers::NONE),
+            key(KeyCode::Enter, KeyModifiers::NONE),
+        ]);
+        assert_eq!(parse_keys("<Space><lt><Bar><F5>", &[]), vec![plain(' '), plain('<'), plain('|'), key(KeyCode::F(5), KeyModifiers::NONE)]);
+        assert_eq!(parse_keys("<F13>", &[]).len(), 5);
+        assert_eq!(parse_keys("<Nop>", &[]), vec![]);
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(parse_keys("<C-w>", &[]), vec![key(KeyCode::Char('w'), KeyModifiers::CONTROL)]);
+        assert_eq!(parse_keys("<C-W>", &[]), vec![key(KeyCode::Char('w'), KeyModifiers::CONTROL)]);
+        assert_eq!(parse_keys("<M-x><A-x>", &[]), vec![key(KeyCode::Char('x'), KeyModifiers::ALT); 2]);
+        assert_eq!(parse_keys("<S-Tab>", &[]), vec![key(KeyCode::BackTab, KeyModifiers::SHIFT)]);
+        assert_eq!(parse_keys("<S-a>", &[]), vec![plain('A')]);
+        assert_eq!(parse_keys("<C-S-Up>", &[]), vec![key(KeyCode::Up, KeyModifiers::CONTROL | KeyModifiers::SHIFT)]);
+        // Shift shows in the character of a key event already
+        assert_eq!(Key::new(KeyCode::Char('A'), KeyModifiers::SHIFT), plain('A'));
+    }
+
+    #[test]
+    fn leader() {
+        let leader = parse_keys(",", &[]);
+        assert_eq!(parse_keys("<leader>w<Leader>", &leader), vec![plain(','), plain('w'), plain(',')]);
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in ["dd", "<Esc>", "<C-w>j", "<M-x>", "<S-Tab>", "<C-M-S-Tab>", "<Space>x", "<lt>", "<Bar>", "<F12>", "<C-S-Up>", "é"] {
+            assert_eq!(format_keys(&parse_keys(text, &[])), text);
+        }
+        assert_eq!(format_keys(&parse_keys("<cr><C-W><A-x>", &[])), "<CR><C-w><M-x>");
+    }
+}
@@ -1,4 +1,7 @@
+mod abbrev;
 mod keymap;
+mod pairs;
 
-pub use keymap::KeyMap;
-
+pub use abbrev::{is_keyword, Abbreviations, AbbrevCommand, AbbrevMode, AbbrevModes, ABBREV_COMMANDS};
+pub use keymap::{format_keys, parse_keys, Key, KeyMap, MapCommand, MapMode, MapModes, Mapping, MAP_COMMANDS};
+pub use pairs::AutoPairs;
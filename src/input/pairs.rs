@@ -0,0 +1,41 @@
+// Openers that insert mode closes automatically, with their closers
+#[derive(Debug, Clone, Default)]
+pub struct AutoPairs {
+    pairs: Vec<(char, char)>,
+}
+
+impl AutoPairs {
+    // Pairs written as two-character strings, like "()" or "''"
+    pub fn parse(pairs: &[String]) -> Result<Self, String> {
+        let pairs = pairs
+            .iter()
+            .map(|pair| {
+                let mut chars = pair.chars();
+                match (chars.next(), chars.next(), chars.next()) {
+                    (Some(open), Some(close), None) => Ok((open, close)),
+                    _ => Err(format!("Auto-pair must be two characters: {:?}", pair)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { pairs })
+    }
+
+    // Pairs used for a filetype with no table in the config
+    pub fn builtin(filetype: Option<&str>) -> Self {
+        let pairs: &[(char, char)] = match filetype {
+            // A single quote usually starts a lifetime rather than a char literal
+            Some("rust") => &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')],
+            _ => &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\''), ('`', '`')],
+        };
+        Self { pairs: pairs.to_vec() }
+    }
+
+    pub fn closer(&self, open: char) -> Option<char> {
+        self.pairs.iter().find(|&&(o, _)| o == open).map(|&(_, c)| c)
+    }
+
+    // Whether `c` only ever closes a pair, like `)` but not `"`
+    pub fn is_closer(&self, c: char) -> bool {
+        self.pairs.iter().any(|&(o, close)| close == c && o != c)
+    }
+}
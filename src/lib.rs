@@ -8,5 +8,12 @@ pub mod command;
 pub mod syntax;
 pub mod window;
 pub mod config;
+pub mod options;
 pub mod search;
+pub mod history;
+pub mod help;
+pub mod register;
+pub mod completion;
+pub mod indent;
+pub mod regex;
 
@@ -1,19 +1,126 @@
-use crate::buffer::Buffer;
-use crossterm::event::{KeyCode, KeyEvent};
-use std::io;
+use crate::command::{CommandExecutor, CompletionSources, SubstituteCount, Substitution};
+use crate::history::{History, Recall};
+use crate::input::{is_keyword, AbbrevCommand, AbbrevMode, Abbreviations, MapCommand};
+use crate::register::Registers;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 pub struct CommandMode {
     input: String,
+    cursor: usize,           // Byte offset in the input that typing goes to
+    recall: Recall,          // Up and Down through the command history
+    pending_register: bool,  // Ctrl-r, waiting for the register name, whose lines are joined with spaces
+    wildmenu: Option<Wildmenu>,
+}
+
+// Tab completion: the matches for the word from `start` to the cursor,
+// listed above the command line while Tab and Shift-Tab go through them
+pub struct Wildmenu {
+    start: usize,
+    typed: String, // The word as it was typed, put back after the last match
+    items: Vec<String>,
+    selected: Option<usize>,
+}
+
+impl Wildmenu {
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+}
+
+// Editor state the command line reads
+pub struct CommandLineContext<'a> {
+    pub registers: &'a Registers,
+    pub abbreviations: &'a [&'a Abbreviations],
+    pub history: &'a History,
+    pub word: Option<&'a str>, // Under the cursor in the buffer, for Ctrl-r Ctrl-w
+    pub commands: &'a CommandExecutor,
+    pub sources: &'a CompletionSources,
 }
 
 impl CommandMode {
     pub fn new() -> Self {
         Self {
             input: String::new(),
+            cursor: 0,
+            recall: Recall::default(),
+            pending_register: false,
+            wildmenu: None,
         }
     }
 
-    pub fn handle_key(&mut self, key: KeyEvent) -> Option<CommandResult> {
+    pub fn handle_key(&mut self, key: KeyEvent, context: &CommandLineContext) -> Option<CommandResult> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        if std::mem::take(&mut self.pending_register) {
+            match key.code {
+                KeyCode::Char('w') if control => {
+                    if let Some(word) = context.word {
+                        self.insert(word);
+                    }
+                }
+                KeyCode::Char(name) => {
+                    if let Some(register) = context.registers.get(name) {
+                        self.insert(&register.lines.join(" "));
+                    }
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        // Tab and Shift-Tab go through the matches, any other key keeps the
+        // one shown and goes on as usual
+        let next_match = match key.code {
+            KeyCode::Tab => Some(false),
+            KeyCode::BackTab => Some(true),
+            KeyCode::Char('n') if control && self.wildmenu.is_some() => Some(false),
+            KeyCode::Char('p') if control && self.wildmenu.is_some() => Some(true),
+            _ => None,
+        };
+        match next_match {
+            Some(backward) if self.wildmenu.is_some() => {
+                self.select_match(backward);
+                return None;
+            }
+            Some(backward) => {
+                self.complete(context, backward);
+                return None;
+            }
+            None => self.wildmenu = None,
+        }
+
+        // Up and Down recall older and newer commands that start with what was typed
+        match key.code {
+            KeyCode::Up => {
+                if let Some(line) = self.recall.older(context.history, &self.input) {
+                    self.input = line;
+                    self.cursor = self.input.len();
+                }
+                return None;
+            }
+            KeyCode::Down => {
+                if let Some(line) = self.recall.newer(context.history) {
+                    self.input = line;
+                    self.cursor = self.input.len();
+                }
+                return None;
+            }
+            _ => self.recall.reset(),
+        }
+
+        // A non-keyword character after a :cabbrev abbreviation expands it
+        if matches!(key.code, KeyCode::Char(c) if !is_keyword(c) && !control) || key.code == KeyCode::Enter {
+            if let Some((start, rhs)) =
+                Abbreviations::find_before(context.abbreviations, AbbrevMode::Command, &self.input, self.cursor)
+            {
+                self.input.replace_range(start..self.cursor, &rhs);
+                self.cursor = start + rhs.len();
+            }
+        }
+
         match key.code {
             KeyCode::Esc => {
                 return Some(CommandResult::Cancel);
@@ -21,28 +128,125 @@ impl CommandMode {
             KeyCode::Enter => {
                 return Some(CommandResult::Execute(self.input.clone()));
             }
+            KeyCode::Char('r') if control => self.pending_register = true,
+            KeyCode::Char('w') if control => {
+                let start = word_start(&self.input[..self.cursor]);
+                self.input.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            KeyCode::Char('u') if control => {
+                self.input.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            KeyCode::Char('b') if control => self.cursor = 0,
+            KeyCode::Char('e') if control => self.cursor = self.input.len(),
+            KeyCode::Char(_) if control => {}
             KeyCode::Char(c) => {
-                self.input.push(c);
+                self.insert(c.encode_utf8(&mut [0; 4]));
             }
             KeyCode::Backspace => {
-                if !self.input.is_empty() {
-                    self.input.pop();
+                if let Some(c) = self.input[..self.cursor].chars().next_back() {
+                    self.cursor -= c.len_utf8();
+                    self.input.remove(self.cursor);
                 }
             }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => {
+                self.cursor -= self.input[..self.cursor].chars().next_back().map_or(0, char::len_utf8);
+            }
+            KeyCode::Right => {
+                self.cursor += self.input[self.cursor..].chars().next().map_or(0, char::len_utf8);
+            }
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
             _ => {}
         }
         None
     }
 
+    fn insert(&mut self, text: &str) {
+        self.input.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    // Tab: complete the word before the cursor. A single match is put in
+    // place; more than one are listed in the wildmenu, starting with the first.
+    fn complete(&mut self, context: &CommandLineContext, backward: bool) {
+        let Some((start, items)) = context.commands.complete(&self.input[..self.cursor], context.sources) else { return };
+        match items.as_slice() {
+            [] => {}
+            [only] => {
+                let only = only.clone();
+                self.input.replace_range(start..self.cursor, &only);
+                self.cursor = start + only.len();
+            }
+            _ => {
+                let typed = self.input[start..self.cursor].to_string();
+                self.wildmenu = Some(Wildmenu { start, typed, items, selected: None });
+                self.select_match(backward);
+            }
+        }
+    }
+
+    // The next (previous) match in the wildmenu, or the word as typed after the last one
+    fn select_match(&mut self, backward: bool) {
+        let Some(menu) = self.wildmenu.as_mut() else { return };
+        let count = menu.items.len();
+        menu.selected = match (menu.selected, backward) {
+            (None, false) => Some(0),
+            (None, true) => Some(count - 1),
+            (Some(i), false) => (i + 1 < count).then_some(i + 1),
+            (Some(i), true) => i.checked_sub(1),
+        };
+        let text = menu.selected.map_or(&menu.typed, |i| &menu.items[i]);
+        self.input.replace_range(menu.start..self.cursor, text);
+        self.cursor = menu.start + text.len();
+    }
+
     pub fn input(&self) -> &str {
         &self.input
     }
 
+    // Where the cursor is drawn, in characters from the start of the input
+    pub fn cursor_col(&self) -> usize {
+        self.input[..self.cursor].chars().count()
+    }
+
+    pub fn wildmenu(&self) -> Option<&Wildmenu> {
+        self.wildmenu.as_ref()
+    }
+
+    // Start the command line with some text, such as '<,'> from visual mode
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_string();
+        self.cursor = self.input.len();
+        self.recall.reset();
+    }
+
     pub fn clear(&mut self) {
         self.input.clear();
+        self.cursor = 0;
+        self.recall.reset();
+        self.pending_register = false;
+        self.wildmenu = None;
     }
 }
 
+// Where Ctrl-w deletes back to: the start of the word before the cursor, or
+// of the run of other characters, after any spaces
+fn word_start(text: &str) -> usize {
+    let trimmed = text.trim_end();
+    let keyword = trimmed.chars().next_back().is_some_and(is_keyword);
+    trimmed
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| !c.is_whitespace() && is_keyword(c) == keyword)
+        .last()
+        .map_or(trimmed.len(), |(i, _)| i)
+}
+
 impl Default for CommandMode {
     fn default() -> Self {
         Self::new()
@@ -54,50 +258,6 @@ pub enum CommandResult {
     Cancel,
 }
 
-pub fn execute_command(cmd: &str, buffer: &mut Buffer) -> io::Result<CommandAction> {
-    let cmd = cmd.trim();
-    
-    if cmd.is_empty() {
-        return Ok(CommandAction::None);
-    }
-
-    match cmd {
-        "q" | "quit" => Ok(CommandAction::Quit),
-        "q!" | "quit!" => Ok(CommandAction::ForceQuit),
-        "w" | "write" => {
-            buffer.save()?;
-            Ok(CommandAction::None)
-        }
-        "wq" | "x" => {
-            buffer.save()?;
-            Ok(CommandAction::Quit)
-        }
-        _ if cmd.starts_with("w ") => {
-            let path = cmd[2..].trim();
-            buffer.save_as(path)?;
-            Ok(CommandAction::None)
-        }
-        _ if cmd.starts_with("e ") => {
-            let path = cmd[2..].trim();
-            Ok(CommandAction::Edit(path.to_string()))
-        }
-        // Window management commands
-        "split" | "sp" => Ok(CommandAction::SplitHorizontal(None)),
-        "vsplit" | "vs" => Ok(CommandAction::SplitVertical(None)),
-        "close" | "clo" => Ok(CommandAction::CloseWindow),
-        "only" | "on" => Ok(CommandAction::CloseOtherWindows),
-        _ if cmd.starts_with("split ") || cmd.starts_with("sp ") => {
-            let path = cmd.split_whitespace().nth(1).unwrap_or("").to_string();
-            Ok(CommandAction::SplitHorizontal(Some(path)))
-        }
-        _ if cmd.starts_with("vsplit ") || cmd.starts_with("vs ") => {
-            let path = cmd.split_whitespace().nth(1).unwrap_or("").to_string();
-            Ok(CommandAction::SplitVertical(Some(path)))
-        }
-        _ => Ok(CommandAction::Error(format!("Unknown command: {}", cmd))),
-    }
-}
-
 pub enum CommandAction {
     None,
     Quit,
@@ -107,6 +267,16 @@ pub enum CommandAction {
     SplitVertical(Option<String>),
     CloseWindow,
     CloseOtherWindows,
+    Buffer(String), // :buffer, by number or name
+    Help(String),   // :help, with the tag to jump to
+    Abbreviate(AbbrevCommand),
+    Map(MapCommand),
+    Set { args: String, local: bool }, // :set or :setlocal
+    Normal { lines: Option<(usize, usize)>, keys: String, remap: bool }, // Keys to run on each line, or once
+    ConfirmSubstitute(Box<Substitution>), // :s with the c flag, asking at each match
+    Substituted(SubstituteCount),
+    Global { lines: Vec<usize>, command: String }, // :g, to run the command on each line
+    NoHighlight, // :nohlsearch
+    Message(String),
     Error(String),
 }
-
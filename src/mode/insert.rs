@@ -1,176 +1,497 @@
 use crate::buffer::Buffer;
 use crate::cursor::Cursor;
+use crate::indent;
+use crate::input::{is_keyword, AbbrevMode, Abbreviations, AutoPairs};
 use crate::mode::Mode;
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::register::{RegisterKind, Registers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
-pub struct InsertMode;
+#[derive(Clone)]
+pub struct InsertMode {
+    pending: Option<Pending>,
+    inserted: String, // Text typed since entering insert mode, for Ctrl-a and the . register
+    auto_closers: Vec<(usize, usize)>, // Closers this insert added, as line and bytes from the line end
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pending {
+    Register, // Ctrl-r, waiting for the register name
+    Literal,  // Ctrl-v, waiting for the key to insert as is
+}
+
+// Editor state that insert mode reads besides the buffer
+pub struct InsertContext<'a> {
+    pub registers: &'a Registers,
+    pub abbreviations: &'a [&'a Abbreviations],
+    pub pairs: &'a AutoPairs,
+}
+
+pub enum InsertAction {
+    None,
+    ModeChange(Mode),
+    OneShotNormal,     // Ctrl-o: run one normal mode command, then come back
+    ScrollPage(bool),  // PageDown (true) / PageUp (false)
+}
 
 impl InsertMode {
     pub fn new() -> Self {
-        Self
+        Self {
+            pending: None,
+            inserted: String::new(),
+            auto_closers: Vec::new(),
+        }
     }
 
     pub fn handle_key(
+        &mut self,
         key: KeyEvent,
         cursor: &mut Cursor,
         buffer: &mut Buffer,
-    ) -> Option<Mode> {
+        context: &InsertContext,
+    ) -> InsertAction {
+        if let Some(pending) = self.pending.take() {
+            match (pending, key.code) {
+                (Pending::Register, KeyCode::Char(name)) => {
+                    let text = context.registers.get(name).map(|register| {
+                        let mut text = register.lines.join("\n");
+                        if register.kind == RegisterKind::Linewise {
+                            text.push('\n');
+                        }
+                        text
+                    });
+                    if let Some(text) = text {
+                        self.insert_text(&text, cursor, buffer);
+                    }
+                }
+                (Pending::Literal, KeyCode::Char(c)) if key.modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii() => {
+                    // Ctrl-v Ctrl-x inserts the control character itself
+                    let control = (c as u8 & 0x1f) as char;
+                    self.insert_text(&control.to_string(), cursor, buffer)
+                }
+                (Pending::Literal, KeyCode::Char(c)) => self.insert_text(&c.to_string(), cursor, buffer),
+                (Pending::Literal, KeyCode::Tab) => self.insert_text("\t", cursor, buffer),
+                (Pending::Literal, KeyCode::Esc) => self.insert_text("\x1b", cursor, buffer),
+                (Pending::Literal, KeyCode::Enter) => self.insert_text("\r", cursor, buffer),
+                _ => {}
+            }
+            return InsertAction::None;
+        }
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            return self.handle_control_key(key, cursor, buffer, context);
+        }
+
+        // A non-keyword character after an abbreviation expands it, and after
+        // a closing word like `fi` or `}` moves the line to its indent
+        let ends_word = match key.code {
+            KeyCode::Char(c) => !is_keyword(c),
+            KeyCode::Enter | KeyCode::Tab | KeyCode::Esc => true,
+            _ => false,
+        };
+        if ends_word {
+            self.expand_abbreviation(cursor, buffer, context.abbreviations);
+            Self::reindent_closing_line(cursor, buffer);
+        }
+
         match key.code {
             KeyCode::Esc => {
                 // Move cursor left when exiting insert mode (like vim)
-                if cursor.col > 0 {
-                    cursor.col -= 1;
-                    cursor.desired_col = cursor.col;
+                if let Some(c) = buffer.get_line(cursor.line).and_then(|line| line[..cursor.col.min(line.len())].chars().next_back()) {
+                    cursor.col -= c.len_utf8();
+                    cursor.remember_col(buffer);
                 }
-                return Some(Mode::Normal);
+                self.auto_closers.clear();
+                return InsertAction::ModeChange(Mode::Normal);
             }
             KeyCode::Char(c) => {
-                // Handle auto-closing brackets and parentheses
-                if let Some(closing_char) = Self::get_closing_char(c) {
-                    let current_line = buffer.get_line(cursor.line).map_or("", |v| v);
-                    
-                    // Check if user is typing a closing bracket that matches an auto-inserted one
-                    if cursor.col < current_line.len() && current_line.chars().nth(cursor.col) == Some(closing_char) {
-                        // Just move cursor past the existing closing bracket
-                        cursor.col += 1;
-                        cursor.desired_col = cursor.col;
-                    } else {
-                        // Insert opening bracket and auto-close
-                        buffer.insert_char(cursor.line, cursor.col, c);
-                        buffer.insert_char(cursor.line, cursor.col + 1, closing_char);
-                        cursor.col += 1;
-                        cursor.desired_col = cursor.col;
-                        
-                        // Force a re-render to ensure consistent highlighting
-                        // This will be handled by the editor's render loop
-                    }
-                } else {
-                    buffer.insert_char(cursor.line, cursor.col, c);
-                    cursor.col += 1;
-                    cursor.desired_col = cursor.col;
+                self.inserted.push(c);
+                self.type_char(c, cursor, buffer, context.pairs);
+                if ends_word {
+                    Self::reindent_closing_line(cursor, buffer);
                 }
             }
             KeyCode::Tab => {
-                // Insert 4 spaces for indentation
-                for _ in 0..4 {
-                    buffer.insert_char(cursor.line, cursor.col, ' ');
-                    cursor.col += 1;
-                }
-                cursor.desired_col = cursor.col;
-            }
-            KeyCode::Backspace => {
-                if cursor.col > 0 {
-                    let current_line = buffer.get_line(cursor.line).map_or("", |v| v);
-                    
-                    // Check for smart bracket deletion
-                    if let Some(closing_char) = Self::get_closing_char_for_deletion(current_line, cursor.col - 1) {
-                        // Check if the next character is the matching closing bracket
-                        if cursor.col < current_line.len() && current_line.chars().nth(cursor.col) == Some(closing_char) {
-                            // Delete both opening and closing brackets
-                            buffer.delete_char(cursor.line, cursor.col - 1);
-                            buffer.delete_char(cursor.line, cursor.col - 1);
-                            cursor.col -= 1;
-                            cursor.desired_col = cursor.col;
-                        } else {
-                            // Normal backspace
-                            buffer.delete_char(cursor.line, cursor.col - 1);
-                            cursor.col -= 1;
-                            cursor.desired_col = cursor.col;
-                        }
-                    } else {
-                        // Normal backspace
-                        buffer.delete_char(cursor.line, cursor.col - 1);
-                        cursor.col -= 1;
-                        cursor.desired_col = cursor.col;
-                    }
-                } else if cursor.line > 0 {
-                    // Join with previous line
-                    if let Some(prev_line) = buffer.get_line(cursor.line - 1) {
-                        let prev_len = prev_line.len();
-                        buffer.join_lines(cursor.line - 1);
-                        cursor.line -= 1;
-                        cursor.col = prev_len;
-                        cursor.desired_col = cursor.col;
-                    }
-                }
+                // A real tab, or spaces up to the next softtabstop (or shiftwidth) column
+                let settings = buffer.indent_settings();
+                let text = if settings.expand_tabs {
+                    let line = buffer.get_line(cursor.line).map_or("", |v| v);
+                    let col = indent::display_col(line, cursor.col, settings.tab_stop);
+                    let width = settings.tab_width();
+                    " ".repeat((col / width + 1) * width - col)
+                } else {
+                    "\t".to_string()
+                };
+                self.insert_text(&text, cursor, buffer);
             }
+            KeyCode::Backspace => match Self::soft_tab_start(cursor, buffer) {
+                // With softtabstop, spaces go back to the previous stop at once
+                Some(start) => self.delete_before_cursor(start, cursor, buffer),
+                None => self.backspace(cursor, buffer, context.pairs),
+            },
             KeyCode::Enter => {
-                // Get current line to determine indentation
+                self.inserted.push('\n');
+                let settings = &buffer.indent_settings();
                 let current_line = buffer.get_line(cursor.line).map_or("", |v| v);
-                let base_indent = Self::get_line_indent(current_line);
-                
-                // Check if we're after an opening bracket/brace for auto-indent
-                let extra_indent = if cursor.col > 0 {
-                    let char_before = current_line.chars().nth(cursor.col - 1);
-                    match char_before {
-                        Some('{') | Some('(') | Some('[') => 4, // Add extra indentation
-                        _ => 0,
-                    }
-                } else {
+                let (before, after) = current_line.split_at(cursor.col.min(current_line.len()));
+                // Enter between a pair like {} puts the closer on its own line below
+                let opens_block = before.chars().next_back().and_then(|c| context.pairs.closer(c)).is_some_and(|close| {
+                    context.pairs.is_closer(close) && after.trim_start().starts_with(close)
+                });
+                let inner_width = indent::indent_after(Some(before), "", indent::buffer_rules(buffer), settings);
+                let width = if !buffer.options().bool("autoindent") {
                     0
+                } else if before.trim().is_empty() {
+                    indent::indent_width(before, settings.tab_stop)
+                } else {
+                    let rules = indent::buffer_rules(buffer);
+                    indent::indent_after(Some(before), after, rules, settings)
                 };
-                
+
+                // The text carried onto the new line starts at the new indent
+                let carried = indent::indent_len(after);
                 buffer.insert_newline(cursor.line, cursor.col);
+                self.shift_auto_closers(cursor.line, if opens_block { 2 } else { 1 });
                 cursor.line += 1;
-                cursor.col = 0;
-                cursor.desired_col = 0;
-                
-                // Add indentation to the new line
-                let total_indent = base_indent + extra_indent;
-                for _ in 0..total_indent {
-                    buffer.insert_char(cursor.line, cursor.col, ' ');
-                    cursor.col += 1;
+                if let Some(line) = buffer.get_line(cursor.line) {
+                    let line = line[carried..].to_string();
+                    buffer.set_line(cursor.line, line);
+                }
+                indent::set_indent(buffer, cursor.line, width, settings);
+                if opens_block {
+                    buffer.insert_newline(cursor.line, 0);
+                    indent::set_indent(buffer, cursor.line, inner_width, settings);
+                }
+                cursor.col = buffer.get_line(cursor.line).map_or(0, |line| indent::indent_len(line));
+                cursor.remember_col(buffer);
+            }
+            KeyCode::Delete => {
+                let len = buffer.get_line(cursor.line).map(|l| l.len()).unwrap_or(0);
+                if cursor.col < len {
+                    buffer.delete_char(cursor.line, cursor.col);
+                } else {
+                    buffer.join_lines(cursor.line);
+                }
+            }
+            KeyCode::Left => self.move_cursor(|c| c.move_left(buffer), cursor),
+            KeyCode::Right => self.move_cursor(|c| c.move_right(buffer), cursor),
+            KeyCode::Up => self.move_cursor(|c| c.move_up(buffer), cursor),
+            KeyCode::Down => self.move_cursor(|c| c.move_down(buffer), cursor),
+            KeyCode::Home => self.move_cursor(|c| c.move_line_start(), cursor),
+            KeyCode::End => self.move_cursor(|c| c.move_line_end(buffer), cursor),
+            KeyCode::PageDown => return InsertAction::ScrollPage(true),
+            KeyCode::PageUp => return InsertAction::ScrollPage(false),
+            _ => {}
+        }
+        InsertAction::None
+    }
+
+    fn handle_control_key(
+        &mut self,
+        key: KeyEvent,
+        cursor: &mut Cursor,
+        buffer: &mut Buffer,
+        context: &InsertContext,
+    ) -> InsertAction {
+        match key.code {
+            KeyCode::Char('w') => {
+                // Delete the word before the cursor
+                let line = buffer.get_line(cursor.line).map_or("", |v| v);
+                let before = &line[..cursor.col.min(line.len())];
+                let trimmed = before.trim_end();
+                let word_start = match trimmed.chars().next_back() {
+                    Some(last) => trimmed
+                        .char_indices()
+                        .rev()
+                        .take_while(|&(_, c)| !c.is_whitespace() && is_keyword(c) == is_keyword(last))
+                        .last()
+                        .map_or(trimmed.len(), |(i, _)| i),
+                    None => 0,
+                };
+                self.delete_before_cursor(word_start, cursor, buffer);
+            }
+            KeyCode::Char('u') => {
+                // Delete typed text back to the indent, then to the start of the line
+                let line = buffer.get_line(cursor.line).map_or("", |v| v);
+                let indent = indent::indent_len(line);
+                let start = if cursor.col > indent { indent } else { 0 };
+                self.delete_before_cursor(start, cursor, buffer);
+            }
+            KeyCode::Char('r') => self.pending = Some(Pending::Register),
+            KeyCode::Char('v') => self.pending = Some(Pending::Literal),
+            KeyCode::Char('a') => {
+                if let Some(register) = context.registers.get('.') {
+                    let text = register.lines.join("\n");
+                    self.insert_text(&text, cursor, buffer);
                 }
-                cursor.desired_col = cursor.col;
             }
-            KeyCode::Left => cursor.move_left(buffer),
-            KeyCode::Right => cursor.move_right(buffer),
-            KeyCode::Up => cursor.move_up(buffer),
-            KeyCode::Down => cursor.move_down(buffer),
+            KeyCode::Char('o') => return InsertAction::OneShotNormal,
+            KeyCode::Char('t') => Self::shift_indent(true, cursor, buffer),
+            KeyCode::Char('d') => Self::shift_indent(false, cursor, buffer),
             _ => {}
         }
-        None
+        InsertAction::None
     }
 
-    fn get_closing_char(opening: char) -> Option<char> {
-        match opening {
-            '(' => Some(')'),
-            '[' => Some(']'),
-            '{' => Some('}'),
-            '"' => Some('"'),
-            '\'' => Some('\''),
-            '`' => Some('`'),
-            _ => None,
+    fn backspace(&mut self, cursor: &mut Cursor, buffer: &mut Buffer, pairs: &AutoPairs) {
+        self.inserted.pop();
+        if cursor.col > 0 {
+            let current_line = buffer.get_line(cursor.line).map_or("", |v| v);
+            let (before, after) = current_line.split_at(cursor.col);
+            let previous = before.chars().next_back().unwrap_or_default();
+
+            // Deleting the opener of an empty pair deletes its closer too
+            if pairs.closer(previous).is_some_and(|close| after.starts_with(close)) {
+                self.take_auto_closer(cursor, buffer);
+                buffer.delete_char(cursor.line, cursor.col);
+            }
+            cursor.col -= previous.len_utf8();
+            buffer.delete_char(cursor.line, cursor.col);
+            cursor.remember_col(buffer);
+        } else if cursor.line > 0 {
+            // Join with previous line
+            if let Some(prev_line) = buffer.get_line(cursor.line - 1) {
+                let prev_len = prev_line.len();
+                buffer.join_lines(cursor.line - 1);
+                cursor.line -= 1;
+                cursor.col = prev_len;
+                cursor.remember_col(buffer);
+            }
+        }
+    }
+
+    // Insert a typed character, adding the closer when it opens a pair. A closer
+    // this insert added is typed over instead of doubled. Quotes are not paired
+    // right after a letter or digit, as in "don't".
+    fn type_char(&mut self, c: char, cursor: &mut Cursor, buffer: &mut Buffer, pairs: &AutoPairs) {
+        let line = buffer.get_line(cursor.line).map_or("", |v| v);
+        let (before, after) = line.split_at(cursor.col.min(line.len()));
+        let closes = pairs.is_closer(c) || pairs.closer(c) == Some(c);
+        if closes && after.starts_with(c) && self.take_auto_closer(cursor, buffer) {
+            cursor.col += c.len_utf8();
+            cursor.remember_col(buffer);
+            return;
+        }
+
+        let after_word = before.chars().next_back().is_some_and(char::is_alphanumeric);
+        let closer = pairs.closer(c).filter(|&close| close != c || !after_word);
+        buffer.insert_char(cursor.line, cursor.col, c);
+        cursor.col += c.len_utf8();
+        cursor.remember_col(buffer);
+        if let Some(close) = closer {
+            buffer.insert_char(cursor.line, cursor.col, close);
+            let line_len = buffer.get_line(cursor.line).map_or(0, |line| line.len());
+            self.auto_closers.push((cursor.line, line_len - cursor.col));
+        }
+        if !c.is_whitespace() {
+            self.wrap_at_text_width(cursor, buffer);
+        }
+    }
+
+    // With textwidth set, a word typed past it moves to a new line with the
+    // line's indent, breaking at the last space that keeps the line within it
+    fn wrap_at_text_width(&mut self, cursor: &mut Cursor, buffer: &mut Buffer) {
+        let text_width = buffer.options().number("textwidth");
+        let settings = buffer.indent_settings();
+        let line = match buffer.get_line(cursor.line) {
+            Some(line) => line,
+            None => return,
+        };
+        if text_width == 0 || indent::display_col(line, cursor.col, settings.tab_stop) <= text_width {
+            return;
+        }
+        let indent_len = indent::indent_len(line);
+        let spaces: Vec<usize> = line[indent_len..cursor.col]
+            .match_indices(' ')
+            .map(|(i, _)| indent_len + i)
+            .filter(|&i| i == indent_len || line.as_bytes()[i - 1] != b' ')
+            .collect();
+        let break_at = match spaces
+            .iter()
+            .rev()
+            .find(|&&i| indent::display_col(line, i, settings.tab_stop) <= text_width)
+            .or(spaces.last())
+        {
+            Some(&i) => i,
+            None => return,
+        };
+
+        let width = indent::indent_width(line, settings.tab_stop);
+        let rest = line[break_at..].trim_start_matches(' ').len();
+        let moved = line.len() - cursor.col; // Bytes after the cursor, which stay after it
+        buffer.insert_newline(cursor.line, break_at);
+        self.shift_auto_closers(cursor.line, 1);
+        cursor.line += 1;
+        if let Some(next) = buffer.get_line(cursor.line) {
+            let next = next[next.len() - rest..].to_string();
+            buffer.set_line(cursor.line, next);
+        }
+        indent::set_indent(buffer, cursor.line, width, &settings);
+        cursor.col = buffer.get_line(cursor.line).map_or(0, |line| line.len() - moved);
+        cursor.remember_col(buffer);
+    }
+
+    // Forget the auto-inserted closer right after the cursor, if there is one
+    fn take_auto_closer(&mut self, cursor: &Cursor, buffer: &Buffer) -> bool {
+        let line_len = buffer.get_line(cursor.line).map_or(0, |line| line.len());
+        let position = (cursor.line, line_len.saturating_sub(cursor.col));
+        match self.auto_closers.iter().rposition(|&closer| closer == position) {
+            Some(i) => {
+                self.auto_closers.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Enter split `line`: closers after the cursor move down `by` lines, and so
+    // do those on later lines
+    fn shift_auto_closers(&mut self, line: usize, by: usize) {
+        for closer in &mut self.auto_closers {
+            if closer.0 >= line {
+                closer.0 += by;
+            }
+        }
+    }
+
+    // Where Backspace stops when the cursor follows spaces and softtabstop is set
+    fn soft_tab_start(cursor: &Cursor, buffer: &Buffer) -> Option<usize> {
+        let settings = buffer.indent_settings();
+        let line = buffer.get_line(cursor.line)?;
+        if settings.soft_tab_stop == 0 || !line[..cursor.col.min(line.len())].ends_with(' ') {
+            return None;
         }
+        let col = indent::display_col(line, cursor.col, settings.tab_stop);
+        let target = (col - 1) / settings.soft_tab_stop * settings.soft_tab_stop;
+        let spaces = line[..cursor.col].len() - line[..cursor.col].trim_end_matches(' ').len();
+        Some(cursor.col - spaces.min(col - target))
     }
-    
-    fn get_line_indent(line: &str) -> usize {
-        let mut indent = 0;
-        for c in line.chars() {
-            if c == ' ' {
-                indent += 1;
+
+    // Waiting for the key after Ctrl-r or Ctrl-v, which is taken as is
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Text typed during this insert, cleared for the next one
+    pub fn take_inserted(&mut self) -> String {
+        std::mem::take(&mut self.inserted)
+    }
+
+    // Insert text as typed, without auto-pairing or auto-indent
+    fn insert_text(&mut self, text: &str, cursor: &mut Cursor, buffer: &mut Buffer) {
+        for c in text.chars() {
+            if c == '\n' {
+                buffer.insert_newline(cursor.line, cursor.col);
+                cursor.line += 1;
+                cursor.col = 0;
             } else {
-                break;
+                buffer.insert_char(cursor.line, cursor.col, c);
+                cursor.col += c.len_utf8();
             }
         }
-        indent
+        cursor.remember_col(buffer);
+        self.inserted.push_str(text);
+    }
+
+    // Replace an abbreviation typed just before the cursor with its expansion,
+    // buffer-local abbreviations first
+    fn expand_abbreviation(&mut self, cursor: &mut Cursor, buffer: &mut Buffer, abbreviations: &[&Abbreviations]) {
+        let line = match buffer.get_line(cursor.line) {
+            Some(line) => line,
+            None => return,
+        };
+        let mut layers = vec![buffer.abbreviations()];
+        layers.extend_from_slice(abbreviations);
+        let (start, rhs) = match Abbreviations::find_before(&layers, AbbrevMode::Insert, line, cursor.col) {
+            Some(found) => found,
+            None => return,
+        };
+
+        // Only text typed during this insert is expanded
+        let lhs = &line[start..cursor.col];
+        if !self.inserted.ends_with(lhs) {
+            return;
+        }
+        self.inserted.truncate(self.inserted.len() - lhs.len());
+        let mut line = line.clone();
+        line.replace_range(start..cursor.col, "");
+        buffer.set_line(cursor.line, line);
+        cursor.col = start;
+        self.insert_text(&rhs, cursor, buffer);
     }
-    
-    fn get_closing_char_for_deletion(line: &str, pos: usize) -> Option<char> {
-        if let Some(char_at_pos) = line.chars().nth(pos) {
-            match char_at_pos {
-                '(' => Some(')'),
-                '[' => Some(']'),
-                '{' => Some('}'),
-                '"' => Some('"'),
-                '\'' => Some('\''),
-                '`' => Some('`'),
-                _ => None,
+
+    fn delete_before_cursor(&mut self, start: usize, cursor: &mut Cursor, buffer: &mut Buffer) {
+        if cursor.col == 0 {
+            // Nothing left on this line, join with the previous one
+            if cursor.line > 0 {
+                let prev_len = buffer.get_line(cursor.line - 1).map(|l| l.len()).unwrap_or(0);
+                buffer.join_lines(cursor.line - 1);
+                cursor.line -= 1;
+                cursor.col = prev_len;
+                cursor.remember_col(buffer);
             }
+            return;
+        }
+
+        if let Some(line) = buffer.get_line(cursor.line) {
+            let mut line = line.clone();
+            let removed: String = line.drain(start..cursor.col).collect();
+            buffer.set_line(cursor.line, line);
+            for _ in removed.chars() {
+                self.inserted.pop();
+            }
+            cursor.col = start;
+            cursor.remember_col(buffer);
+        }
+    }
+
+    // Ctrl-t / Ctrl-d: move the line's indent to the next or previous shiftwidth multiple
+    fn shift_indent(increase: bool, cursor: &mut Cursor, buffer: &mut Buffer) {
+        let settings = &buffer.indent_settings();
+        let (width, old_len) = match buffer.get_line(cursor.line) {
+            Some(line) => (indent::indent_width(line, settings.tab_stop), indent::indent_len(line)),
+            None => return,
+        };
+        indent::set_indent(buffer, cursor.line, settings.shift(width, increase), settings);
+        let new_len = buffer.get_line(cursor.line).map_or(0, |line| indent::indent_len(line));
+        cursor.col = if cursor.col >= old_len {
+            cursor.col + new_len - old_len
         } else {
-            None
+            cursor.col.min(new_len)
+        };
+        cursor.remember_col(buffer);
+    }
+
+    // Typing the first word of a line that closes a block (`}`, `end`, `fi`,
+    // `</div>`, `else:`...) lines it up with the block's opening line
+    fn reindent_closing_line(cursor: &mut Cursor, buffer: &mut Buffer) {
+        let settings = &buffer.indent_settings();
+        let line = match buffer.get_line(cursor.line) {
+            Some(line) => line,
+            None => return,
+        };
+        let before = line[..cursor.col.min(line.len())].trim();
+        let rules = indent::buffer_rules(buffer);
+        if before.is_empty() || before.contains(char::is_whitespace) || !rules.dedents(line.trim()) {
+            return;
         }
+
+        let previous = (0..cursor.line)
+            .rev()
+            .filter_map(|i| buffer.get_line(i))
+            .find(|line| !line.trim().is_empty());
+        let width = indent::indent_after(previous.map(|l| l.as_str()), line, rules, settings);
+        let delta = indent::set_indent(buffer, cursor.line, width, settings);
+        cursor.col = (cursor.col as isize + delta).max(0) as usize;
+        cursor.remember_col(buffer);
     }
+
+    fn move_cursor<F: FnOnce(&mut Cursor)>(&mut self, motion: F, cursor: &mut Cursor) {
+        // Moving around starts a new insert, like vim
+        self.inserted.clear();
+        self.auto_closers.clear();
+        motion(cursor);
+    }
+
 }
 
 impl Default for InsertMode {
@@ -4,8 +4,8 @@ mod visual;
 pub mod command;
 
 pub use normal::{NormalMode, NormalAction};
-pub use insert::InsertMode;
-pub use visual::{VisualMode, VisualType};
+pub use insert::{InsertMode, InsertAction, InsertContext};
+pub use visual::{VisualMode, VisualType, VisualAction, BlockInsert};
 pub use command::CommandMode;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
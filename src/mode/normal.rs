@@ -1,12 +1,16 @@
 use crate::buffer::Buffer;
 use crate::cursor::Cursor;
+use crate::indent;
 use crate::mode::Mode;
+use crate::register::{Register, RegisterKind, Registers};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
+#[derive(Clone)]
 pub struct NormalMode {
     pending_operator: Option<char>,
-    yank_register: Vec<String>,
     operator: Option<Operator>,
+    register: Option<char>, // Register picked with "x for the next yank, delete or put
+    awaiting: Option<char>, // ", m, ' or `: the next key is a register or mark name
 }
 
 pub enum NormalAction {
@@ -15,22 +19,54 @@ pub enum NormalAction {
     StartSearch(bool), // true for forward, false for backward
     NextMatch,
     PrevMatch,
+    SearchWord { forward: bool, whole_word: bool }, // *, #, g* and g#
+    SelectMatch { forward: bool, operator: Option<char> }, // gn and gN, alone or after d, c or y
+    CommandWindow(char), // q:, q/ and q?, with the prompt whose history it lists
     WindowCommand,
+    AddCursorAtNextMatch,
+    AddCursorAbove,
+    AddCursorBelow,
+    RepeatLastChange,
+    Reindent(usize, usize), // = operator over these lines
+    Message(String),
+    ExCommand(String), // A command line to run, for & and g&
 }
 
+// The keys after Ctrl-w: key, command name and what it does
+const WINDOW_COMMANDS: &[(char, &str, &str)] = &[
+    ('h', "navigate_h", "Go to the window on the left"),
+    ('j', "navigate_j", "Go to the window below"),
+    ('k', "navigate_k", "Go to the window above"),
+    ('l', "navigate_l", "Go to the window on the right"),
+    ('w', "next_window", "Go to the next window"),
+    ('W', "prev_window", "Go to the previous window"),
+    ('s', "split_horizontal", "Split horizontally"),
+    ('v', "split_vertical", "Split vertically"),
+    ('c', "close_window", "Close the window"),
+    ('q', "close_window", "Close the window"),
+    ('o', "close_other_windows", "Close the other windows"),
+    ('+', "increase_height", "Increase the height"),
+    ('-', "decrease_height", "Decrease the height"),
+    ('>', "increase_width", "Increase the width"),
+    ('<', "decrease_width", "Decrease the width"),
+    ('=', "equal_size", "Make all windows the same size"),
+];
+
 #[derive(Clone, Copy, PartialEq)]
 enum Operator {
     Delete,
     Change,
     Yank,
+    Reindent,
 }
 
 impl NormalMode {
     pub fn new() -> Self {
         Self {
             pending_operator: None,
-            yank_register: Vec::new(),
             operator: None,
+            register: None,
+            awaiting: None,
         }
     }
 
@@ -39,13 +75,31 @@ impl NormalMode {
         key: KeyEvent,
         cursor: &mut Cursor,
         buffer: &mut Buffer,
+        registers: &mut Registers,
     ) -> NormalAction {
+        if let Some(prefix) = self.awaiting.take() {
+            return match key.code {
+                KeyCode::Char(name) if prefix == '"' => {
+                    self.register = Some(name);
+                    NormalAction::None
+                }
+                KeyCode::Char(name) => Self::mark_command(prefix, name, cursor, buffer),
+                _ => NormalAction::None,
+            };
+        }
+
+        // After q only :, / or ? means anything
+        if self.pending_operator == Some('q') && !matches!(key.code, KeyCode::Char(':' | '/' | '?')) {
+            self.pending_operator = None;
+            return NormalAction::None;
+        }
+
         // Handle Ctrl+r for redo
         if key.code == KeyCode::Char('r') && key.modifiers.contains(KeyModifiers::CONTROL) {
             if let Some((line, col)) = buffer.redo() {
                 cursor.line = line;
                 cursor.col = col;
-                cursor.desired_col = col;
+                cursor.remember_col(buffer);
             }
             return NormalAction::None;
         }
@@ -55,6 +109,21 @@ impl NormalMode {
             return NormalAction::WindowCommand;
         }
 
+        // Multiple cursors: Ctrl+n adds one at the next match of the word under
+        // the cursor, Alt+j/k or Ctrl+Down/Up add one below/above
+        if let Some(action) = Self::multi_cursor_action(&key) {
+            return action;
+        }
+
+        if self.operator == Some(Operator::Reindent) {
+            return self.reindent_motion(key, cursor, buffer);
+        }
+
+        // Handle Ctrl+v for visual block mode
+        if key.code == KeyCode::Char('v') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return NormalAction::ModeChange(Mode::Visual(crate::mode::VisualType::Block));
+        }
+
 
         match key.code {
             KeyCode::Char('h') => {
@@ -108,17 +177,22 @@ impl NormalMode {
                     cursor.move_word_forward(buffer);
                     let end_col = cursor.col;
                     
-                    if let Some(line) = buffer.get_line_mut(cursor.line) {
+                    if let Some(line) = buffer.get_line(cursor.line) {
                         if start_col < line.len() {
+                            let mut line = line.clone();
                             let deleted: String = line.drain(start_col..end_col.min(line.len())).collect();
+                            if op != Operator::Yank {
+                                buffer.set_line(cursor.line, line);
+                            }
                             if op == Operator::Yank || op == Operator::Change {
-                                self.yank_register = vec![deleted];
+                                registers.set(self.take_register(), Register::characterwise(deleted));
                             }
                         }
                     }
                     cursor.col = start_col;
-                    cursor.desired_col = start_col;
+                    cursor.remember_col(buffer);
                     self.operator = None;
+                    self.pending_operator = None;
                     
                     if op == Operator::Change {
                         return NormalAction::ModeChange(Mode::Insert);
@@ -133,15 +207,20 @@ impl NormalMode {
                 if let Some(op) = self.operator {
                     // Operator to end of line (d$, c$, y$)
                     let start_col = cursor.col;
-                    if let Some(line) = buffer.get_line_mut(cursor.line) {
+                    if let Some(line) = buffer.get_line(cursor.line) {
                         if start_col < line.len() {
+                            let mut line = line.clone();
                             let deleted: String = line.drain(start_col..).collect();
+                            if op != Operator::Yank {
+                                buffer.set_line(cursor.line, line);
+                            }
                             if op == Operator::Yank || op == Operator::Change {
-                                self.yank_register = vec![deleted];
+                                registers.set(self.take_register(), Register::characterwise(deleted));
                             }
                         }
                     }
                     self.operator = None;
+                    self.pending_operator = None;
                     
                     if op == Operator::Change {
                         return NormalAction::ModeChange(Mode::Insert);
@@ -179,6 +258,7 @@ impl NormalMode {
                         cursor.desired_col = 0;
                     }
                     self.pending_operator = None;
+                    self.operator = None;
                     return NormalAction::ModeChange(Mode::Insert);
                 } else if self.operator.is_some() {
                     // Already have an operator, ignore
@@ -192,7 +272,7 @@ impl NormalMode {
                 if self.pending_operator == Some('d') {
                     // dd - delete line
                     if let Some(line) = buffer.delete_line(cursor.line) {
-                        self.yank_register = vec![line];
+                        registers.set(self.take_register(), Register::linewise(vec![line]));
                     }
                     self.pending_operator = None;
                     self.operator = None;
@@ -208,7 +288,7 @@ impl NormalMode {
                 if self.pending_operator == Some('y') {
                     // yy - yank line
                     if let Some(line) = buffer.get_line(cursor.line) {
-                        self.yank_register = vec![line.clone()];
+                        registers.set(self.take_register(), Register::linewise(vec![line.clone()]));
                     }
                     self.pending_operator = None;
                     self.operator = None;
@@ -220,27 +300,25 @@ impl NormalMode {
                     self.pending_operator = Some('y');
                 }
             }
+            KeyCode::Char('=') => {
+                if self.operator.is_some() {
+                    self.operator = None;
+                    self.pending_operator = None;
+                } else {
+                    self.operator = Some(Operator::Reindent);
+                    self.pending_operator = Some('=');
+                }
+            }
             KeyCode::Char('p') => {
-                // Paste below
-                if !self.yank_register.is_empty() {
-                    for (i, line) in self.yank_register.iter().enumerate() {
-                        buffer.insert_newline(cursor.line + i, buffer.get_line(cursor.line + i).map(|l| l.len()).unwrap_or(0));
-                        if let Some(target) = buffer.get_line_mut(cursor.line + i + 1) {
-                            *target = line.clone();
-                        }
-                    }
-                    cursor.line += 1;
+                // Paste after cursor (below for whole lines)
+                if let Some(register) = registers.get(self.take_register()) {
+                    Self::put(register, true, cursor, buffer);
                 }
             }
             KeyCode::Char('P') => {
-                // Paste above
-                if !self.yank_register.is_empty() {
-                    for (i, line) in self.yank_register.iter().enumerate() {
-                        buffer.insert_newline(cursor.line + i, 0);
-                        if let Some(target) = buffer.get_line_mut(cursor.line + i) {
-                            *target = line.clone();
-                        }
-                    }
+                // Paste before cursor (above for whole lines)
+                if let Some(register) = registers.get(self.take_register()) {
+                    Self::put(register, false, cursor, buffer);
                 }
             }
             KeyCode::Char('v') => {
@@ -254,24 +332,56 @@ impl NormalMode {
                 if let Some((line, col)) = buffer.undo() {
                     cursor.line = line;
                     cursor.col = col;
-                    cursor.desired_col = col;
+                    cursor.remember_col(buffer);
                 }
             }
+            KeyCode::Char(prompt @ (':' | '/' | '?')) if self.pending_operator == Some('q') => {
+                self.pending_operator = None;
+                return NormalAction::CommandWindow(prompt);
+            }
+            KeyCode::Char('q') if !self.is_pending() => {
+                self.pending_operator = Some('q');
+            }
             KeyCode::Char('/') => {
                 return NormalAction::StartSearch(true);
             }
             KeyCode::Char('?') => {
                 return NormalAction::StartSearch(false);
             }
+            KeyCode::Char(key @ ('n' | 'N')) if self.pending_operator == Some('g') => {
+                let operator = self.operator.take().map(|op| match op {
+                    Operator::Delete => 'd',
+                    Operator::Change => 'c',
+                    Operator::Yank => 'y',
+                    Operator::Reindent => '=',
+                });
+                self.pending_operator = None;
+                return NormalAction::SelectMatch { forward: key == 'n', operator };
+            }
             KeyCode::Char('n') => {
                 return NormalAction::NextMatch;
             }
             KeyCode::Char('N') => {
                 return NormalAction::PrevMatch;
             }
+            KeyCode::Char(key @ ('*' | '#')) if self.operator.is_none() => {
+                let whole_word = self.pending_operator.take().is_none();
+                return NormalAction::SearchWord { forward: key == '*', whole_word };
+            }
             KeyCode::Char(':') => {
                 return NormalAction::ModeChange(Mode::Command);
             }
+            KeyCode::Char(prefix @ ('"' | 'm' | '\'' | '`')) if self.operator.is_none() => {
+                self.awaiting = Some(prefix);
+            }
+            KeyCode::Char('.') if !self.is_pending() => {
+                return NormalAction::RepeatLastChange;
+            }
+            // & repeats the last :s on this line, g& on every line with its flags
+            KeyCode::Char('&') if self.operator.is_none() && matches!(self.pending_operator, None | Some('g')) => {
+                let command = if self.pending_operator.take().is_some() { "%&&" } else { "&" };
+                return NormalAction::ExCommand(command.to_string());
+            }
             _ => {
                 self.pending_operator = None;
                 self.operator = None;
@@ -280,29 +390,230 @@ impl NormalMode {
         NormalAction::None
     }
 
+    // The motion after `=`: `==` for this line, j/k for two lines, G to the
+    // end of the buffer and gg to the start
+    fn reindent_motion(&mut self, key: KeyEvent, cursor: &Cursor, buffer: &Buffer) -> NormalAction {
+        let line = cursor.line;
+        let range = match key.code {
+            KeyCode::Char('g') if self.pending_operator != Some('g') => {
+                self.pending_operator = Some('g');
+                return NormalAction::None;
+            }
+            KeyCode::Char('g') => Some((0, line)),
+            KeyCode::Char('=') => Some((line, line)),
+            KeyCode::Char('j') => Some((line, (line + 1).min(buffer.line_count().saturating_sub(1)))),
+            KeyCode::Char('k') => Some((line.saturating_sub(1), line)),
+            KeyCode::Char('G') => Some((line, buffer.line_count().saturating_sub(1))),
+            _ => None,
+        };
+        self.operator = None;
+        self.pending_operator = None;
+        match range {
+            Some((start, end)) => NormalAction::Reindent(start, end),
+            None => NormalAction::None,
+        }
+    }
+
+    // m{a-z} sets a mark, '{mark} jumps to its line and `{mark} to its position
+    fn mark_command(prefix: char, name: char, cursor: &mut Cursor, buffer: &mut Buffer) -> NormalAction {
+        if prefix == 'm' {
+            if name.is_ascii_lowercase() {
+                buffer.set_mark(name, cursor.line, cursor.col);
+            }
+            return NormalAction::None;
+        }
+        let Some((line, col)) = buffer.mark(name) else {
+            return NormalAction::Message(format!("Mark not set: {}", name));
+        };
+        let line = line.min(buffer.line_count().saturating_sub(1));
+        let text = buffer.get_line(line).map_or("", |l| l.as_str());
+        cursor.line = line;
+        cursor.col = if prefix == '`' { col.min(text.len()) } else { text.len() - text.trim_start().len() };
+        cursor.remember_col(buffer);
+        NormalAction::None
+    }
+
+    fn take_register(&mut self) -> char {
+        self.register.take().unwrap_or('"')
+    }
+
+    // Whether a command is still being typed (operator, register or g prefix)
+    pub fn is_pending(&self) -> bool {
+        self.pending_operator.is_some() || self.operator.is_some() || self.awaiting.is_some() || self.register.is_some()
+    }
+
+    // Waiting for the motion of an operator such as d or =
+    pub fn has_operator(&self) -> bool {
+        self.operator.is_some()
+    }
+
+    // Waiting for a register name after " or a mark name after m, ' or `,
+    // which is taken as is
+    pub fn awaiting_name(&self) -> bool {
+        self.awaiting.is_some()
+    }
+
+    fn multi_cursor_action(key: &KeyEvent) -> Option<NormalAction> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Char('n') if ctrl => Some(NormalAction::AddCursorAtNextMatch),
+            KeyCode::Char('j') if alt => Some(NormalAction::AddCursorBelow),
+            KeyCode::Char('k') if alt => Some(NormalAction::AddCursorAbove),
+            KeyCode::Down if ctrl => Some(NormalAction::AddCursorBelow),
+            KeyCode::Up if ctrl => Some(NormalAction::AddCursorAbove),
+            _ => None,
+        }
+    }
+
+    // Whether a key should be replayed at every cursor when there are several,
+    // as opposed to acting once for the whole window (undo, search, modes...)
+    pub fn applies_at_every_cursor(&self, key: &KeyEvent) -> bool {
+        if self.operator.is_some() {
+            return true;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) || Self::multi_cursor_action(key).is_some() {
+            return false;
+        }
+        !matches!(
+            key.code,
+            KeyCode::Char('u') | KeyCode::Char('/') | KeyCode::Char('?') | KeyCode::Char('n')
+                | KeyCode::Char('N') | KeyCode::Char(':') | KeyCode::Char('v') | KeyCode::Char('V')
+                | KeyCode::Char('*') | KeyCode::Char('#') | KeyCode::Char('.') | KeyCode::Char('&') | KeyCode::Char('q')
+                | KeyCode::Esc
+        )
+    }
+
     pub fn handle_window_command(&mut self, key: KeyEvent) -> Option<String> {
         match key.code {
-            KeyCode::Char('h') => Some("navigate_h".to_string()),
-            KeyCode::Char('j') => Some("navigate_j".to_string()),
-            KeyCode::Char('k') => Some("navigate_k".to_string()),
-            KeyCode::Char('l') => Some("navigate_l".to_string()),
-            KeyCode::Char('w') => Some("next_window".to_string()),
-            KeyCode::Char('W') => Some("prev_window".to_string()),
-            KeyCode::Char('s') => Some("split_horizontal".to_string()),
-            KeyCode::Char('v') => Some("split_vertical".to_string()),
-            KeyCode::Char('c') | KeyCode::Char('q') => Some("close_window".to_string()),
-            KeyCode::Char('o') => Some("close_other_windows".to_string()),
-            KeyCode::Char('+') => Some("increase_height".to_string()),
-            KeyCode::Char('-') => Some("decrease_height".to_string()),
-            KeyCode::Char('>') => Some("increase_width".to_string()),
-            KeyCode::Char('<') => Some("decrease_width".to_string()),
-            KeyCode::Char('=') => Some("equal_size".to_string()),
+            KeyCode::Char(c) => WINDOW_COMMANDS.iter().find(|&&(k, _, _)| k == c).map(|&(_, cmd, _)| cmd.to_string()),
             _ => None,
         }
     }
 
-    pub fn yank_register(&self) -> &[String] {
-        &self.yank_register
+    // The prefix typed so far, for the key hint popup: an operator, g, " or a mark key
+    pub fn pending_prefix(&self) -> Option<char> {
+        self.awaiting.or(self.pending_operator)
+    }
+
+    // Keys that can follow a prefix key, with what they do
+    pub fn continuations(prefix: KeyEvent) -> Vec<(String, &'static str)> {
+        let table: &[(&str, &str)] = match prefix.code {
+            KeyCode::Char('w') if prefix.modifiers.contains(KeyModifiers::CONTROL) => {
+                return WINDOW_COMMANDS.iter().map(|&(key, _, desc)| (key.to_string(), desc)).collect();
+            }
+            KeyCode::Char('g') => &[
+                ("g", "Go to the first line"),
+                ("n", "Select the next match"),
+                ("N", "Select the previous match"),
+                ("*", "Search forward for the word under the cursor"),
+                ("#", "Search backward for the word under the cursor"),
+                ("&", "Repeat the last :s on every line"),
+            ],
+            KeyCode::Char('d') => &[("d", "Delete the line"), ("w", "Delete to the next word"), ("$", "Delete to the end of the line")],
+            KeyCode::Char('c') => &[("c", "Change the line"), ("w", "Change to the next word"), ("$", "Change to the end of the line")],
+            KeyCode::Char('y') => &[("y", "Yank the line"), ("w", "Yank to the next word"), ("$", "Yank to the end of the line")],
+            KeyCode::Char('=') => &[
+                ("=", "Reindent the line"),
+                ("j", "Reindent this line and the next"),
+                ("k", "Reindent this line and the previous"),
+                ("G", "Reindent to the last line"),
+                ("gg", "Reindent to the first line"),
+            ],
+            KeyCode::Char('"') => &[("\"", "Unnamed register"), ("a-z", "Named register"), (".", "Last inserted text")],
+            KeyCode::Char('q') => &[
+                (":", "Edit and run a command from the history"),
+                ("/", "Edit and search for a pattern from the history"),
+                ("?", "Edit and search backward for a pattern from the history"),
+            ],
+            KeyCode::Char('m') => &[("a-z", "Set a mark")],
+            KeyCode::Char('\'') => &[("a-z", "Go to the mark's line"), ("<", "Start of the last selection"), (">", "End of the last selection")],
+            KeyCode::Char('`') => &[("a-z", "Go to the mark"), ("<", "Start of the last selection"), (">", "End of the last selection")],
+            _ => &[],
+        };
+        table.iter().map(|&(key, desc)| (key.to_string(), desc)).collect()
+    }
+
+    fn put(register: &Register, after: bool, cursor: &mut Cursor, buffer: &mut Buffer) {
+        if register.is_empty() {
+            return;
+        }
+
+        buffer.begin_undo_group();
+        match register.kind {
+            RegisterKind::Linewise => {
+                let target = if after { cursor.line + 1 } else { cursor.line };
+                for (i, line) in register.lines.iter().enumerate() {
+                    buffer.insert_line(target + i, line.clone());
+                }
+                cursor.line = target;
+                cursor.col = 0;
+            }
+            RegisterKind::Characterwise => {
+                let mut line = buffer.get_line(cursor.line).cloned().unwrap_or_default();
+                let col = Self::put_column(&line, cursor.col, after);
+                let tail = line.split_off(col);
+                let last = register.lines.len() - 1;
+
+                line.push_str(&register.lines[0]);
+                if last == 0 {
+                    cursor.col = line.len().saturating_sub(1);
+                    line.push_str(&tail);
+                    buffer.set_line(cursor.line, line);
+                } else {
+                    buffer.set_line(cursor.line, line);
+                    for (i, text) in register.lines.iter().enumerate().skip(1) {
+                        let mut new_line = text.clone();
+                        if i == last {
+                            new_line.push_str(&tail);
+                        }
+                        buffer.insert_line(cursor.line + i, new_line);
+                    }
+                    cursor.col = col;
+                }
+            }
+            RegisterKind::Blockwise => {
+                let current = buffer.get_line(cursor.line).map_or("", |v| v);
+                let col = Self::put_column(current, cursor.col, after);
+                // Every line gets its piece in the same screen column
+                let tab_stop = buffer.indent_settings().tab_stop;
+                let screen_col = indent::display_col(current, col, tab_stop);
+                let width = register.width();
+
+                for (i, text) in register.lines.iter().enumerate() {
+                    let line_idx = cursor.line + i;
+                    if line_idx >= buffer.line_count() {
+                        buffer.insert_line(line_idx, String::new());
+                    }
+                    let mut line = buffer.get_line(line_idx).cloned().unwrap_or_default();
+                    let line_width = indent::display_col(&line, line.len(), tab_stop);
+                    if line_width < screen_col {
+                        line.push_str(&" ".repeat(screen_col - line_width));
+                    }
+                    let at = indent::byte_from_display_col(&line, screen_col, tab_stop);
+                    // Keep the block rectangular when text follows it
+                    let mut piece = text.clone();
+                    let piece_width = piece.chars().count();
+                    if line.len() > at && piece_width < width {
+                        piece.push_str(&" ".repeat(width - piece_width));
+                    }
+                    line.insert_str(at, &piece);
+                    buffer.set_line(line_idx, line);
+                }
+                cursor.col = col;
+            }
+        }
+        buffer.end_undo_group();
+        cursor.remember_col(buffer);
+    }
+
+    fn put_column(line: &str, col: usize, after: bool) -> usize {
+        let mut col = if after && !line.is_empty() { col + 1 } else { col };
+        col = col.min(line.len());
+        while !line.is_char_boundary(col) {
+            col += 1;
+        }
+        col
     }
 }
 
@@ -1,7 +1,9 @@
 use crate::buffer::Buffer;
 use crate::cursor::Cursor;
+use crate::indent::{byte_at_display_col, byte_from_display_col, display_col};
 use crate::mode::Mode;
-use crossterm::event::{KeyCode, KeyEvent};
+use crate::register::{Register, RegisterKind, Registers};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VisualType {
@@ -10,10 +12,77 @@ pub enum VisualType {
     Block,
 }
 
+pub enum VisualAction {
+    None,
+    ModeChange(Mode),
+    // Enter insert mode and repeat the typed text on every line of the block
+    // once insert mode ends. An undo group is left open for the editor to close.
+    BlockInsert(BlockInsert),
+    // Leave visual mode with one cursor on every selected line
+    SplitCursors(Vec<Cursor>),
+    // `=`: leave visual mode and reindent these lines
+    Reindent(usize, usize),
+}
+
 pub struct VisualMode {
     visual_type: VisualType,
     start_line: usize,
     start_col: usize,
+    to_eol: bool, // Block extends to the end of every line ($)
+}
+
+// Pending blockwise I/A/c, applied to the remaining lines when insert mode ends
+#[derive(Debug, Clone)]
+pub struct BlockInsert {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub col: usize,        // Where the text goes in the first line
+    screen_col: usize,     // and the screen column it goes in at on the others
+    pub to_eol: bool,
+    pub pad: bool,
+    original_len: usize,
+    original_line_count: usize,
+}
+
+impl BlockInsert {
+    pub fn apply(&self, buffer: &mut Buffer) {
+        if buffer.line_count() != self.original_line_count {
+            // A newline was typed, vim gives up on repeating the insert too
+            return;
+        }
+
+        let first = match buffer.get_line(self.start_line) {
+            Some(line) => line.clone(),
+            None => return,
+        };
+        let col = if self.to_eol { self.original_len } else { self.col };
+        if first.len() <= self.original_len || col > first.len() {
+            return;
+        }
+        let inserted = match first.get(col..col + first.len() - self.original_len) {
+            Some(text) => text.to_string(),
+            None => return,
+        };
+
+        let tab_stop = buffer.indent_settings().tab_stop;
+        for line_idx in self.start_line + 1..=self.end_line {
+            let mut line = match buffer.get_line(line_idx) {
+                Some(line) => line.clone(),
+                None => break,
+            };
+            let width = display_col(&line, line.len(), tab_stop);
+            if !self.to_eol && width < self.screen_col {
+                if !self.pad {
+                    // Short lines are left alone by blockwise insert
+                    continue;
+                }
+                line.push_str(&" ".repeat(self.screen_col - width));
+            }
+            let target = if self.to_eol { line.len() } else { byte_from_display_col(&line, self.screen_col, tab_stop) };
+            line.insert_str(target, &inserted);
+            buffer.set_line(line_idx, line);
+        }
+    }
 }
 
 impl VisualMode {
@@ -22,6 +91,7 @@ impl VisualMode {
             visual_type,
             start_line: cursor.line,
             start_col: cursor.col,
+            to_eol: false,
         }
     }
 
@@ -30,39 +100,220 @@ impl VisualMode {
         key: KeyEvent,
         cursor: &mut Cursor,
         buffer: &mut Buffer,
-    ) -> Option<Mode> {
+        registers: &mut Registers,
+    ) -> VisualAction {
+        // '< and '> follow the selection, so they hold it once the key ends visual mode
+        self.set_marks(cursor, buffer);
+
+        if key.code == KeyCode::Char('v') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return self.switch_type(VisualType::Block);
+        }
+
+        if key.code == KeyCode::Char('n') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return VisualAction::SplitCursors(self.split_cursors(cursor, buffer));
+        }
+
         match key.code {
             KeyCode::Esc => {
-                return Some(Mode::Normal);
+                return VisualAction::ModeChange(Mode::Normal);
             }
-            KeyCode::Char('h') => cursor.move_left(buffer),
+            KeyCode::Char('h') => self.move_horizontal(|c| c.move_left(buffer), cursor),
             KeyCode::Char('j') => cursor.move_down(buffer),
             KeyCode::Char('k') => cursor.move_up(buffer),
-            KeyCode::Char('l') => cursor.move_right(buffer),
-            KeyCode::Char('w') => cursor.move_word_forward(buffer),
-            KeyCode::Char('b') => cursor.move_word_backward(buffer),
-            KeyCode::Char('0') => cursor.move_line_start(),
-            KeyCode::Char('$') => cursor.move_line_end(buffer),
+            KeyCode::Char('l') => self.move_horizontal(|c| c.move_right(buffer), cursor),
+            KeyCode::Char('w') => self.move_horizontal(|c| c.move_word_forward(buffer), cursor),
+            KeyCode::Char('b') => self.move_horizontal(|c| c.move_word_backward(buffer), cursor),
+            KeyCode::Char('0') => self.move_horizontal(|c| c.move_line_start(), cursor),
+            KeyCode::Char('$') => {
+                cursor.move_line_end(buffer);
+                if self.visual_type == VisualType::Block {
+                    self.to_eol = true;
+                }
+            }
+            KeyCode::Char('o') => {
+                // Jump to the other end of the selection
+                let (line, col) = (cursor.line, cursor.col);
+                cursor.line = self.start_line;
+                cursor.col = self.start_col;
+                cursor.remember_col(buffer);
+                self.start_line = line;
+                self.start_col = col;
+            }
+            KeyCode::Char('v') => return self.switch_type(VisualType::Character),
+            KeyCode::Char('V') => return self.switch_type(VisualType::Line),
             KeyCode::Char('d') | KeyCode::Char('x') => {
-                // Delete selection
-                self.delete_selection(cursor, buffer);
-                return Some(Mode::Normal);
+                buffer.begin_undo_group();
+                let register = self.delete_selection(cursor, buffer);
+                buffer.end_undo_group();
+                registers.set('"', register);
+                return VisualAction::ModeChange(Mode::Normal);
+            }
+            KeyCode::Char(':') => return VisualAction::ModeChange(Mode::Command),
+            KeyCode::Char('=') => {
+                let (start, end) = self.line_range(cursor);
+                return VisualAction::Reindent(start, end);
             }
             KeyCode::Char('y') => {
-                // Yank selection (to be implemented with register system)
-                return Some(Mode::Normal);
+                registers.set('"', self.yank_selection(cursor, buffer));
+                let (start_line, start_col) = self.start_position(cursor, buffer);
+                cursor.line = start_line;
+                cursor.col = start_col;
+                cursor.remember_col(buffer);
+                return VisualAction::ModeChange(Mode::Normal);
+            }
+            KeyCode::Char('c') | KeyCode::Char('s') => {
+                buffer.begin_undo_group();
+                let (start_line, end_line) = self.line_range(cursor);
+                let (left, _) = self.block_columns(cursor, buffer);
+                if self.visual_type == VisualType::Line {
+                    // Keep the first line, emptied, to type into
+                    registers.set('"', self.yank_selection(cursor, buffer));
+                    for _ in start_line..end_line {
+                        buffer.delete_line(start_line + 1);
+                    }
+                    buffer.set_line(start_line, String::new());
+                    cursor.line = start_line;
+                    cursor.col = 0;
+                    cursor.desired_col = 0;
+                    buffer.end_undo_group();
+                    return VisualAction::ModeChange(Mode::Insert);
+                }
+                let register = self.delete_selection(cursor, buffer);
+                registers.set('"', register);
+
+                if self.visual_type == VisualType::Block {
+                    // delete_selection left the cursor where the block was
+                    return VisualAction::BlockInsert(self.block_insert(
+                        start_line, end_line, (cursor.col, left), false, false, buffer,
+                    ));
+                }
+                buffer.end_undo_group();
+                return VisualAction::ModeChange(Mode::Insert);
+            }
+            KeyCode::Char('I') if self.visual_type == VisualType::Block => {
+                let (start_line, end_line) = self.line_range(cursor);
+                let (left, _) = self.block_columns(cursor, buffer);
+                let first = buffer.get_line(start_line).map_or("", |l| l.as_str());
+                cursor.line = start_line;
+                cursor.col = byte_from_display_col(first, left, buffer.indent_settings().tab_stop);
+                cursor.remember_col(buffer);
+                buffer.begin_undo_group();
+                return VisualAction::BlockInsert(self.block_insert(
+                    start_line, end_line, (cursor.col, left), false, false, buffer,
+                ));
+            }
+            KeyCode::Char('A') if self.visual_type == VisualType::Block => {
+                let (start_line, end_line) = self.line_range(cursor);
+                let (_, right) = self.block_columns(cursor, buffer);
+                let to_eol = self.to_eol;
+                let tab_stop = buffer.indent_settings().tab_stop;
+                buffer.begin_undo_group();
+
+                // Appending past a short first line pads it out to the block edge
+                let mut first = buffer.get_line(start_line).cloned().unwrap_or_default();
+                let width = display_col(&first, first.len(), tab_stop);
+                if !to_eol && width <= right {
+                    first.push_str(&" ".repeat(right + 1 - width));
+                    buffer.set_line(start_line, first.clone());
+                }
+                let col = if to_eol { first.len() } else { byte_from_display_col(&first, right + 1, tab_stop) };
+                cursor.line = start_line;
+                cursor.col = col;
+                cursor.remember_col(buffer);
+                return VisualAction::BlockInsert(self.block_insert(
+                    start_line, end_line, (col, right + 1), to_eol, true, buffer,
+                ));
             }
             _ => {}
         }
-        None
+        VisualAction::None
     }
 
-    fn delete_selection(&self, cursor: &mut Cursor, buffer: &mut Buffer) {
-        let (start_line, end_line) = if self.start_line <= cursor.line {
-            (self.start_line, cursor.line)
-        } else {
-            (cursor.line, self.start_line)
-        };
+    // One cursor per selected line in the cursor's column, the current line first
+    fn split_cursors(&self, cursor: &Cursor, buffer: &Buffer) -> Vec<Cursor> {
+        let (start_line, end_line) = self.line_range(cursor);
+        let mut cursors = vec![*cursor];
+        for line in (start_line..=end_line).filter(|&l| l != cursor.line) {
+            let text = buffer.get_line(line).map_or("", |l| l.as_str());
+            let col = match self.to_eol {
+                true => text.len(),
+                false => byte_at_display_col(text, cursor.desired_col, buffer.indent_settings().tab_stop),
+            };
+            cursors.push(Cursor::at(line, col, buffer));
+        }
+        cursors
+    }
+
+    fn move_horizontal<F: FnOnce(&mut Cursor)>(&mut self, motion: F, cursor: &mut Cursor) {
+        motion(cursor);
+        self.to_eol = false;
+    }
+
+    fn switch_type(&mut self, visual_type: VisualType) -> VisualAction {
+        if self.visual_type == visual_type {
+            return VisualAction::ModeChange(Mode::Normal);
+        }
+        self.visual_type = visual_type;
+        VisualAction::ModeChange(Mode::Visual(visual_type))
+    }
+
+    // `col` is the byte in the first line and the screen column on the others
+    fn block_insert(
+        &self,
+        start_line: usize,
+        end_line: usize,
+        (col, screen_col): (usize, usize),
+        to_eol: bool,
+        pad: bool,
+        buffer: &Buffer,
+    ) -> BlockInsert {
+        BlockInsert {
+            start_line,
+            end_line,
+            col,
+            screen_col,
+            to_eol,
+            pad,
+            original_len: buffer.get_line(start_line).map(|l| l.len()).unwrap_or(0),
+            original_line_count: buffer.line_count(),
+        }
+    }
+
+    fn yank_selection(&self, cursor: &Cursor, buffer: &Buffer) -> Register {
+        let (start_line, end_line) = self.line_range(cursor);
+
+        match self.visual_type {
+            VisualType::Line => Register::linewise(
+                (start_line..=end_line)
+                    .filter_map(|i| buffer.get_line(i).cloned())
+                    .collect(),
+            ),
+            VisualType::Character => {
+                let ((start_line, start_col), (end_line, end_col)) = self.char_bounds(cursor);
+                let mut lines = Vec::new();
+                for line_idx in start_line..=end_line {
+                    let line = buffer.get_line(line_idx).map_or("", |v| v);
+                    let from = if line_idx == start_line { start_col } else { 0 };
+                    let to = if line_idx == end_line { end_col + 1 } else { line.len() };
+                    lines.push(slice(line, from, to).to_string());
+                }
+                Register::new(RegisterKind::Characterwise, lines)
+            }
+            VisualType::Block => Register::blockwise(
+                (start_line..=end_line)
+                    .map(|i| {
+                        let line = buffer.get_line(i).map_or("", |v| v);
+                        let (from, to) = self.block_span(cursor, buffer, i);
+                        line[from..to].to_string()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn delete_selection(&self, cursor: &mut Cursor, buffer: &mut Buffer) -> Register {
+        let register = self.yank_selection(cursor, buffer);
+        let (start_line, end_line) = self.line_range(cursor);
 
         match self.visual_type {
             VisualType::Line => {
@@ -73,22 +324,132 @@ impl VisualMode {
                 cursor.col = 0;
             }
             VisualType::Character => {
-                // Simplified character-wise deletion
+                let ((start_line, start_col), (end_line, end_col)) = self.char_bounds(cursor);
+                let first = buffer.get_line(start_line).cloned().unwrap_or_default();
+                let last = buffer.get_line(end_line).cloned().unwrap_or_default();
+                let start_col = floor_boundary(&first, start_col);
+                let mut end_col = floor_boundary(&last, (end_col + 1).min(last.len()));
                 if start_line == end_line {
-                    let (start_col, end_col) = if self.start_col <= cursor.col {
-                        (self.start_col, cursor.col)
-                    } else {
-                        (cursor.col, self.start_col)
-                    };
-                    
-                    if let Some(line) = buffer.get_line_mut(start_line) {
-                        line.drain(start_col..=end_col.min(line.len().saturating_sub(1)));
+                    end_col = end_col.max(start_col);
+                }
+                let joined = format!("{}{}", &first[..start_col], &last[end_col..]);
+
+                for _ in start_line..end_line {
+                    buffer.delete_line(start_line + 1);
+                }
+                buffer.set_line(start_line, joined);
+                cursor.line = start_line;
+                cursor.col = start_col;
+            }
+            VisualType::Block => {
+                let spans: Vec<(usize, usize)> = (start_line..=end_line).map(|i| self.block_span(cursor, buffer, i)).collect();
+                for (line_idx, (from, to)) in (start_line..=end_line).zip(spans.iter().copied()) {
+                    let line = buffer.get_line(line_idx).cloned().unwrap_or_default();
+                    if from < to {
+                        buffer.set_line(line_idx, format!("{}{}", &line[..from], &line[to..]));
                     }
-                    cursor.col = start_col;
                 }
+                cursor.line = start_line;
+                cursor.col = spans[0].0;
+            }
+        }
+        cursor.remember_col(buffer);
+        register
+    }
+
+    // Whether the character at (line, col) is part of the selection
+    pub fn contains(&self, cursor: &Cursor, buffer: &Buffer, line: usize, col: usize) -> bool {
+        let (start_line, end_line) = self.line_range(cursor);
+        if line < start_line || line > end_line {
+            return false;
+        }
+
+        match self.visual_type {
+            VisualType::Line => true,
+            VisualType::Character => {
+                let ((start_line, start_col), (end_line, end_col)) = self.char_bounds(cursor);
+                (line > start_line || col >= start_col) && (line < end_line || col <= end_col)
             }
             VisualType::Block => {
-                // Block mode to be implemented
+                let (from, to) = self.block_span(cursor, buffer, line);
+                (from..to).contains(&col)
+            }
+        }
+    }
+
+    fn line_range(&self, cursor: &Cursor) -> (usize, usize) {
+        if self.start_line <= cursor.line {
+            (self.start_line, cursor.line)
+        } else {
+            (cursor.line, self.start_line)
+        }
+    }
+
+    fn set_marks(&self, cursor: &Cursor, buffer: &mut Buffer) {
+        let (start, end) = match self.visual_type {
+            VisualType::Character => self.char_bounds(cursor),
+            VisualType::Line => {
+                let (start, end) = self.line_range(cursor);
+                ((start, 0), (end, buffer.get_line(end).map_or(0, |l| l.len())))
+            }
+            VisualType::Block => {
+                let (start, end) = self.line_range(cursor);
+                let (from, _) = self.block_span(cursor, buffer, start);
+                let (_, to) = self.block_span(cursor, buffer, end);
+                ((start, from), (end, to.saturating_sub(1)))
+            }
+        };
+        buffer.set_mark('<', start.0, start.1);
+        buffer.set_mark('>', end.0, end.1);
+    }
+
+    // Start and end positions in buffer order, end inclusive
+    fn char_bounds(&self, cursor: &Cursor) -> ((usize, usize), (usize, usize)) {
+        let anchor = (self.start_line, self.start_col);
+        let head = (cursor.line, cursor.col);
+        if anchor <= head {
+            (anchor, head)
+        } else {
+            (head, anchor)
+        }
+    }
+
+    // The first and last screen column of the block. A corner on a tab
+    // takes in every column the tab covers.
+    fn block_columns(&self, cursor: &Cursor, buffer: &Buffer) -> (usize, usize) {
+        let tab_stop = buffer.indent_settings().tab_stop;
+        let cells = |line: usize, col: usize| {
+            let text = buffer.get_line(line).map_or("", |l| l.as_str());
+            let start = display_col(text, col, tab_stop);
+            let end = match text.get(col..).and_then(|rest| rest.chars().next()) {
+                Some(c) => display_col(text, col + c.len_utf8(), tab_stop) - 1,
+                None => start,
+            };
+            (start, end)
+        };
+        let (anchor_start, anchor_end) = cells(self.start_line, self.start_col);
+        let (head_start, head_end) = cells(cursor.line, cursor.col);
+        (anchor_start.min(head_start), anchor_end.max(head_end))
+    }
+
+    // The bytes of `line` inside the block: every character with a screen
+    // column in it, up to the end of the line with $
+    fn block_span(&self, cursor: &Cursor, buffer: &Buffer, line: usize) -> (usize, usize) {
+        let (left, right) = self.block_columns(cursor, buffer);
+        let tab_stop = buffer.indent_settings().tab_stop;
+        let text = buffer.get_line(line).map_or("", |l| l.as_str());
+        let from = byte_at_display_col(text, left, tab_stop);
+        let to = if self.to_eol { text.len() } else { byte_from_display_col(text, right + 1, tab_stop) };
+        (from, to.max(from))
+    }
+
+    fn start_position(&self, cursor: &Cursor, buffer: &Buffer) -> (usize, usize) {
+        match self.visual_type {
+            VisualType::Character => self.char_bounds(cursor).0,
+            VisualType::Line => (self.line_range(cursor).0, 0),
+            VisualType::Block => {
+                let line = self.line_range(cursor).0;
+                (line, self.block_span(cursor, buffer, line).0)
             }
         }
     }
@@ -114,3 +475,17 @@ impl VisualMode {
     }
 }
 
+// Byte slice of a line clamped to its length and char boundaries
+fn slice(line: &str, from: usize, to: usize) -> &str {
+    let to = floor_boundary(line, to.min(line.len()));
+    let from = floor_boundary(line, from.min(to));
+    &line[from..to]
+}
+
+fn floor_boundary(line: &str, mut col: usize) -> usize {
+    col = col.min(line.len());
+    while !line.is_char_boundary(col) {
+        col -= 1;
+    }
+    col
+}
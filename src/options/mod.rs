@@ -0,0 +1,299 @@
+use crate::config::Config;
+use std::collections::HashMap;
+use std::fmt;
+
+mod modeline;
+
+pub use modeline::modeline_settings;
+
+// What an option's value belongs to. Buffer and window options have a global
+// value too, which new buffers and windows start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Buffer,
+    Window,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Bool(bool),
+    Number(usize),
+    String(String),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Number(value) => write!(f, "{}", value),
+            OptionValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// Built-in value of an option, before the config file
+#[derive(Debug, Clone, Copy)]
+pub enum Initial {
+    Bool(bool),
+    Number(usize),
+    String(&'static str),
+}
+
+impl Initial {
+    pub fn value(self) -> OptionValue {
+        match self {
+            Initial::Bool(value) => OptionValue::Bool(value),
+            Initial::Number(value) => OptionValue::Number(value),
+            Initial::String(value) => OptionValue::String(value.to_string()),
+        }
+    }
+}
+
+pub struct OptionDef {
+    pub name: &'static str,
+    pub short: Option<&'static str>,
+    pub scope: Scope,
+    pub initial: Initial,
+}
+
+impl OptionDef {
+    pub fn is_bool(&self) -> bool {
+        matches!(self.initial, Initial::Bool(_))
+    }
+
+    // Parse the text after `=` in `:set name=value`
+    pub fn parse(&self, text: &str) -> Result<OptionValue, String> {
+        match self.initial {
+            Initial::Bool(_) => Err(format!("Invalid argument: {}={}", self.name, text)),
+            Initial::Number(_) => match text.parse() {
+                Ok(0) if self.name == "tabstop" => Err(format!("Argument must be positive: {}={}", self.name, text)),
+                Ok(n) => Ok(OptionValue::Number(n)),
+                Err(_) => Err(format!("Number required after =: {}={}", self.name, text)),
+            },
+            Initial::String(_) if !self.choices().is_empty() && !self.choices().contains(&text) => Err(format!(
+                "Invalid argument: {}={} (one of: {})",
+                self.name,
+                text,
+                self.choices().join(", ")
+            )),
+            Initial::String(_) => Ok(OptionValue::String(text.to_string())),
+        }
+    }
+
+    // The values a string option is limited to, empty when it takes any text
+    pub fn choices(&self) -> &'static [&'static str] {
+        match self.name {
+            "fileencoding" => &["utf-8", "utf-8-bom", "latin1", "utf-16be", "utf-16le"],
+            "fileformat" => &["unix", "dos", "mac"],
+            "inccommand" => &["", "nosplit", "split"],
+            "indentrules" => &["", "braces", "python", "shell", "ruby", "lua", "markup", "yaml", "plain"],
+            _ => &[],
+        }
+    }
+
+    // A value from a TOML table such as a [filetype.<name>] section
+    pub fn value_from_toml(&self, value: &toml::Value) -> Result<OptionValue, String> {
+        match (self.initial, value) {
+            (Initial::Bool(_), toml::Value::Boolean(value)) => Ok(OptionValue::Bool(*value)),
+            (Initial::Number(_), toml::Value::Integer(value)) => self.parse(&value.to_string()),
+            (Initial::String(_) | Initial::Number(_), toml::Value::String(value)) => self.parse(value),
+            _ => Err(format!("Invalid value for {}: {}", self.name, value)),
+        }
+    }
+
+    // `name=value`, or `name` / `noname` for booleans, as :set shows it
+    pub fn show(&self, value: &OptionValue) -> String {
+        match value {
+            OptionValue::Bool(true) => self.name.to_string(),
+            OptionValue::Bool(false) => format!("no{}", self.name),
+            value => format!("{}={}", self.name, value),
+        }
+    }
+}
+
+// Every option, sorted by name
+pub const OPTIONS: &[OptionDef] = &[
+    OptionDef { name: "autoindent", short: Some("ai"), scope: Scope::Buffer, initial: Initial::Bool(true) },
+    OptionDef { name: "autopairs", short: Some("ap"), scope: Scope::Buffer, initial: Initial::Bool(true) },
+    OptionDef { name: "colorscheme", short: Some("colo"), scope: Scope::Global, initial: Initial::String("default") },
+    OptionDef { name: "commentstring", short: Some("cms"), scope: Scope::Buffer, initial: Initial::String("") },
+    OptionDef { name: "detectindent", short: Some("di"), scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "dictionary", short: Some("dict"), scope: Scope::Global, initial: Initial::String("") },
+    OptionDef { name: "expandtab", short: Some("et"), scope: Scope::Buffer, initial: Initial::Bool(true) },
+    OptionDef { name: "fileencoding", short: Some("fenc"), scope: Scope::Buffer, initial: Initial::String("utf-8") },
+    OptionDef { name: "fileformat", short: Some("ff"), scope: Scope::Buffer, initial: Initial::String("unix") },
+    OptionDef { name: "filetype", short: Some("ft"), scope: Scope::Buffer, initial: Initial::String("") },
+    OptionDef { name: "hlsearch", short: Some("hls"), scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "ignorecase", short: Some("ic"), scope: Scope::Global, initial: Initial::Bool(false) },
+    OptionDef { name: "inccommand", short: Some("icm"), scope: Scope::Global, initial: Initial::String("nosplit") },
+    OptionDef { name: "incsearch", short: Some("is"), scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "indentrules", short: None, scope: Scope::Buffer, initial: Initial::String("") },
+    OptionDef { name: "insertfinalnewline", short: Some("ifn"), scope: Scope::Buffer, initial: Initial::Bool(false) },
+    OptionDef { name: "keyhintdelay", short: None, scope: Scope::Global, initial: Initial::Number(500) },
+    OptionDef { name: "keyhints", short: None, scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "mapleader", short: None, scope: Scope::Global, initial: Initial::String("\\") },
+    OptionDef { name: "modeline", short: Some("ml"), scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "modelines", short: Some("mls"), scope: Scope::Global, initial: Initial::Number(5) },
+    OptionDef { name: "number", short: Some("nu"), scope: Scope::Window, initial: Initial::Bool(true) },
+    OptionDef { name: "shiftwidth", short: Some("sw"), scope: Scope::Buffer, initial: Initial::Number(0) },
+    OptionDef { name: "showstatus", short: None, scope: Scope::Global, initial: Initial::Bool(true) },
+    OptionDef { name: "smartcase", short: Some("scs"), scope: Scope::Global, initial: Initial::Bool(false) },
+    OptionDef { name: "softtabstop", short: Some("sts"), scope: Scope::Buffer, initial: Initial::Number(0) },
+    OptionDef { name: "tabstop", short: Some("ts"), scope: Scope::Buffer, initial: Initial::Number(4) },
+    OptionDef { name: "textwidth", short: Some("tw"), scope: Scope::Buffer, initial: Initial::Number(0) },
+    OptionDef { name: "timeoutlen", short: Some("tm"), scope: Scope::Global, initial: Initial::Number(1000) },
+    OptionDef { name: "trimtrailingwhitespace", short: Some("ttw"), scope: Scope::Buffer, initial: Initial::Bool(false) },
+];
+
+// An option by its full or short name
+pub fn find(name: &str) -> Option<&'static OptionDef> {
+    OPTIONS.iter().find(|def| def.name == name || def.short == Some(name))
+}
+
+// Option values, where one never set has its built-in value. Buffers and
+// windows hold their own copy of the options of their scope.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    values: HashMap<&'static str, OptionValue>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Global values from the [editor] and [ui] sections of the config
+    pub fn from_config(config: &Config) -> Self {
+        let editor = &config.editor;
+        let mut options = Self::new();
+        options.set("autoindent", OptionValue::Bool(editor.auto_indent));
+        options.set("autopairs", OptionValue::Bool(editor.auto_pairs));
+        options.set("colorscheme", OptionValue::String(config.ui.color_scheme.clone()));
+        options.set("detectindent", OptionValue::Bool(editor.detect_indent));
+        options.set("dictionary", OptionValue::String(editor.dictionary.clone().unwrap_or_default()));
+        options.set("expandtab", OptionValue::Bool(editor.expand_tabs));
+        options.set("mapleader", OptionValue::String(editor.leader.clone()));
+        options.set("number", OptionValue::Bool(editor.line_numbers));
+        options.set("shiftwidth", OptionValue::Number(editor.shift_width));
+        options.set("showstatus", OptionValue::Bool(config.ui.show_status_line));
+        options.set("softtabstop", OptionValue::Number(editor.soft_tab_stop));
+        options.set("tabstop", OptionValue::Number(editor.tab_size.max(1)));
+        options.set("timeoutlen", OptionValue::Number(editor.timeout_len));
+        options
+    }
+
+    // The values of the options of one scope, to start a new buffer or window with
+    pub fn local(&self, scope: Scope) -> Self {
+        let values = self
+            .values
+            .iter()
+            .filter(|(name, _)| find(name).is_some_and(|def| def.scope == scope))
+            .map(|(&name, value)| (name, value.clone()))
+            .collect();
+        Self { values }
+    }
+
+    pub fn get(&self, def: &'static OptionDef) -> OptionValue {
+        self.values.get(def.name).cloned().unwrap_or_else(|| def.initial.value())
+    }
+
+    pub fn set(&mut self, name: &'static str, value: OptionValue) {
+        self.values.insert(name, value);
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        match self.value(name) {
+            Some(OptionValue::Bool(value)) => value,
+            _ => false,
+        }
+    }
+
+    pub fn number(&self, name: &str) -> usize {
+        match self.value(name) {
+            Some(OptionValue::Number(value)) => value,
+            _ => 0,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> String {
+        match self.value(name) {
+            Some(OptionValue::String(value)) => value,
+            _ => String::new(),
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<OptionValue> {
+        find(name).map(|def| self.get(def))
+    }
+}
+
+// Option values to apply together, in order
+pub type Settings = Vec<(&'static OptionDef, OptionValue)>;
+
+// Buffer option values from a [filetype.<name>] section or an ftplugin file
+pub fn parse_table(table: &toml::Table) -> Result<Settings, String> {
+    table
+        .iter()
+        .map(|(name, value)| {
+            let def = find(name).ok_or_else(|| format!("Unknown option: {}", name))?;
+            if def.scope != Scope::Buffer || def.name == "filetype" {
+                return Err(format!("Not a filetype option: {}", name));
+            }
+            Ok((def, def.value_from_toml(value)?))
+        })
+        .collect()
+}
+
+// One argument of :set
+pub enum SetArg {
+    Show(&'static OptionDef),             // `name?`, or `name` for a non-boolean
+    Set(&'static OptionDef, OptionValue), // `name`, `noname`, `name=value`
+    Toggle(&'static OptionDef),           // `invname` or `name!`
+    All,                                  // `all`
+}
+
+impl SetArg {
+    // The arguments of `:set`, separated by spaces; `\ ` is a space in a value
+    pub fn parse_all(args: &str) -> Result<Vec<Self>, String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut chars = args.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => word.extend(chars.next()),
+                ' ' => words.extend((!word.is_empty()).then(|| std::mem::take(&mut word))),
+                c => word.push(c),
+            }
+        }
+        words.extend((!word.is_empty()).then_some(word));
+        words.iter().map(|word| Self::parse(word)).collect()
+    }
+
+    fn parse(arg: &str) -> Result<Self, String> {
+        if arg == "all" {
+            return Ok(SetArg::All);
+        }
+        let unknown = || format!("Unknown option: {}", arg);
+        if let Some((name, value)) = arg.split_once(['=', ':']) {
+            let def = find(name).ok_or_else(unknown)?;
+            return Ok(SetArg::Set(def, def.parse(value)?));
+        }
+        if let Some(name) = arg.strip_suffix('?') {
+            return find(name).map(SetArg::Show).ok_or_else(unknown);
+        }
+        if let Some(def) = arg.strip_suffix('!').and_then(find).filter(|def| def.is_bool()) {
+            return Ok(SetArg::Toggle(def));
+        }
+        if let Some(def) = find(arg) {
+            return Ok(if def.is_bool() { SetArg::Set(def, OptionValue::Bool(true)) } else { SetArg::Show(def) });
+        }
+        if let Some(def) = arg.strip_prefix("no").and_then(find).filter(|def| def.is_bool()) {
+            return Ok(SetArg::Set(def, OptionValue::Bool(false)));
+        }
+        if let Some(def) = arg.strip_prefix("inv").and_then(find).filter(|def| def.is_bool()) {
+            return Ok(SetArg::Toggle(def));
+        }
+        Err(unknown())
+    }
+}
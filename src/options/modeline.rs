@@ -0,0 +1,224 @@
+use super::{find, OptionDef, OptionValue, SetArg, Settings};
+
+// Options a modeline may set. They only change how a buffer is shown, edited
+// and written, so opening a file can never run anything.
+const ALLOWED: &[&str] = &[
+    "autoindent",
+    "autopairs",
+    "commentstring",
+    "expandtab",
+    "fileencoding",
+    "fileformat",
+    "filetype",
+    "indentrules",
+    "insertfinalnewline",
+    "shiftwidth",
+    "softtabstop",
+    "tabstop",
+    "textwidth",
+    "trimtrailingwhitespace",
+];
+
+// [editor] config keys accepted in `avim:` modelines, besides option names
+const CONFIG_NAMES: &[(&str, &str)] = &[
+    ("auto_indent", "autoindent"),
+    ("auto_pairs", "autopairs"),
+    ("expand_tabs", "expandtab"),
+    ("shift_width", "shiftwidth"),
+    ("soft_tab_stop", "softtabstop"),
+    ("tab_size", "tabstop"),
+];
+
+// Settings from the modelines in the first and last `count` lines, in order.
+// The first option that is unknown or not allowed is reported as an error.
+pub fn modeline_settings(lines: &[&str], count: usize) -> (Settings, Option<String>) {
+    let head = lines.len().min(count);
+    let tail = lines.len().saturating_sub(count).max(head);
+    let mut settings = Settings::new();
+    let mut error = None;
+    for line in lines[..head].iter().chain(&lines[tail..]) {
+        let result = if let Some(args) = vim_modeline(line) {
+            args.iter().try_for_each(|arg| set_arg(arg, &mut settings))
+        } else if let Some(args) = avim_modeline(line) {
+            args.iter().try_for_each(|arg| config_arg(arg, &mut settings))
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            error.get_or_insert(e);
+        }
+    }
+    (settings, error)
+}
+
+// `vim: ts=2 sw=2 et` (options up to the end of the line, separated by spaces
+// or colons) or `vim: set ts=2 sw=2 et:` (options up to the next colon, so the
+// rest of a comment can follow). `vi:`, `Vim:` and `ex:` also start one.
+fn vim_modeline(line: &str) -> Option<Vec<String>> {
+    let rest = ["vim:", "Vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker).find_map(|(i, _)| {
+            let at_start = line[..i].chars().next_back().is_none_or(char::is_whitespace);
+            (at_start && !(i == 0 && *marker == "ex:")).then(|| &line[i + marker.len()..])
+        })
+    })?;
+
+    let rest = rest.trim_start();
+    if let Some(set) = rest.strip_prefix("set ").or_else(|| rest.strip_prefix("se ")) {
+        // Up to an unescaped colon; `\:` is a colon in a value
+        let mut args = String::new();
+        let mut chars = set.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(':') => args.push(':'),
+                    Some(c) => args.extend(['\\', c]),
+                    None => args.push('\\'),
+                },
+                ':' => return Some(split(&args, &[' ', '\t'])),
+                c => args.push(c),
+            }
+        }
+        // `set` without a closing colon isn't a modeline
+        None
+    } else {
+        Some(split(rest, &[' ', '\t', ':']))
+    }
+}
+
+// `avim: tab_size=2 expand_tabs=false`, stopping at the first word that isn't
+// `name=value`, such as a comment's `*/`
+fn avim_modeline(line: &str) -> Option<Vec<String>> {
+    let (i, _) = line
+        .match_indices("avim:")
+        .find(|&(i, _)| line[..i].chars().next_back().is_none_or(|c| !c.is_alphanumeric()))?;
+    let args = split(&line[i + "avim:".len()..], &[' ', '\t', ',', ';']);
+    Some(args.into_iter().take_while(|arg| arg.contains('=')).collect())
+}
+
+fn split(text: &str, separators: &[char]) -> Vec<String> {
+    text.split(separators).filter(|word| !word.is_empty()).map(str::to_string).collect()
+}
+
+fn set_arg(arg: &str, settings: &mut Settings) -> Result<(), String> {
+    match SetArg::parse(arg)? {
+        SetArg::Set(def, value) => {
+            allowed(def)?;
+            settings.push((def, value));
+            Ok(())
+        }
+        _ => Err(format!("Not allowed in a modeline: {}", arg)),
+    }
+}
+
+fn config_arg(arg: &str, settings: &mut Settings) -> Result<(), String> {
+    let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+    let name = CONFIG_NAMES.iter().find(|(key, _)| *key == name).map_or(name, |&(_, option)| option);
+    let def = find(name).ok_or_else(|| format!("Unknown option: {}", name))?;
+    allowed(def)?;
+    let value = match value {
+        "true" if def.is_bool() => OptionValue::Bool(true),
+        "false" if def.is_bool() => OptionValue::Bool(false),
+        value => def.parse(value)?,
+    };
+    settings.push((def, value));
+    Ok(())
+}
+
+fn allowed(def: &OptionDef) -> Result<(), String> {
+    if ALLOWED.contains(&def.name) {
+        Ok(())
+    } else {
+        Err(format!("Not allowed in a modeline: {}", def.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The option names and values set by a single line, or the error it gave
+    fn settings(line: &str) -> Result<Vec<(&'static str, OptionValue)>, String> {
+        match modeline_settings(&[line], 5) {
+            (_, Some(e)) => Err(e),
+            (settings, None) => Ok(settings.into_iter().map(|(def, value)| (def.name, value)).collect()),
+        }
+    }
+
+    #[test]
+    fn vim_forms() {
+        let expected = vec![
+            ("tabstop", OptionValue::Number(2)),
+            ("shiftwidth", OptionValue::Number(2)),
+            ("expandtab", OptionValue::Bool(true)),
+        ];
+        assert_eq!(settings("# vim: ts=2 sw=2 et"), Ok(expected.clone()));
+        assert_eq!(settings("/* vim: set ts=2 sw=2 et: */"), Ok(expected.clone()));
+        assert_eq!(settings("// vi:ts=2:sw=2:et"), Ok(expected));
+        assert_eq!(settings("# vim: noet"), Ok(vec![("expandtab", OptionValue::Bool(false))]));
+        assert_eq!(settings("# vim: set cms=#\\:%s:"), Ok(vec![("commentstring", OptionValue::String("#:%s".into()))]));
+        assert_eq!(settings("# vim: set ts=2"), Ok(vec![]));
+        assert_eq!(settings("ex: ts=2"), Ok(vec![]));
+        assert_eq!(settings("svim: ts=2"), Ok(vec![]));
+    }
+
+    #[test]
+    fn avim_form_maps_config_names() {
+        assert_eq!(
+            settings("// avim: tab_size=3, expand_tabs=false; shift_width=3 */"),
+            Ok(vec![
+                ("tabstop", OptionValue::Number(3)),
+                ("expandtab", OptionValue::Bool(false)),
+                ("shiftwidth", OptionValue::Number(3)),
+            ])
+        );
+        assert_eq!(settings("# avim: textwidth=72"), Ok(vec![("textwidth", OptionValue::Number(72))]));
+        assert!(settings("# avim: tab_size=wide").is_err());
+        assert!(settings("# avim: color_scheme=evil").is_err());
+    }
+
+    #[test]
+    fn refuses_options_outside_the_allowlist() {
+        for line in [
+            "vim: set colorscheme=evil :",
+            "vim: set colo=evil :",
+            "vim: set mapleader=x :",
+            "vim: modeline",
+            "vim: nomodeline",
+            "vim: set modelines=1000 :",
+            "vim: set dictionary=/etc/passwd :",
+            "avim: colorscheme=evil",
+            "avim: mapleader=x",
+            "avim: dictionary=/etc/passwd",
+        ] {
+            assert_eq!(settings(line).map_err(|e| e.starts_with("Not allowed in a modeline")), Err(true), "{}", line);
+        }
+        for line in ["vim: invet", "vim: et!", "vim: ts?", "vim: all", "vim: set all :"] {
+            assert!(settings(line).is_err(), "{}", line);
+        }
+        assert!(settings("vim: set nosuchoption :").is_err());
+    }
+
+    #[test]
+    fn keeps_the_allowed_settings_around_an_error() {
+        let (settings, error) = modeline_settings(&["vim: ts=4", "vim: set colo=evil sw=8 :", "vim: et"], 5);
+        assert_eq!(error.as_deref(), Some("Not allowed in a modeline: colorscheme"));
+        let names: Vec<&str> = settings.iter().map(|(def, _)| def.name).collect();
+        assert_eq!(names, vec!["tabstop", "expandtab"]);
+    }
+
+    #[test]
+    fn scans_only_the_first_and_last_lines() {
+        let mut lines = vec!["vim: ts=1", "vim: ts=2", "vim: ts=3", "vim: ts=4", "vim: ts=5"];
+        let values = |lines: &[&str], count| -> Vec<OptionValue> {
+            modeline_settings(lines, count).0.into_iter().map(|(_, value)| value).collect()
+        };
+        assert_eq!(values(&lines, 1), vec![OptionValue::Number(1), OptionValue::Number(5)]);
+        assert_eq!(values(&lines, 2).len(), 4);
+        // Lines that are both among the first and the last are read once
+        assert_eq!(values(&lines, 5).len(), 5);
+        assert_eq!(values(&lines, 100).len(), 5);
+        assert!(values(&lines, 0).is_empty());
+        lines.insert(2, "vim: ts=9");
+        assert!(!values(&lines, 2).contains(&OptionValue::Number(9)));
+    }
+}
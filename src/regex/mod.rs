@@ -0,0 +1,171 @@
+// Vim-style regular expressions, used by :substitute and search
+mod parse;
+mod program;
+
+use program::Program;
+
+pub use program::is_word_char;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Program,
+    multiline: bool,
+}
+
+// Where a match and its \(\) groups are in the text, as byte offsets
+#[derive(Debug, Clone)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    // The whole match, as moved by \zs and \ze
+    pub fn range(&self) -> (usize, usize) {
+        self.get(0).unwrap_or((0, 0))
+    }
+
+    // Group `n`, 0 for the whole match; None when it didn't take part
+    pub fn get(&self, n: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(2 * n)?, self.slots.get(2 * n + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+}
+
+impl Regex {
+    // `ignore_case` applies unless the pattern has \c or \C
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        let parsed = parse::parse(pattern)?;
+        let ignore_case = parsed.ignore_case.unwrap_or(ignore_case);
+        Ok(Self {
+            program: Program::compile(&parsed.node, parsed.groups, ignore_case)?,
+            multiline: parsed.multiline,
+        })
+    }
+
+    // Whether a match can run over a line break, so lines must be searched
+    // joined with '\n' rather than one at a time
+    pub fn multiline(&self) -> bool {
+        self.multiline
+    }
+
+    // The first match that starts at or after byte `start`. The text before
+    // `start` still counts for ^ and \<.
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Captures> {
+        self.program.exec(text, start).map(|slots| Captures { slots })
+    }
+
+    pub fn find_at(&self, text: &str, start: usize) -> Option<(usize, usize)> {
+        self.captures_at(text, start).map(|captures| captures.range())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    // Every match in `text` that doesn't overlap the one before it
+    pub fn find_iter<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut pos = Some(0);
+        std::iter::from_fn(move || {
+            let (start, end) = self.find_at(text, pos?)?;
+            // After an empty match, look again from the next character
+            pos = if end > start { Some(end) } else { text[end..].chars().next().map(|c| end + c.len_utf8()) };
+            Some((start, end))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+
+    fn find(pattern: &str, text: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern, false).unwrap().find_at(text, 0)
+    }
+
+    #[test]
+    fn magic() {
+        assert_eq!(find("a.c", "xabc"), Some((1, 4)));
+        assert_eq!(find("ab*", "abbb"), Some((0, 4)));
+        assert_eq!(find("a+", "aa+"), Some((1, 3)));
+        assert_eq!(find("a\\+", "xaa"), Some((1, 3)));
+        assert_eq!(find("\\(ab\\)\\{2}", "ababab"), Some((0, 4)));
+        assert_eq!(find("foo\\|bar", "a bar"), Some((2, 5)));
+        assert_eq!(find("^a", "ba"), None);
+    }
+
+    #[test]
+    fn very_magic() {
+        assert_eq!(find("\\v(ab)+", "xabab"), Some((1, 5)));
+        assert_eq!(find("\\va{2,3}", "aaaa"), Some((0, 3)));
+        assert_eq!(find("\\v<is>", "this is"), Some((5, 7)));
+        assert_eq!(find("\\vfoo|bar", "bar"), Some((0, 3)));
+    }
+
+    #[test]
+    fn nomagic() {
+        assert_eq!(find("\\Ma.c", "abc"), None);
+        assert_eq!(find("\\Ma.c", "a.c"), Some((0, 3)));
+        assert_eq!(find("\\Ma\\.c", "abc"), Some((0, 3)));
+        assert_eq!(find("\\Ma*", "aa*"), Some((1, 3)));
+        assert_eq!(find("\\Ma$", "a$a"), Some((2, 3)));
+    }
+
+    #[test]
+    fn very_nomagic() {
+        assert_eq!(find("\\Va.c*", "abc"), None);
+        assert_eq!(find("\\Va.c*", "xa.c*"), Some((1, 5)));
+        assert_eq!(find("\\Va\\.c", "abc"), Some((0, 3)));
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(find("abc", "ABC"), None);
+        assert_eq!(find("\\cabc", "ABC"), Some((0, 3)));
+        assert_eq!(Regex::new("abc", true).unwrap().find_at("ABC", 0), Some((0, 3)));
+        assert_eq!(Regex::new("\\Cabc", true).unwrap().find_at("ABC", 0), None);
+    }
+
+    #[test]
+    fn match_start_and_end() {
+        assert_eq!(find("foo\\zsbar", "foobar"), Some((3, 6)));
+        assert_eq!(find("foo\\zebar", "foobar foo"), Some((0, 3)));
+        assert_eq!(find("a\\zsb\\zec", "abc"), Some((1, 2)));
+        assert_eq!(find("foo\\zebar", "foo"), None);
+    }
+
+    #[test]
+    fn groups() {
+        let regex = Regex::new("\\(\\w\\+\\)=\\(\\d*\\)", false).unwrap();
+        let captures = regex.captures_at("let x=12", 0).unwrap();
+        assert_eq!(captures.range(), (4, 8));
+        assert_eq!(captures.get(1), Some((4, 5)));
+        assert_eq!(captures.get(2), Some((6, 8)));
+        assert_eq!(captures.get(3), None);
+    }
+
+    #[test]
+    fn empty_matches() {
+        let regex = Regex::new("x*", false).unwrap();
+        let matches: Vec<_> = regex.find_iter("axxb").collect();
+        assert_eq!(matches, vec![(0, 0), (1, 3), (3, 3), (4, 4)]);
+        let regex = Regex::new("^", false).unwrap();
+        assert_eq!(regex.find_iter("é").collect::<Vec<_>>(), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn nested_stars_run_in_linear_time() {
+        let text = "a".repeat(5000);
+        assert!(!Regex::new("\\(a*\\)*b", false).unwrap().is_match(&text));
+        assert!(Regex::new("\\(a*\\)*$", false).unwrap().is_match(&text));
+    }
+
+    #[test]
+    fn counted_repeats_are_capped() {
+        assert!(Regex::new("a\\{1000}", false).is_ok());
+        assert!(Regex::new("\\(\\(a\\{1000}\\)\\{1000}\\)\\{1000}", false).is_err());
+        assert!(Regex::new("\\%(\\%(\\%(\\)\\{1000}\\)\\{1000}\\)\\{1000}", false).is_err());
+        assert!(Regex::new("a\\{1001}", false).is_err());
+    }
+}
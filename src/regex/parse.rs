@@ -0,0 +1,437 @@
+// Vim's pattern syntax. In the default "magic" mode `.`, `*`, `[]`, `^` and
+// `$` are special as they are, `\+`, `\=`, `\?`, `\{}`, `\(\)`, `\|`, `\<` and
+// `\>` with a backslash; \v, \M and \V change which need one.
+
+// Most repetitions a \{n,m} may ask for, since each one is compiled out
+const MAX_REPEAT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assertion {
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+}
+
+// Characters matched by [...] or a class such as \d: ranges of them, or
+// everything else when negated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+    newline: bool, // Also matches a line break, for \_s and the like
+}
+
+impl CharClass {
+    fn new(ranges: &[(char, char)], negated: bool) -> Self {
+        Self { ranges: ranges.to_vec(), negated, newline: false }
+    }
+
+    pub fn matches(&self, c: char, ignore_case: bool) -> bool {
+        if c == '\n' {
+            return self.newline;
+        }
+        let contains = |c: char| self.ranges.iter().any(|&(first, last)| first <= c && c <= last);
+        let found = contains(c) || (ignore_case && (c.to_lowercase().any(contains) || c.to_uppercase().any(contains)));
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>), // The capture group number, None for \%(\)
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize>, greedy: bool },
+    MatchStart, // \zs
+    MatchEnd,   // \ze
+}
+
+pub struct Parsed {
+    pub node: Node,
+    pub groups: usize,
+    pub ignore_case: Option<bool>, // From \c or \C in the pattern
+    pub multiline: bool,           // Has \n or \_x, so a match may run over line breaks
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    Any,
+    Star,
+    Plus,
+    Optional,
+    Braces { min: usize, max: Option<usize>, greedy: bool },
+    Caret,
+    Dollar,
+    Class(CharClass),
+    Open { capture: bool },
+    Close,
+    Alt,
+    Assert(Assertion),
+    MatchStart,
+    MatchEnd,
+}
+
+pub fn parse(pattern: &str) -> Result<Parsed, String> {
+    let mut ignore_case = None;
+    let tokens = tokenize(pattern, &mut ignore_case)?;
+    let multiline = tokens.iter().any(|token| match token {
+        Token::Literal(c) => *c == '\n',
+        Token::Class(class) => class.newline,
+        _ => false,
+    });
+    let mut parser = Parser { tokens, pos: 0, groups: 0 };
+    let node = parser.alternation()?;
+    if parser.pos < parser.tokens.len() {
+        return Err("Unmatched \\)".to_string());
+    }
+    Ok(Parsed { node, groups: parser.groups, ignore_case, multiline })
+}
+
+// How much of the pattern syntax needs a backslash: \v very magic, \m magic
+// (the default), \M nomagic and \V very nomagic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Magic {
+    Very,
+    On,
+    Off,
+    VeryOff,
+}
+
+impl Magic {
+    // Whether `c` is an operator rather than itself, with or without a backslash
+    fn is_special(self, c: char, escaped: bool) -> bool {
+        let plain = match self {
+            Magic::Very => return !escaped,
+            Magic::On => ".*[^$~".contains(c),
+            Magic::Off => "^$".contains(c),
+            Magic::VeryOff => false,
+        };
+        plain != escaped
+    }
+}
+
+// Characters that are operators in some magic mode
+const OPERATORS: &str = ".*[^$~+=?{()|<>@%";
+
+fn tokenize(pattern: &str, ignore_case: &mut Option<bool>) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut magic = Magic::On;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let (c, escaped) = match c {
+            '\\' => match chars.next() {
+                Some(next) => (next, true),
+                None => ('\\', false),
+            },
+            c => (c, false),
+        };
+        let token = if escaped && (c.is_ascii_alphanumeric() || c == '_') {
+            match c {
+                'v' | 'm' | 'M' | 'V' => {
+                    magic = match c {
+                        'v' => Magic::Very,
+                        'm' => Magic::On,
+                        'M' => Magic::Off,
+                        _ => Magic::VeryOff,
+                    };
+                    continue;
+                }
+                'z' => match chars.next() {
+                    Some('s') => Token::MatchStart,
+                    Some('e') => Token::MatchEnd,
+                    _ => return Err("Invalid character after \\z".to_string()),
+                },
+                'c' => {
+                    *ignore_case = Some(true);
+                    continue;
+                }
+                'C' => {
+                    *ignore_case = Some(false);
+                    continue;
+                }
+                't' => Token::Literal('\t'),
+                'e' => Token::Literal('\x1b'),
+                'n' => Token::Literal('\n'),
+                // \_x is x or a line break
+                '_' => match chars.next() {
+                    Some('^') => Token::Assert(Assertion::LineStart),
+                    Some('$') => Token::Assert(Assertion::LineEnd),
+                    Some('.') => Token::Class(CharClass { ranges: Vec::new(), negated: true, newline: true }),
+                    Some('[') => {
+                        let (mut class, len) = bracket(&chars).ok_or("Missing ] after \\_[")?;
+                        chars.nth(len - 1);
+                        class.newline = true;
+                        Token::Class(class)
+                    }
+                    Some(name) => match class_escape(name) {
+                        Some(mut class) => {
+                            class.newline = true;
+                            Token::Class(class)
+                        }
+                        None => return Err(format!("Invalid use of \\_{}", name)),
+                    },
+                    None => return Err("Invalid use of \\_".to_string()),
+                },
+                name => match class_escape(name) {
+                    Some(class) => Token::Class(class),
+                    None => Token::Literal(name),
+                },
+            }
+        } else if OPERATORS.contains(c) && magic.is_special(c, escaped) {
+            match c {
+                '.' => Token::Any,
+                '*' => Token::Star,
+                '^' => Token::Caret,
+                '$' => Token::Dollar,
+                '~' => Token::Literal('~'),
+                '[' => match bracket(&chars) {
+                    // Without a closing ] the [ is taken literally
+                    Some((class, len)) => {
+                        chars.nth(len - 1);
+                        Token::Class(class)
+                    }
+                    None => Token::Literal('['),
+                },
+                '+' => Token::Plus,
+                '=' | '?' => Token::Optional,
+                '{' => parse_braces(&mut chars)?,
+                '(' => Token::Open { capture: true },
+                '%' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    Token::Open { capture: false }
+                }
+                ')' => Token::Close,
+                '|' => Token::Alt,
+                '<' => Token::Assert(Assertion::WordStart),
+                '>' => Token::Assert(Assertion::WordEnd),
+                '@' => return Err("\\@ is not supported".to_string()),
+                c => Token::Literal(c),
+            }
+        } else {
+            Token::Literal(c)
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// A [...] class starting after the [, and how many characters it takes up
+fn bracket(chars: &std::iter::Peekable<std::str::Chars>) -> Option<(CharClass, usize)> {
+    let rest: String = chars.clone().collect();
+    parse_bracket(&rest).filter(|&(_, len)| len > 0)
+}
+
+// \s, \d, \w and the other character classes, upper case for the complement
+fn class_escape(name: char) -> Option<CharClass> {
+    let ranges: &[(char, char)] = match name.to_ascii_lowercase() {
+        's' => &[(' ', ' '), ('\t', '\t')],
+        'd' => &[('0', '9')],
+        'w' => &[('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')],
+        'h' => &[('A', 'Z'), ('a', 'z'), ('_', '_')],
+        'a' => &[('A', 'Z'), ('a', 'z')],
+        'l' => &[('a', 'z')],
+        'u' => &[('A', 'Z')],
+        'x' => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+        'o' => &[('0', '7')],
+        _ => return None,
+    };
+    Some(CharClass::new(ranges, name.is_ascii_uppercase()))
+}
+
+// \{n,m}, \{n}, \{n,}, \{,m} and \{} (same as *); \{-...} matches as few as possible
+fn parse_braces(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Token, String> {
+    let mut body = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some('\\') if chars.peek() == Some(&'}') => {
+                chars.next();
+                break;
+            }
+            Some(c) => body.push(c),
+            None => return Err("Missing } after \\{".to_string()),
+        }
+    }
+    let (greedy, body) = match body.strip_prefix('-') {
+        Some(body) => (false, body),
+        None => (true, body.as_str()),
+    };
+    let number = |text: &str| -> Result<Option<usize>, String> {
+        match text.trim() {
+            "" => Ok(None),
+            text => match text.parse::<usize>() {
+                Ok(n) if n <= MAX_REPEAT => Ok(Some(n)),
+                _ => Err(format!("Invalid count in \\{{{}}}", body)),
+            },
+        }
+    };
+    let (min, max) = match body.split_once(',') {
+        Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+        None => {
+            let n = number(body)?;
+            (n.unwrap_or(0), n)
+        }
+    };
+    // \{5,2} is the same as \{2,5}
+    let (min, max) = match max {
+        Some(max) if max < min => (max, Some(min)),
+        max => (min, max),
+    };
+    Ok(Token::Braces { min, max, greedy })
+}
+
+// The class after a [, and how many characters of `text` it took up to the ]
+fn parse_bracket(text: &str) -> Option<(CharClass, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    let negated = chars.first() == Some(&'^');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            i += 1;
+            break;
+        }
+        first = false;
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let class = class_escape(match &rest[..end] {
+                    "alpha" => 'a',
+                    "digit" => 'd',
+                    "alnum" => 'w',
+                    "lower" => 'l',
+                    "upper" => 'u',
+                    "space" | "blank" => 's',
+                    "xdigit" => 'x',
+                    _ => return None,
+                })?;
+                ranges.extend(class.ranges.iter().copied().filter(|&range| range != ('_', '_')));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        let (start, len) = bracket_char(&chars[i..]);
+        i += len;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&c| c != ']') {
+            let (end, len) = bracket_char(&chars[i + 1..]);
+            i += 1 + len;
+            if end < start {
+                return None;
+            }
+            ranges.push((start, end));
+        } else {
+            ranges.push((start, start));
+        }
+    }
+    Some((CharClass::new(&ranges, negated), i))
+}
+
+// One character inside [], with \e, \t, \\, \] and \- escapes
+fn bracket_char(chars: &[char]) -> (char, usize) {
+    match chars {
+        ['\\', 'e', ..] => ('\x1b', 2),
+        ['\\', 't', ..] => ('\t', 2),
+        ['\\', c @ ('\\' | ']' | '^' | '-'), ..] => (*c, 2),
+        [c, ..] => (*c, 1),
+        [] => ('\0', 0),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.branch()?];
+        while self.peek() == Some(&Token::Alt) {
+            self.pos += 1;
+            branches.push(self.branch()?);
+        }
+        Ok(if branches.len() == 1 { branches.remove(0) } else { Node::Alt(branches) })
+    }
+
+    fn branch(&mut self) -> Result<Node, String> {
+        let mut pieces = Vec::new();
+        while let Some(token) = self.peek().cloned() {
+            if matches!(token, Token::Alt | Token::Close) {
+                break;
+            }
+            self.pos += 1;
+            let atom = match token {
+                // ^ is only special at the start of a branch and $ at its end
+                Token::Caret if pieces.is_empty() => Node::Assert(Assertion::LineStart),
+                Token::Caret => Node::Char('^'),
+                Token::Dollar if matches!(self.peek(), None | Some(Token::Alt) | Some(Token::Close)) => {
+                    Node::Assert(Assertion::LineEnd)
+                }
+                Token::Dollar => Node::Char('$'),
+                Token::Literal(c) => Node::Char(c),
+                Token::Any => Node::Any,
+                Token::Class(class) => Node::Class(class),
+                Token::Assert(assertion) => Node::Assert(assertion),
+                Token::MatchStart => Node::MatchStart,
+                Token::MatchEnd => Node::MatchEnd,
+                Token::Open { capture } => {
+                    let group = capture.then(|| {
+                        self.groups += 1;
+                        self.groups
+                    });
+                    let inner = self.alternation()?;
+                    if self.peek() != Some(&Token::Close) {
+                        return Err("Unmatched \\(".to_string());
+                    }
+                    self.pos += 1;
+                    Node::Group(Box::new(inner), group)
+                }
+                // A * with nothing before it matches a *
+                Token::Star if pieces.is_empty() || pieces.last() == Some(&Node::Assert(Assertion::LineStart)) => {
+                    Node::Char('*')
+                }
+                Token::Star | Token::Plus | Token::Optional | Token::Braces { .. } => {
+                    let Some(previous) = pieces.pop() else {
+                        return Err("Multi follows nothing".to_string());
+                    };
+                    pieces.push(Self::repeat(previous, &token));
+                    continue;
+                }
+                Token::Alt | Token::Close => unreachable!(),
+            };
+            pieces.push(atom);
+        }
+        Ok(match pieces.len() {
+            0 => Node::Empty,
+            1 => pieces.remove(0),
+            _ => Node::Concat(pieces),
+        })
+    }
+
+    fn repeat(node: Node, token: &Token) -> Node {
+        let (min, max, greedy) = match *token {
+            Token::Star => (0, None, true),
+            Token::Plus => (1, None, true),
+            Token::Optional => (0, Some(1), true),
+            Token::Braces { min, max, greedy } => (min, max, greedy),
+            _ => unreachable!(),
+        };
+        Node::Repeat { node: Box::new(node), min, max, greedy }
+    }
+}
@@ -0,0 +1,250 @@
+// Patterns compiled to instructions for a Pike VM: every way through the
+// pattern is followed at once, one character at a time, so matching takes
+// time linear in the text whatever the pattern.
+
+use super::parse::{Assertion, CharClass, Node};
+
+// Most instructions a pattern may compile to. Counted repeats are copied out,
+// so nested ones like \(a\{1000}\)\{1000} would otherwise grow without bound.
+const MAX_INSTS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    Split(usize, usize), // Both, the first one preferred
+    Jmp(usize),
+    Save(usize), // Record the position in a capture slot
+    Match,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    insts: Vec<Inst>,
+    pub slots: usize,
+    match_start: usize, // Slot of \zs, and \ze after it
+    pub ignore_case: bool,
+}
+
+impl Program {
+    // Slots 0 and 1 hold the whole match, 2n and 2n+1 group n, then \zs and \ze
+    pub fn compile(node: &Node, groups: usize, ignore_case: bool) -> Result<Self, String> {
+        if size(node) > MAX_INSTS {
+            return Err("Pattern too long or repeated too often".to_string());
+        }
+        let match_start = 2 * (groups + 1);
+        let mut program = Self {
+            insts: Vec::new(),
+            slots: match_start + 2,
+            match_start,
+            ignore_case,
+        };
+        program.insts.push(Inst::Save(0));
+        program.emit(node);
+        program.insts.push(Inst::Save(1));
+        program.insts.push(Inst::Match);
+        Ok(program)
+    }
+
+    fn emit(&mut self, node: &Node) {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => self.insts.push(Inst::Char(*c)),
+            Node::Any => self.insts.push(Inst::Any),
+            Node::Class(class) => self.insts.push(Inst::Class(class.clone())),
+            Node::Assert(assertion) => self.insts.push(Inst::Assert(*assertion)),
+            Node::MatchStart => self.insts.push(Inst::Save(self.match_start)),
+            Node::MatchEnd => self.insts.push(Inst::Save(self.match_start + 1)),
+            Node::Group(inner, group) => match group {
+                Some(n) => {
+                    self.insts.push(Inst::Save(2 * n));
+                    self.emit(inner);
+                    self.insts.push(Inst::Save(2 * n + 1));
+                }
+                None => self.emit(inner),
+            },
+            Node::Concat(nodes) => nodes.iter().for_each(|node| self.emit(node)),
+            Node::Alt(branches) => {
+                let mut jumps = Vec::new();
+                for (i, branch) in branches.iter().enumerate() {
+                    if i + 1 == branches.len() {
+                        self.emit(branch);
+                    } else {
+                        let split = self.placeholder();
+                        self.emit(branch);
+                        jumps.push(self.placeholder());
+                        self.insts[split] = Inst::Split(split + 1, self.insts.len());
+                    }
+                }
+                let end = self.insts.len();
+                for jump in jumps {
+                    self.insts[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.emit(node);
+                }
+                match max {
+                    None => {
+                        let split = self.placeholder();
+                        self.emit(node);
+                        self.insts.push(Inst::Jmp(split));
+                        let end = self.insts.len();
+                        self.insts[split] = self.split(split + 1, end, *greedy);
+                    }
+                    Some(max) => {
+                        // Each optional copy may be skipped to the end
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.placeholder());
+                            self.emit(node);
+                        }
+                        let end = self.insts.len();
+                        for split in splits {
+                            self.insts[split] = self.split(split + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn placeholder(&mut self) -> usize {
+        self.insts.push(Inst::Jmp(0));
+        self.insts.len() - 1
+    }
+
+    fn split(&self, body: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, skip)
+        } else {
+            Inst::Split(skip, body)
+        }
+    }
+
+    // The capture slots of the leftmost match starting at or after `start`,
+    // preferring the way through the pattern vim's backtracking would take
+    pub fn exec(&self, text: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let mut current = Threads::new(self.insts.len());
+        let mut matched = None;
+        let mut pos = start;
+        loop {
+            if matched.is_none() {
+                // A match starting here ranks below the ones that started earlier
+                self.add_thread(&mut current, 0, vec![None; self.slots], text, pos);
+            }
+            if current.list.is_empty() && matched.is_some() {
+                break;
+            }
+            let c = text[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+            let mut next = Threads::new(self.insts.len());
+            for (pc, slots) in current.list.drain(..) {
+                let step = match (&self.insts[pc], c) {
+                    (Inst::Match, _) => {
+                        // Threads ranked below this one can't win any more
+                        matched = Some(slots);
+                        break;
+                    }
+                    (Inst::Char(expected), Some(c)) => chars_equal(*expected, c, self.ignore_case),
+                    (Inst::Any, Some(c)) => c != '\n',
+                    (Inst::Class(class), Some(c)) => class.matches(c, self.ignore_case),
+                    _ => false,
+                };
+                if step {
+                    self.add_thread(&mut next, pc + 1, slots, text, next_pos);
+                }
+            }
+            current = next;
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+        }
+        let mut slots = matched?;
+        // \zs and \ze move the ends of the match
+        if let Some(zs) = slots[self.match_start] {
+            slots[0] = Some(zs);
+        }
+        if let Some(ze) = slots[self.match_start + 1] {
+            slots[1] = Some(ze.max(slots[0].unwrap_or(ze)));
+        }
+        Some(slots)
+    }
+
+    // Follow jumps, splits, saves and assertions from `pc` to the instructions
+    // that consume a character
+    fn add_thread(&self, threads: &mut Threads, pc: usize, mut slots: Vec<Option<usize>>, text: &str, pos: usize) {
+        if threads.seen[pc] {
+            return;
+        }
+        threads.seen[pc] = true;
+        match &self.insts[pc] {
+            Inst::Jmp(target) => self.add_thread(threads, *target, slots, text, pos),
+            Inst::Split(first, second) => {
+                self.add_thread(threads, *first, slots.clone(), text, pos);
+                self.add_thread(threads, *second, slots, text, pos);
+            }
+            Inst::Save(slot) => {
+                slots[*slot] = Some(pos);
+                self.add_thread(threads, pc + 1, slots, text, pos);
+            }
+            Inst::Assert(assertion) => {
+                if holds(*assertion, text, pos) {
+                    self.add_thread(threads, pc + 1, slots, text, pos);
+                }
+            }
+            _ => threads.list.push((pc, slots)),
+        }
+    }
+}
+
+// About how many instructions a node compiles to, and at least one for each
+// node so that repeating an empty group counts too. Saturates rather than
+// overflowing.
+fn size(node: &Node) -> usize {
+    match node {
+        Node::Empty | Node::Char(_) | Node::Any | Node::Class(_) | Node::Assert(_) | Node::MatchStart | Node::MatchEnd => 1,
+        Node::Group(inner, _) => size(inner).saturating_add(2),
+        Node::Concat(nodes) => nodes.iter().fold(1, |total, node| total.saturating_add(size(node))),
+        Node::Alt(branches) => branches.iter().fold(1, |total, branch| total.saturating_add(size(branch)).saturating_add(2)),
+        Node::Repeat { node, min, max, .. } => {
+            let copies = max.unwrap_or(min + 1).max(1);
+            size(node).saturating_add(2).saturating_mul(copies)
+        }
+    }
+}
+
+// Threads waiting at one position, highest priority first
+struct Threads {
+    list: Vec<(usize, Vec<Option<usize>>)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Self { list: Vec::new(), seen: vec![false; len] }
+    }
+}
+
+fn chars_equal(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn holds(assertion: Assertion, text: &str, pos: usize) -> bool {
+    let before = text[..pos].chars().next_back();
+    let after = text[pos..].chars().next();
+    match assertion {
+        Assertion::LineStart => before.is_none_or(|c| c == '\n'),
+        Assertion::LineEnd => after.is_none_or(|c| c == '\n'),
+        Assertion::WordStart => after.is_some_and(is_word_char) && !before.is_some_and(is_word_char),
+        Assertion::WordEnd => before.is_some_and(is_word_char) && !after.is_some_and(is_word_char),
+    }
+}
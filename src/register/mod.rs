@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    Characterwise,
+    Linewise,
+    Blockwise,
+}
+
+// Yanked or deleted text along with how it should be put back
+#[derive(Debug, Clone)]
+pub struct Register {
+    pub kind: RegisterKind,
+    pub lines: Vec<String>,
+}
+
+impl Register {
+    pub fn new(kind: RegisterKind, lines: Vec<String>) -> Self {
+        Self { kind, lines }
+    }
+
+    pub fn characterwise(text: String) -> Self {
+        Self::new(RegisterKind::Characterwise, text.split('\n').map(|s| s.to_string()).collect())
+    }
+
+    pub fn linewise(lines: Vec<String>) -> Self {
+        Self::new(RegisterKind::Linewise, lines)
+    }
+
+    pub fn blockwise(lines: Vec<String>) -> Self {
+        Self::new(RegisterKind::Blockwise, lines)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // Width of the widest line, used to pad blockwise puts
+    pub fn width(&self) -> usize {
+        self.lines.iter().map(|l| l.chars().count()).max().unwrap_or(0)
+    }
+}
+
+pub struct Registers {
+    unnamed: Option<Register>,
+    named: HashMap<char, Register>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self {
+            unnamed: None,
+            named: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            '"' => self.unnamed.as_ref(),
+            _ => self.named.get(&name.to_ascii_lowercase()),
+        }
+    }
+
+    pub fn set(&mut self, name: char, register: Register) {
+        if name != '"' {
+            self.named.insert(name.to_ascii_lowercase(), register.clone());
+        }
+        self.unnamed = Some(register);
+    }
+
+    // The . register holds the last inserted text and never touches the unnamed one
+    pub fn set_last_inserted(&mut self, text: String) {
+        self.named.insert('.', Register::characterwise(text));
+    }
+
+    pub fn unnamed(&self) -> Option<&Register> {
+        self.unnamed.as_ref()
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
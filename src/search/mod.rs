@@ -1,11 +1,72 @@
 use crate::buffer::Buffer;
+use crate::regex::Regex;
+
+// Where the cursor goes relative to a match: /foo/+1, /foo/e-1, /foo/s+2
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Offset {
+    #[default]
+    None,
+    Lines(isize), // Start of the line that many lines below (above) the match
+    Start(isize), // Characters right (left) of the start of the match
+    End(isize),   // Characters right (left) of the last character of the match
+}
+
+impl Offset {
+    fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid search offset: {}", text);
+        // "+", "-" and "" alone are one line (character) and no offset
+        let number = |text: &str| -> Result<isize, String> {
+            match text {
+                "" => Ok(0),
+                "+" => Ok(1),
+                "-" => Ok(-1),
+                text => text.strip_prefix('+').unwrap_or(text).parse().map_err(|_| invalid()),
+            }
+        };
+        match text.chars().next() {
+            None => Ok(Offset::None),
+            Some('e') => Ok(Offset::End(number(&text[1..])?)),
+            Some('s' | 'b') => Ok(Offset::Start(number(&text[1..])?)),
+            Some(c) if c == '+' || c == '-' || c.is_ascii_digit() => Ok(Offset::Lines(number(text)?)),
+            Some(_) => Err(invalid()),
+        }
+    }
+}
+
+// One match, from its first character to just past its last one. A match that
+// runs over line breaks ends on a later line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: (usize, usize), // (line, col)
+    pub end: (usize, usize),
+}
+
+impl Match {
+    // The last character of the match, or where an empty one is. A match up
+    // to a line break ends on the end of the line before it.
+    pub fn last(&self, buffer: &Buffer) -> (usize, usize) {
+        if self.end == self.start {
+            self.start
+        } else if self.end.1 == 0 {
+            let line = self.end.0 - 1;
+            step(buffer, (line, buffer.get_line(line).map_or(0, |text| text.len())), -1)
+        } else {
+            step(buffer, self.end, -1)
+        }
+    }
+
+    fn contains(&self, position: (usize, usize)) -> bool {
+        position == self.start || (self.start..self.end).contains(&position)
+    }
+}
 
 #[derive(Clone)]
 pub struct SearchState {
     pub query: String,
     pub forward: bool,
-    pub matches: Vec<(usize, usize)>, // (line, col)
-    pub current_match: Option<usize>,
+    pub offset: Offset,
+    pub ignore_case: bool, // The 'ignorecase' and 'smartcase' options
+    pub smart_case: bool,
 }
 
 impl SearchState {
@@ -13,97 +74,299 @@ impl SearchState {
         Self {
             query: String::new(),
             forward: true,
-            matches: Vec::new(),
-            current_match: None,
+            offset: Offset::None,
+            ignore_case: false,
+            smart_case: false,
         }
     }
 
-    pub fn search(&mut self, buffer: &Buffer, query: &str, forward: bool) {
-        self.query = query.to_string();
-        self.forward = forward;
-        self.matches.clear();
-        self.current_match = None;
+    // Whether `pattern` is matched ignoring case when it has no \c or \C:
+    // with 'ignorecase', unless 'smartcase' and it has an upper case letter
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        self.ignore_case && !(self.smart_case && has_uppercase(pattern))
+    }
 
-        if query.is_empty() {
-            return;
-        }
+    pub fn regex(&self, pattern: &str) -> Result<Regex, String> {
+        Regex::new(pattern, self.ignores_case(pattern))
+    }
 
-        // Find all matches
-        for (line_idx, line) in (0..buffer.line_count()).filter_map(|i| buffer.get_line(i).map(|l| (i, l))) {
-            let mut start = 0;
-            while let Some(pos) = line[start..].find(query) {
-                self.matches.push((line_idx, start + pos));
-                start += pos + 1;
-            }
-        }
+    // Run what was typed after / or ?: a pattern, an offset and more searches
+    // after `;`, each starting where the one before it put the cursor. The
+    // last pattern becomes the one n and N search for.
+    pub fn search(&mut self, buffer: &Buffer, input: &str, forward: bool, cursor: (usize, usize)) -> Result<Found, String> {
+        let mut position = cursor;
+        let mut rest = input;
+        let mut forward = forward;
+        loop {
+            let delimiter = if forward { '/' } else { '?' };
+            let (pattern, after) = split_pattern(rest, delimiter);
+            let (offset_text, next) = match after.find(';') {
+                Some(i) => (&after[..i], Some(&after[i + 1..])),
+                None => (after, None),
+            };
+
+            // An empty pattern is the last one, and with nothing else typed its offset too
+            let offset = if input.is_empty() { self.offset } else { Offset::parse(offset_text)? };
+            let pattern = match pattern.is_empty() {
+                true if self.query.is_empty() => return Err("No previous regular expression".to_string()),
+                true => self.query.clone(),
+                false => pattern,
+            };
+            self.set_pattern(&pattern, forward, offset);
+            let found = self.next_match(buffer, position, false)?;
+            position = found.cursor;
 
-        if !self.matches.is_empty() {
-            self.current_match = Some(0);
+            match next {
+                Some(next) => {
+                    forward = match next.chars().next() {
+                        Some('/') => true,
+                        Some('?') => false,
+                        _ => return Err("Expected / or ? after ;".to_string()),
+                    };
+                    rest = &next[1..];
+                }
+                None => return Ok(found),
+            }
         }
     }
 
-    pub fn next_match(&mut self) -> Option<(usize, usize)> {
-        if self.matches.is_empty() {
-            return None;
-        }
+    // Make `pattern` the one n and N search for
+    pub fn set_pattern(&mut self, pattern: &str, forward: bool, offset: Offset) {
+        self.query = pattern.to_string();
+        self.forward = forward;
+        self.offset = offset;
+    }
 
-        if let Some(current) = self.current_match {
-            let next = if self.forward {
-                (current + 1) % self.matches.len()
-            } else {
-                if current == 0 {
-                    self.matches.len() - 1
-                } else {
-                    current - 1
-                }
-            };
-            self.current_match = Some(next);
-            Some(self.matches[next])
-        } else {
-            None
-        }
+    // :s and :g make their pattern the one n and N search for too, in the
+    // same direction as before and without an offset
+    pub fn use_pattern(&mut self, pattern: &str) {
+        self.query = pattern.to_string();
+        self.offset = Offset::None;
     }
 
-    pub fn prev_match(&mut self) -> Option<(usize, usize)> {
-        if self.matches.is_empty() {
-            return None;
+    // What an empty pattern stands for: the last one searched for or used
+    // by :s or :g
+    pub fn last_pattern(&self) -> Result<String, String> {
+        match self.query.is_empty() {
+            true => Err("No previous regular expression".to_string()),
+            false => Ok(self.query.clone()),
         }
+    }
 
-        if let Some(current) = self.current_match {
-            let prev = if current == 0 {
-                self.matches.len() - 1
-            } else {
-                current - 1
-            };
-            self.current_match = Some(prev);
-            Some(self.matches[prev])
-        } else {
-            None
+    // n, and N with `reverse`: the next match from the cursor in (against) the
+    // search direction, wrapping around the end of the buffer
+    pub fn next_match(&self, buffer: &Buffer, cursor: (usize, usize), reverse: bool) -> Result<Found, String> {
+        if self.query.is_empty() {
+            return Err("No previous regular expression".to_string());
         }
+        let regex = self.regex(&self.query)?;
+        let forward = self.forward != reverse;
+        // The first match the offset puts past the cursor. A line offset
+        // counts from the line the cursor's match is on, as in vim.
+        let past = |m: &Match| match self.offset {
+            Offset::Lines(n) => {
+                let line = cursor.0 as isize - n;
+                if forward { m.start.0 as isize > line } else { (m.start.0 as isize) < line }
+            }
+            _ if forward => self.apply_offset(buffer, m) > cursor,
+            _ => self.apply_offset(buffer, m) < cursor,
+        };
+        let first_line = match self.offset {
+            Offset::Lines(n) => cursor.0.saturating_add_signed(-n),
+            _ => cursor.0,
+        };
+        let range = find_match(buffer, &regex, first_line, forward, past)
+            .ok_or_else(|| format!("Pattern not found: {}", self.query))?;
+        Ok(Found { range, cursor: self.apply_offset(buffer, &range) })
     }
 
-    pub fn current(&self) -> Option<(usize, usize)> {
-        self.current_match.and_then(|i| self.matches.get(i).copied())
+    // gn and gN: the match under the cursor, or else the next (previous) one
+    pub fn match_object(&self, buffer: &Buffer, cursor: (usize, usize), forward: bool) -> Result<Match, String> {
+        if self.query.is_empty() {
+            return Err("No previous regular expression".to_string());
+        }
+        let regex = self.regex(&self.query)?;
+        let under = |m: &Match| m.contains(cursor);
+        let past = |m: &Match| under(m) || if forward { m.start > cursor } else { m.start < cursor };
+        find_match(buffer, &regex, cursor.0, forward, past).ok_or_else(|| format!("Pattern not found: {}", self.query))
     }
 
     pub fn clear(&mut self) {
         self.query.clear();
-        self.matches.clear();
-        self.current_match = None;
     }
 
     pub fn is_active(&self) -> bool {
         !self.query.is_empty()
     }
 
-    pub fn match_count(&self) -> usize {
-        self.matches.len()
+    // Where the cursor goes for a match
+    fn apply_offset(&self, buffer: &Buffer, found: &Match) -> (usize, usize) {
+        match self.offset {
+            Offset::None => found.start,
+            Offset::Lines(n) => {
+                let last = buffer.line_count().saturating_sub(1);
+                (found.start.0.saturating_add_signed(n).min(last), 0)
+            }
+            Offset::Start(n) => step(buffer, found.start, n),
+            Offset::End(n) => step(buffer, found.last(buffer), n),
+        }
     }
 }
 
+// A match and where the search offset puts the cursor for it
+#[derive(Debug, Clone, Copy)]
+pub struct Found {
+    pub range: Match,
+    pub cursor: (usize, usize),
+}
+
 impl Default for SearchState {
     fn default() -> Self {
         Self::new()
     }
 }
 
+// A pattern up to an unescaped `delimiter` or the end of the text, with
+// `\delimiter` taken as the delimiter itself
+pub fn split_pattern(text: &str, delimiter: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == delimiter {
+            return (pattern, &text[i + c.len_utf8()..]);
+        }
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => pattern.push(next),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            }
+        } else {
+            pattern.push(c);
+        }
+    }
+    (pattern, "")
+}
+
+// Whether a pattern has an upper case letter that isn't part of an escape like \S
+fn has_uppercase(pattern: &str) -> bool {
+    let mut escaped = false;
+    for c in pattern.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// `n` characters right (left) of `position` on its line, stopping at the ends
+fn step(buffer: &Buffer, (line, col): (usize, usize), n: isize) -> (usize, usize) {
+    let text = buffer.get_line(line).map_or("", String::as_str);
+    let mut col = col.min(text.len());
+    if n >= 0 {
+        for _ in 0..n {
+            match text[col..].chars().next() {
+                Some(c) if col + c.len_utf8() < text.len() => col += c.len_utf8(),
+                _ => break,
+            }
+        }
+    } else {
+        for _ in 0..n.unsigned_abs() {
+            match text[..col].chars().next_back() {
+                Some(c) => col -= c.len_utf8(),
+                None => break,
+            }
+        }
+    }
+    (line, col)
+}
+
+// The first match from `first_line` on (the last up to it when not
+// `forward`) that `wanted` accepts, or else the first (last) in the buffer
+fn find_match<F>(buffer: &Buffer, regex: &Regex, first_line: usize, forward: bool, wanted: F) -> Option<Match>
+where
+    F: Fn(&Match) -> bool,
+{
+    if regex.multiline() {
+        let matches = find_matches(buffer, regex);
+        return match forward {
+            true => matches.iter().find(|m| wanted(m)).or(matches.first()).copied(),
+            false => matches.iter().rev().find(|m| wanted(m)).or(matches.last()).copied(),
+        };
+    }
+    // Other patterns only need the lines up to the match
+    let line_matches = |line: usize| {
+        let text = buffer.get_line(line).map_or("", String::as_str);
+        let mut matches: Vec<Match> = regex.find_iter(text).map(|(start, end)| Match { start: (line, start), end: (line, end) }).collect();
+        if !forward {
+            matches.reverse();
+        }
+        matches
+    };
+    let count = buffer.line_count();
+    let first_line = first_line.min(count.saturating_sub(1));
+    match forward {
+        true => (first_line..count).flat_map(line_matches).find(|m| wanted(m)).or_else(|| (0..count).flat_map(line_matches).next()),
+        false => (0..=first_line).rev().flat_map(line_matches).find(|m| wanted(m)).or_else(|| (0..count).rev().flat_map(line_matches).next()),
+    }
+}
+
+// Every match in the buffer, in order, none overlapping the one before it
+pub fn find_matches(buffer: &Buffer, regex: &Regex) -> Vec<Match> {
+    let mut matches = Vec::new();
+    if regex.multiline() {
+        // Search the whole text at once, each line ending in a line break
+        let mut text = String::new();
+        let mut starts = Vec::new();
+        for line in buffer.lines() {
+            starts.push(text.len());
+            text.push_str(line);
+            text.push('\n');
+        }
+        let position = |offset: usize| match starts.partition_point(|&start| start <= offset) {
+            _ if offset == text.len() => (starts.len(), 0),
+            line => (line - 1, offset - starts[line - 1]),
+        };
+        for (start, end) in regex.find_iter(&text) {
+            if start < text.len() {
+                matches.push(Match { start: position(start), end: position(end) });
+            }
+        }
+    } else {
+        for (line, text) in buffer.lines().iter().enumerate() {
+            for (start, end) in regex.find_iter(text) {
+                matches.push(Match { start: (line, start), end: (line, end) });
+            }
+        }
+    }
+    matches
+}
+
+// The lines a match starts on, in order
+pub fn matching_lines(buffer: &Buffer, regex: &Regex) -> Vec<usize> {
+    let mut lines: Vec<usize> = match regex.multiline() {
+        true => find_matches(buffer, regex).iter().map(|m| m.start.0).collect(),
+        false => (0..buffer.line_count()).filter(|&line| buffer.get_line(line).is_some_and(|text| regex.is_match(text))).collect(),
+    };
+    lines.dedup();
+    lines
+}
+
+// The first line after `from` (before it when not `forward`) that has a match
+// for `regex`, wrapping around the end of the buffer
+pub fn find_line(buffer: &Buffer, regex: &Regex, from: usize, forward: bool) -> Option<usize> {
+    let count = buffer.line_count();
+    let lines = if regex.multiline() { matching_lines(buffer, regex) } else { Vec::new() };
+    (1..=count)
+        .map(|step| if forward { (from + step) % count } else { (from + count * 2 - step) % count })
+        .find(|&line| match regex.multiline() {
+            true => lines.binary_search(&line).is_ok(),
+            false => buffer.get_line(line).is_some_and(|text| regex.is_match(text)),
+        })
+}
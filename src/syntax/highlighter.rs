@@ -2,20 +2,48 @@ use syntect::parsing::SyntaxSet;
 use syntect::highlighting::{ThemeSet, Style, Theme};
 use syntect::easy::HighlightLines;
 use std::path::Path;
+use std::sync::OnceLock;
+
+const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+// syntect's bundled themes, parsed once for every buffer to pick from
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
 
 #[derive(Clone)]
 pub struct Highlighter {
     syntax_set: SyntaxSet,
     theme: Theme,
+    theme_name: String,
 }
 
 impl Highlighter {
     pub fn new() -> Self {
         let syntax_set = SyntaxSet::load_defaults_newlines();
-        let theme_set = ThemeSet::load_defaults();
-        let theme = theme_set.themes["base16-ocean.dark"].clone();
+        let theme = themes().themes[DEFAULT_THEME].clone();
         
-        Self { syntax_set, theme }
+        Self { syntax_set, theme, theme_name: DEFAULT_THEME.to_string() }
+    }
+
+    // Switch to one of syntect's bundled themes; "default" is base16-ocean.dark
+    pub fn set_theme(&mut self, name: &str) -> Result<(), String> {
+        let name = if name == "default" { DEFAULT_THEME } else { name };
+        if name == self.theme_name {
+            return Ok(());
+        }
+        match themes().themes.get(name) {
+            Some(theme) => {
+                self.theme = theme.clone();
+                self.theme_name = name.to_string();
+                Ok(())
+            }
+            None => {
+                let names: Vec<_> = themes().themes.keys().map(String::as_str).collect();
+                Err(format!("Unknown color scheme: {} (available: default, {})", name, names.join(", ")))
+            }
+        }
     }
     
     pub fn highlight_line<'a>(&self, line: &'a str, syntax_name: &str) -> Vec<(Style, &'a str)> {
@@ -28,11 +56,16 @@ impl Highlighter {
     }
     
     pub fn detect_syntax(&self, file_path: &Path) -> Option<String> {
-        if let Some(syntax) = self.syntax_set.find_syntax_for_file(file_path).ok().flatten() {
-            Some(syntax.name.clone())
-        } else {
-            None
-        }
+        self.syntax_set
+            .find_syntax_for_file(file_path)
+            .ok()
+            .flatten()
+            .map(|syntax| syntax.name.clone())
+    }
+    
+    // Syntax named by a file extension or, ignoring case, its name: "py", "make", "Rust"
+    pub fn find_syntax(&self, token: &str) -> Option<String> {
+        self.syntax_set.find_syntax_by_token(token).map(|syntax| syntax.name.clone())
     }
     
     pub fn syntax_name(&self) -> Option<&str> {
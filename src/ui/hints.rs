@@ -0,0 +1,41 @@
+// The keys that can follow a pending prefix, drawn in columns above the status line
+pub struct KeyHints {
+    pub prefix: String,
+    pub entries: Vec<(String, String)>, // Key and what it does
+}
+
+impl KeyHints {
+    const MAX_DESC: usize = 40;
+
+    pub fn new(prefix: String, entries: Vec<(String, String)>) -> Self {
+        Self { prefix, entries }
+    }
+
+    // Lines of the popup for a screen `width` wide, at most `max_rows` of them
+    // after the title. Entries that don't fit are left out.
+    pub fn layout(&self, width: usize, max_rows: usize) -> Vec<String> {
+        let key_width = self.entries.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+        let desc_width = self
+            .entries
+            .iter()
+            .map(|(_, desc)| desc.chars().count().min(Self::MAX_DESC))
+            .max()
+            .unwrap_or(0);
+        let column_width = key_width + 3 + desc_width + 2;
+        let columns = (width / column_width.max(1)).max(1);
+        let rows = self.entries.len().div_ceil(columns).min(max_rows);
+
+        let mut lines = vec![format!(" {}", self.prefix)];
+        for row in 0..rows {
+            let mut line = String::new();
+            for column in 0..columns {
+                if let Some((key, desc)) = self.entries.get(column * rows + row) {
+                    let desc: String = desc.chars().take(Self::MAX_DESC).collect();
+                    line.push_str(&format!(" {:>kw$} → {:<dw$} ", key, desc, kw = key_width, dw = desc_width));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
@@ -1,6 +1,10 @@
+mod hints;
+mod popup;
 mod renderer;
 mod statusline;
 
+pub use hints::KeyHints;
+pub use popup::Popup;
 pub use renderer::Renderer;
 pub use statusline::StatusLine;
 
@@ -0,0 +1,25 @@
+// A menu of choices drawn in a box under the cursor
+pub struct Popup {
+    pub items: Vec<String>,
+    pub selected: Option<usize>,
+}
+
+impl Popup {
+    pub const MAX_HEIGHT: usize = 10;
+
+    pub fn new(items: Vec<String>, selected: Option<usize>) -> Self {
+        Self { items, selected }
+    }
+
+    pub fn width(&self) -> usize {
+        self.items.iter().map(|i| i.chars().count()).max().unwrap_or(0) + 2
+    }
+
+    // First item shown so the selection stays on screen
+    pub fn scroll_offset(&self, height: usize) -> usize {
+        match self.selected {
+            Some(selected) if selected >= height => selected + 1 - height,
+            _ => 0,
+        }
+    }
+}
@@ -1,6 +1,7 @@
+use crate::indent;
 use crate::mode::{Mode, VisualMode};
-use crate::command::CommandMode;
-use crate::ui::StatusLine;
+use crate::command::{CommandMode, Wildmenu};
+use crate::ui::{KeyHints, Popup, StatusLine};
 use crossterm::{
     cursor,
     execute,
@@ -10,12 +11,22 @@ use crossterm::{
 use std::io::{self, Write, Stdout};
 use syntect::highlighting::Color as SyntectColor;
 
+const SELECTION_STYLE: &str = "\x1b[48;5;240m\x1b[37m";
+const CURSOR_STYLE: &str = "\x1b[7m";
+const MATCH_STYLE: &str = "\x1b[43m\x1b[30m";
+const PREVIEW_HEIGHT: usize = 7; // Most lines listed in the inccommand split
+
 pub struct Renderer {
     stdout: Stdout,
     width: u16,
     height: u16,
     last_cursor: (usize, usize),
     needs_full_redraw: bool,
+    show_status: bool,
+    key_hints: Option<KeyHints>,
+    matches: Vec<(usize, usize, usize)>,  // Line, start and end of each highlighted match
+    search_count: Option<(usize, usize)>, // Match the cursor is on and how many, for the status line
+    preview_lines: Vec<usize>,            // Lines a command preview changes, listed when off screen
 }
 
 impl Renderer {
@@ -27,6 +38,11 @@ impl Renderer {
             height,
             last_cursor: (0, 0),
             needs_full_redraw: true,
+            show_status: true,
+            key_hints: None,
+            matches: Vec::new(),
+            search_count: None,
+            preview_lines: Vec::new(),
         })
     }
 
@@ -57,6 +73,7 @@ impl Renderer {
         command_mode: &CommandMode,
         visual_mode: Option<&VisualMode>,
         message: Option<&str>,
+        popup: Option<&Popup>,
     ) -> io::Result<()> {
         // Update terminal size
         let (width, height) = terminal::size()?;
@@ -91,12 +108,28 @@ impl Renderer {
             )?;
         }
 
-        let visible_lines = if window_count > 1 {
-            (height as usize).saturating_sub(3) // Account for window indicator
+        let show_status = self.show_status;
+        let status_rows = if show_status { 2 } else { 1 };
+        let text_rows = if window_count > 1 {
+            (height as usize).saturating_sub(status_rows + 1) // Account for window indicator
+        } else {
+            (height as usize).saturating_sub(status_rows) // Normal mode
+        };
+        // Changed lines of a preview that are off screen get a split below the text
+        let is_off_screen = |rows: usize, line: &usize| *line < viewport_offset || *line >= viewport_offset + rows;
+        let split_rows = match self.preview_lines.iter().any(|line| is_off_screen(text_rows, line)) {
+            true => (text_rows / 2).min(PREVIEW_HEIGHT + 1),
+            false => 0,
+        };
+        let visible_lines = text_rows - split_rows;
+        let off_screen: Vec<usize> = self.preview_lines.iter().copied().filter(|line| is_off_screen(visible_lines, line)).collect();
+        let show_numbers = window_manager.get_active_window().options.bool("number");
+        let line_num_width = if show_numbers {
+            (active_buffer.line_count().to_string().len() + 1) as u16
         } else {
-            (height as usize).saturating_sub(2) // Normal mode
+            0
         };
-        let line_num_width = (active_buffer.line_count().to_string().len() + 1) as u16;
+        let tab_stop = active_buffer.indent_settings().tab_stop;
         
         // Build entire screen output in memory first
         let mut screen_buffer = String::with_capacity(visible_lines * 100);
@@ -106,56 +139,65 @@ impl Renderer {
             
             if line_idx < active_buffer.line_count() {
                 // Line number
-                screen_buffer.push_str(&format!("\x1b[33m{:>width$} \x1b[0m", 
-                    line_idx + 1, 
-                    width = line_num_width as usize - 1
-                ));
+                if show_numbers {
+                    screen_buffer.push_str(&format!("\x1b[33m{:>width$} \x1b[0m", 
+                        line_idx + 1, 
+                        width = line_num_width as usize - 1
+                    ));
+                }
                 
                 if let Some(line) = active_buffer.get_line(line_idx) {
                     // Check if this line is in visual selection
                     if let Some(visual) = visual_mode {
                         if let Mode::Visual(_) = mode {
-                            let (start_line, start_col, end_line, end_col) = visual.get_selection(&active_cursor);
+                            let (start_line, _, end_line, _) = visual.get_selection(&active_cursor);
                             
                             if line_idx >= start_line && line_idx <= end_line {
-                                // Highlight selected portion with syntax highlighting
-                                let highlighted = active_buffer.highlight_line(line_idx);
-                                let _chars: Vec<char> = line.chars().collect();
-                                if start_line == end_line {
-                                    let mut char_idx = 0;
-                                    for (style, text) in highlighted {
-                                        for c in text.chars() {
-                                            if char_idx >= start_col && char_idx <= end_col {
-                                                screen_buffer.push_str("\x1b[48;5;240m\x1b[37m");
-                                                screen_buffer.push(c);
-                                                screen_buffer.push_str("\x1b[0m");
-                                            } else {
-                                                screen_buffer.push_str(&Self::rgb_to_ansi(style.foreground));
-                                                screen_buffer.push(c);
-                                                screen_buffer.push_str("\x1b[0m");
-                                            }
-                                            char_idx += 1;
-                                        }
-                                    }
-                                } else {
-                                    // Multi-line selection - just use syntax highlighting
-                                    for (style, text) in highlighted {
-                                        screen_buffer.push_str(&Self::rgb_to_ansi(style.foreground));
-                                        screen_buffer.push_str(&text);
-                                        screen_buffer.push_str("\x1b[0m");
-                                    }
+                                // Highlight selected characters, keeping syntax colors elsewhere
+                                Self::push_marked_line(&mut screen_buffer, active_buffer, line_idx, |col| {
+                                    visual.contains(&active_cursor, active_buffer, line_idx, col).then_some(SELECTION_STYLE)
+                                });
+                                if row < visible_lines - 1 {
+                                    screen_buffer.push_str("\r\n");
                                 }
-                                screen_buffer.push_str("\r\n");
                                 continue;
                             }
                         }
                     }
                     
+                    // Highlight matches, an empty one as the character it is at
+                    let matches = &self.matches;
+                    if matches.iter().any(|&(line, _, _)| line == line_idx) {
+                        Self::push_marked_line(&mut screen_buffer, active_buffer, line_idx, |col| {
+                            matches
+                                .iter()
+                                .any(|&(line, start, end)| line == line_idx && (start..end.max(start + 1)).contains(&col))
+                                .then_some(MATCH_STYLE)
+                        });
+                        if row < visible_lines - 1 {
+                            screen_buffer.push_str("\r\n");
+                        }
+                        continue;
+                    }
+
+                    // Draw extra cursors of a multi-cursor edit
+                    let extra_cursors = window_manager.get_extra_cursors();
+                    if extra_cursors.iter().any(|c| c.line == line_idx) {
+                        Self::push_marked_line(&mut screen_buffer, active_buffer, line_idx, |col| {
+                            extra_cursors.iter().any(|c| c.line == line_idx && c.col == col).then_some(CURSOR_STYLE)
+                        });
+                        if row < visible_lines - 1 {
+                            screen_buffer.push_str("\r\n");
+                        }
+                        continue;
+                    }
+
                     // Apply syntax highlighting to the line
                     let highlighted = active_buffer.highlight_line(line_idx);
+                    let mut screen_col = 0;
                     if highlighted.is_empty() {
                         // Fallback for empty lines or no highlighting
-                        screen_buffer.push_str(line);
+                        Self::push_text(&mut screen_buffer, line, &mut screen_col, tab_stop);
                     } else {
                         // Ensure we're using the current line content
                         let current_line = active_buffer.get_line(line_idx).map_or("", |v| v);
@@ -180,21 +222,21 @@ impl Renderer {
                                 };
                                 
                                 screen_buffer.push_str(&Self::rgb_to_ansi(normalized_style.foreground));
-                                screen_buffer.push_str(actual_text);
+                                Self::push_text(&mut screen_buffer, actual_text, &mut screen_col, tab_stop);
                                 screen_buffer.push_str("\x1b[0m");
                                 char_pos = end_pos;
                             }
                         }
                         // Add any remaining characters that weren't highlighted
                         if char_pos < current_line.len() {
-                            screen_buffer.push_str(&current_line[char_pos..]);
+                            Self::push_text(&mut screen_buffer, &current_line[char_pos..], &mut screen_col, tab_stop);
                         }
                     }
                 }
             } else {
-                screen_buffer.push_str(&format!("\x1b[34m{:>width$} ~\x1b[0m", 
+                screen_buffer.push_str(&format!("\x1b[34m{:>width$}~\x1b[0m", 
                     "", 
-                    width = line_num_width as usize - 1
+                    width = line_num_width as usize
                 ));
             }
             
@@ -203,6 +245,10 @@ impl Renderer {
             }
         }
         
+        if split_rows > 0 {
+            self.push_preview_split(&mut screen_buffer, active_buffer, &off_screen, split_rows, width as usize);
+        }
+
         // Write entire screen in one go
         execute!(
             self.stdout,
@@ -211,20 +257,19 @@ impl Renderer {
         )?;
 
         // Render status line
-        let status_line = StatusLine::new(mode, active_buffer, &active_cursor);
-        let status_row = if window_count > 1 {
-            visible_lines + 1 // Account for window indicator
-        } else {
-            visible_lines
-        };
-        self.render_status_line(&status_line, status_row as u16)?;
+        let text_top = if window_count > 1 { 1 } else { 0 }; // Account for window indicator
+        if show_status {
+            let status_line = StatusLine::new(mode, active_buffer, &active_cursor, self.search_count);
+            self.render_status_line(&status_line, (text_top + text_rows) as u16)?;
+        }
+
+        if let Some(hints) = self.key_hints.take() {
+            self.render_key_hints(&hints, text_top, text_top + visible_lines)?;
+            self.key_hints = Some(hints);
+        }
 
         // Render command line or message
-        let cmd_line_row = if window_count > 1 {
-            visible_lines + 2 // Account for window indicator
-        } else {
-            visible_lines + 1
-        };
+        let cmd_line_row = text_top + text_rows + status_rows - 1;
         execute!(self.stdout, cursor::MoveTo(0, cmd_line_row as u16))?;
         
         // Clear the command line area
@@ -241,56 +286,138 @@ impl Renderer {
                 Print(command_mode.input()),
                 ResetColor
             )?;
+            if let Some(menu) = command_mode.wildmenu() {
+                self.render_wildmenu(menu, cmd_line_row.saturating_sub(1) as u16)?;
+            }
         } else if let Some(msg) = message {
             execute!(self.stdout, Print(msg))?;
         }
 
         // Position cursor
         if let Mode::Command = mode {
-            // In command mode, position cursor at end of command input
-            let cmd_col = 1 + command_mode.input().len(); // 1 for the ':'
-            let cmd_row = if window_count > 1 {
-                visible_lines + 2 // Account for window indicator
-            } else {
-                visible_lines + 1
-            };
+            // In command mode, position cursor where the command line is edited
+            let cmd_col = 1 + command_mode.cursor_col(); // 1 for the ':'
             execute!(
                 self.stdout,
-                cursor::MoveTo(cmd_col as u16, cmd_row as u16),
+                cursor::MoveTo(cmd_col as u16, cmd_line_row as u16),
                 cursor::Show
             )?;
-                  } else {
-                      // Normal cursor positioning in text area
-                      let screen_row = active_cursor.line.saturating_sub(viewport_offset);
-                      let line_num_width = (active_buffer.line_count().to_string().len() + 1) as u16;
-                      let screen_col = (active_cursor.col + line_num_width as usize).min((width as usize).saturating_sub(1));
-                      
-                      // Ensure cursor is visible on screen
-                      if screen_row >= visible_lines {
-                          return Ok(());
-                      }
-                      
-                      // Adjust for window indicator if multiple windows
-                      let adjusted_row = if window_count > 1 {
-                          screen_row + 1
-                      } else {
-                          screen_row
-                      };
-                      
-                      // Update cursor position
-                      self.last_cursor = (active_cursor.line, active_cursor.col);
-        
-        execute!(
-            self.stdout,
-            cursor::MoveTo(screen_col as u16, adjusted_row as u16),
-            cursor::Show
-        )?;
-                  }
+        } else {
+            // Normal cursor positioning in text area
+            let screen_row = active_cursor.line.saturating_sub(viewport_offset);
+            let line = active_buffer.get_line(active_cursor.line).map_or("", |v| v);
+            let text_col = indent::display_col(line, active_cursor.col, tab_stop);
+            let screen_col = (text_col + line_num_width as usize).min((width as usize).saturating_sub(1));
+
+            // Ensure cursor is visible on screen
+            if screen_row >= visible_lines {
+                return Ok(());
+            }
+
+            // Adjust for window indicator if multiple windows
+            let adjusted_row = screen_row + text_top;
+
+            if let Some(popup) = popup {
+                self.render_popup(popup, screen_col, adjusted_row, text_top, text_top + visible_lines)?;
+            }
+
+            // Update cursor position
+            self.last_cursor = (active_cursor.line, active_cursor.col);
+
+            execute!(
+                self.stdout,
+                cursor::MoveTo(screen_col as u16, adjusted_row as u16),
+                cursor::Show
+            )?;
+        }
 
         self.stdout.flush()?;
         Ok(())
     }
 
+    // Syntax highlighted line where `mark` can override the style of any byte column.
+    // A mark past the last character is drawn as a trailing space.
+    fn push_marked_line<F>(screen_buffer: &mut String, buffer: &crate::buffer::Buffer, line_idx: usize, mark: F)
+    where
+        F: Fn(usize) -> Option<&'static str>,
+    {
+        let tab_stop = buffer.indent_settings().tab_stop;
+        let mut byte_idx = 0;
+        let mut screen_col = 0;
+        for (style, text) in buffer.highlight_line(line_idx) {
+            for c in text.chars() {
+                match mark(byte_idx) {
+                    Some(marked) => screen_buffer.push_str(marked),
+                    None => screen_buffer.push_str(&Self::rgb_to_ansi(style.foreground)),
+                }
+                Self::push_text(screen_buffer, c.encode_utf8(&mut [0; 4]), &mut screen_col, tab_stop);
+                screen_buffer.push_str("\x1b[0m");
+                byte_idx += c.len_utf8();
+            }
+        }
+        if let Some(marked) = mark(byte_idx) {
+            screen_buffer.push_str(marked);
+            screen_buffer.push(' ');
+            screen_buffer.push_str("\x1b[0m");
+        }
+    }
+
+    // The inccommand split: a bar, then the changed lines that are off screen
+    // with their line numbers
+    fn push_preview_split(&self, screen_buffer: &mut String, buffer: &crate::buffer::Buffer, lines: &[usize], rows: usize, width: usize) {
+        let title = format!(" {} changed lines off screen", lines.len());
+        screen_buffer.push_str(&format!("\r\n\x1b[7m{:<width$}\x1b[0m", title, width = width));
+        let number_width = lines.last().map_or(1, |line| (line + 1).to_string().len());
+        for &line in lines.iter().take(rows - 1) {
+            screen_buffer.push_str(&format!("\r\n\x1b[33m|{:>width$}|\x1b[0m ", line + 1, width = number_width));
+            Self::push_marked_line(screen_buffer, buffer, line, |col| {
+                self.matches
+                    .iter()
+                    .any(|&(match_line, start, end)| match_line == line && (start..end.max(start + 1)).contains(&col))
+                    .then_some(MATCH_STYLE)
+            });
+        }
+    }
+
+    // Text with tabs expanded to spaces up to the next tabstop. `col` is the
+    // screen column the text starts at and is advanced past it.
+    fn push_text(screen_buffer: &mut String, text: &str, col: &mut usize, tab_stop: usize) {
+        for c in text.chars() {
+            if c == '\t' {
+                let next = (*col / tab_stop + 1) * tab_stop;
+                screen_buffer.extend(std::iter::repeat_n(' ', next - *col));
+                *col = next;
+            } else {
+                screen_buffer.push(c);
+                *col += 1;
+            }
+        }
+    }
+
+    // The showstatus option
+    pub fn set_show_status(&mut self, show: bool) {
+        self.show_status = show;
+    }
+
+    // The key hint popup to draw, if a prefix has been pending for a while
+    pub fn set_key_hints(&mut self, hints: Option<KeyHints>) {
+        self.key_hints = hints;
+    }
+
+    // Matches to highlight in the active window
+    pub fn set_matches(&mut self, matches: Vec<(usize, usize, usize)>) {
+        self.matches = matches;
+    }
+
+    pub fn set_search_count(&mut self, count: Option<(usize, usize)>) {
+        self.search_count = count;
+    }
+
+    // Lines changed by a command being typed, for the split inccommand shows
+    pub fn set_preview_lines(&mut self, lines: Vec<usize>) {
+        self.preview_lines = lines;
+    }
+
     pub fn force_redraw(&mut self) {
         self.needs_full_redraw = true;
     }
@@ -309,8 +436,8 @@ impl Renderer {
         let chars: Vec<char> = line.chars().collect();
         
         // Before highlight
-        for i in 0..start.min(chars.len()) {
-            execute!(self.stdout, Print(chars[i]))?;
+        for &c in chars.iter().take(start) {
+            execute!(self.stdout, Print(c))?;
         }
         
         // Highlighted portion
@@ -320,20 +447,113 @@ impl Renderer {
             SetForegroundColor(Color::White)
         )?;
         
-        for i in start..end.min(chars.len()) {
-            execute!(self.stdout, Print(chars[i]))?;
+        for &c in chars.iter().take(end).skip(start) {
+            execute!(self.stdout, Print(c))?;
         }
         
         execute!(self.stdout, ResetColor)?;
         
         // After highlight
-        for i in end..chars.len() {
-            execute!(self.stdout, Print(chars[i]))?;
+        for &c in chars.iter().skip(end) {
+            execute!(self.stdout, Print(c))?;
         }
         
         Ok(())
     }
 
+    // Draw a popup menu below the cursor row, or above it when there is more room there
+    fn render_popup(&mut self, popup: &Popup, col: usize, row: usize, top: usize, bottom: usize) -> io::Result<()> {
+        let below = bottom.saturating_sub(row + 1);
+        let above = row.saturating_sub(top);
+        let wanted = popup.items.len().min(Popup::MAX_HEIGHT);
+        let (first_row, height) = if below >= wanted || below >= above {
+            (row + 1, wanted.min(below))
+        } else {
+            (row - wanted.min(above), wanted.min(above))
+        };
+
+        let width = popup.width().min(self.width as usize);
+        let col = col.min((self.width as usize).saturating_sub(width));
+        let offset = popup.scroll_offset(height);
+
+        for (i, item) in popup.items.iter().enumerate().skip(offset).take(height) {
+            let background = if popup.selected == Some(i) { Color::DarkBlue } else { Color::DarkGrey };
+            let text: String = item.chars().take(width.saturating_sub(2)).collect();
+            execute!(
+                self.stdout,
+                cursor::MoveTo(col as u16, (first_row + i - offset) as u16),
+                SetBackgroundColor(background),
+                SetForegroundColor(Color::White),
+                Print(format!(" {:<w$} ", text, w = width.saturating_sub(2))),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    // Draw the key hints over the bottom of the text area, ending at `bottom`
+    fn render_key_hints(&mut self, hints: &KeyHints, top: usize, bottom: usize) -> io::Result<()> {
+        let width = self.width as usize;
+        let lines = hints.layout(width, bottom.saturating_sub(top + 1));
+        let first_row = bottom.saturating_sub(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            let text: String = line.chars().take(width).collect();
+            execute!(
+                self.stdout,
+                cursor::MoveTo(0, (first_row + i) as u16),
+                SetBackgroundColor(Color::DarkGrey),
+                SetForegroundColor(if i == 0 { Color::Yellow } else { Color::White }),
+                Print(format!("{:<w$}", text, w = width)),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    // The matches of a Tab completion across one row, scrolled so the
+    // selected one shows, with < and > where more are off the row
+    fn render_wildmenu(&mut self, menu: &Wildmenu, row: u16) -> io::Result<()> {
+        let width = self.width as usize;
+        let items = menu.items();
+        let item_width = |item: &String| item.chars().count() + 2;
+        let mut first = 0;
+        if let Some(selected) = menu.selected() {
+            // The widest run of items ending at the selected one that fits
+            let mut used = item_width(&items[selected]);
+            first = selected;
+            while first > 0 && used + item_width(&items[first - 1]) + 4 <= width {
+                first -= 1;
+                used += item_width(&items[first]);
+            }
+        }
+
+        execute!(self.stdout, cursor::MoveTo(0, row), SetBackgroundColor(Color::DarkGrey), SetForegroundColor(Color::White))?;
+        let mut used = 0;
+        if first > 0 {
+            execute!(self.stdout, Print("< "))?;
+            used += 2;
+        }
+        let mut last = first;
+        while last < items.len() && used + item_width(&items[last]) + 2 <= width {
+            let (background, foreground) = match menu.selected() == Some(last) {
+                true => (Color::Yellow, Color::Black),
+                false => (Color::DarkGrey, Color::White),
+            };
+            execute!(self.stdout, SetBackgroundColor(background), SetForegroundColor(foreground), Print(format!(" {} ", items[last])))?;
+            used += item_width(&items[last]);
+            last += 1;
+        }
+        let more = if last < items.len() { ">" } else { "" };
+        execute!(
+            self.stdout,
+            SetBackgroundColor(Color::DarkGrey),
+            SetForegroundColor(Color::White),
+            Print(format!("{:>w$}", more, w = width.saturating_sub(used))),
+            ResetColor
+        )?;
+        Ok(())
+    }
+
     fn render_status_line(&mut self, status_line: &StatusLine, row: u16) -> io::Result<()> {
         execute!(
             self.stdout,
@@ -4,22 +4,26 @@ use crate::mode::Mode;
 
 pub struct StatusLine {
     mode: Mode,
-    file_path: Option<String>,
+    name: String,
     modified: bool,
     line: usize,
     col: usize,
     total_lines: usize,
+    indent: String,
+    search_count: Option<(usize, usize)>, // [3/17]: the match at or before the cursor, of all of them
 }
 
 impl StatusLine {
-    pub fn new(mode: &Mode, buffer: &Buffer, cursor: &Cursor) -> Self {
+    pub fn new(mode: &Mode, buffer: &Buffer, cursor: &Cursor, search_count: Option<(usize, usize)>) -> Self {
         Self {
             mode: *mode,
-            file_path: buffer.file_path().map(|p| p.display().to_string()),
+            name: buffer.name(),
             modified: buffer.is_modified(),
             line: cursor.line + 1,
             col: cursor.col + 1,
             total_lines: buffer.line_count(),
+            indent: buffer.indent_settings().describe(),
+            search_count,
         }
     }
 
@@ -27,11 +31,18 @@ impl StatusLine {
         let left = format!(
             " {} {}{}",
             self.mode.name(),
-            self.file_path.as_deref().unwrap_or("[No Name]"),
+            self.name,
             if self.modified { " [+]" } else { "" }
         );
 
-        let right = format!(" {}:{} {}/{} ", self.line, self.col, self.line, self.total_lines);
+        let search_count = match self.search_count {
+            Some((current, total)) => format!("[{}/{}]  ", current, total),
+            None => String::new(),
+        };
+        let right = format!(
+            " {}{}  {}:{} {}/{} ",
+            search_count, self.indent, self.line, self.col, self.line, self.total_lines
+        );
 
         let padding = width.saturating_sub(left.len() + right.len());
         format!("{}{}{}", left, " ".repeat(padding), right)
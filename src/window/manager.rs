@@ -65,11 +65,7 @@ impl WindowManager {
 
     pub fn get_active_cursor(&self) -> Cursor {
         let window = &self.windows[self.active_window];
-        Cursor {
-            line: window.cursor_line,
-            col: window.cursor_col,
-            desired_col: window.cursor_col,
-        }
+        Cursor::at(window.cursor_line, window.cursor_col, &self.buffers[window.buffer_id])
     }
 
     pub fn set_active_cursor(&mut self, cursor: Cursor) {
@@ -78,6 +74,14 @@ impl WindowManager {
         window.cursor_col = cursor.col;
     }
 
+    pub fn get_extra_cursors(&self) -> &[Cursor] {
+        &self.windows[self.active_window].extra_cursors
+    }
+
+    pub fn set_extra_cursors(&mut self, cursors: Vec<Cursor>) {
+        self.windows[self.active_window].extra_cursors = cursors;
+    }
+
     pub fn get_viewport_offset(&self) -> usize {
         self.windows[self.active_window].viewport_offset
     }
@@ -112,7 +116,9 @@ impl WindowManager {
         };
 
         // Create new window
-        let new_window = Window::new(new_buffer_id);
+        // The new window starts with the current one's options, like vim
+        let mut new_window = Window::new(new_buffer_id);
+        new_window.options = self.windows[current_window_id].options.clone();
         let new_window_id = self.windows.len();
         self.windows.push(new_window);
         
@@ -266,6 +272,16 @@ impl WindowManager {
         }
     }
 
+    pub fn get_active_window_id(&self) -> usize {
+        self.active_window
+    }
+
+    pub fn set_active_window(&mut self, window_id: usize) {
+        if window_id < self.windows.len() {
+            self.active_window = window_id;
+        }
+    }
+
     pub fn get_window_count(&self) -> usize {
         self.windows.len()
     }
@@ -4,11 +4,16 @@ mod manager;
 pub use split::{Split, SplitType, WindowLayout, LayoutNode};
 pub use manager::{WindowManager, WindowBounds};
 
+use crate::cursor::Cursor;
+use crate::options::Options;
+
 pub struct Window {
     pub buffer_id: usize,
     pub cursor_line: usize,
     pub cursor_col: usize,
+    pub extra_cursors: Vec<Cursor>, // Multi-cursor editing, the primary cursor is cursor_line/cursor_col
     pub viewport_offset: usize,
+    pub options: Options, // Window-local options
 }
 
 impl Window {
@@ -17,7 +22,9 @@ impl Window {
             buffer_id,
             cursor_line: 0,
             cursor_col: 0,
+            extra_cursors: Vec::new(),
             viewport_offset: 0,
+            options: Options::new(),
         }
     }
 }